@@ -15,10 +15,12 @@
 //! The definitions of the ucg AST and Tokens.
 use std;
 use std::borrow::Borrow;
+use std::cell::RefCell;
 use std::cmp::Eq;
 use std::cmp::Ordering;
 use std::cmp::PartialEq;
 use std::cmp::PartialOrd;
+use std::collections::BTreeMap;
 use std::convert::Into;
 use std::fmt;
 use std::hash::Hash;
@@ -31,6 +33,7 @@ use abortable_parser;
 use crate::build::scope::Scope;
 use crate::build::Val;
 
+pub mod printer;
 pub mod walk;
 
 macro_rules! enum_type_equality {
@@ -59,6 +62,18 @@ pub struct Position {
     pub line: usize,
     pub column: usize,
     pub offset: usize,
+    /// Where the item at this position ends if it is known.
+    pub end: Option<SpanEnd>,
+}
+
+/// The end of the span of source covered by a token or AST node.
+///
+/// It points just past the last character of the span.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
+pub struct SpanEnd {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
 }
 
 impl Position {
@@ -69,6 +84,7 @@ impl Position {
             line: line,
             column: column,
             offset: offset,
+            end: None,
         }
     }
 
@@ -76,6 +92,30 @@ impl Position {
         self.file = Some(file.into());
         self
     }
+
+    pub fn with_end(mut self, end: SpanEnd) -> Self {
+        self.end = Some(end);
+        self
+    }
+}
+
+impl<'a> From<&'a Position> for SpanEnd {
+    fn from(pos: &'a Position) -> Self {
+        SpanEnd {
+            line: pos.line,
+            column: pos.column,
+            offset: pos.offset,
+        }
+    }
+}
+
+impl abortable_parser::Positioned for Position {
+    fn line(&self) -> usize {
+        self.line
+    }
+    fn column(&self) -> usize {
+        self.column
+    }
 }
 
 impl<'a> From<&'a Position> for Position {
@@ -282,6 +322,20 @@ impl Value {
         }
     }
 
+    /// Returns a mutable reference to the position for a Value.
+    pub fn pos_mut(&mut self) -> &mut Position {
+        match self {
+            Value::Empty(ref mut pos) => pos,
+            Value::Boolean(ref mut b) => &mut b.pos,
+            Value::Int(ref mut i) => &mut i.pos,
+            Value::Float(ref mut f) => &mut f.pos,
+            Value::Str(ref mut s) => &mut s.pos,
+            Value::Symbol(ref mut s) => &mut s.pos,
+            Value::Tuple(ref mut fs) => &mut fs.pos,
+            Value::List(ref mut def) => &mut def.pos,
+        }
+    }
+
     /// Returns true if called on a Value that is the same type as itself.
     pub fn type_equal(&self, target: &Self) -> bool {
         enum_type_equality!(
@@ -386,6 +440,16 @@ impl<'a> From<&'a PositionedItem<String>> for PositionedItem<String> {
     }
 }
 
+/// The type names that can be used in a type annotation or with the is
+/// operator.
+pub const TYPE_NAMES: &[&str] = &[
+    "str", "bool", "null", "int", "float", "tuple", "list", "func", "module",
+];
+
+/// The funcs bound by let in one scope keyed by name. Every func bound there
+/// shares the same set so it sees the funcs bound after it as well.
+pub type RecFuncs = Rc<RefCell<BTreeMap<String, FuncDef>>>;
+
 /// Encodes a func expression in the UCG AST..
 ///
 /// A func is a pure function over a tuple.
@@ -393,7 +457,13 @@ impl<'a> From<&'a PositionedItem<String>> for PositionedItem<String> {
 pub struct FuncDef {
     pub scope: Option<Scope>,
     pub argdefs: Vec<PositionedItem<String>>,
+    /// The type annotations of the arguments that have one keyed by argument name.
+    pub arg_types: BTreeMap<String, Token>,
     pub fields: Box<Expression>,
+    /// The funcs bound by let in the same scope as this one including itself.
+    /// Calls look up their names here so funcs can call themselves and each
+    /// other.
+    pub rec_funcs: Option<RecFuncs>,
     pub pos: Position,
 }
 
@@ -536,6 +606,14 @@ impl FuncOpDef {
             FuncOpDef::Reduce(def) => &def.pos,
         }
     }
+
+    pub fn pos_mut(&mut self) -> &mut Position {
+        match self {
+            FuncOpDef::Map(def) => &mut def.pos,
+            FuncOpDef::Filter(def) => &mut def.pos,
+            FuncOpDef::Reduce(def) => &mut def.pos,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -543,6 +621,10 @@ pub struct ModuleDef {
     pub scope: Option<Scope>,
     pub pos: Position,
     pub arg_set: FieldList,
+    /// The type annotations of the parameters that have one keyed by parameter name.
+    pub arg_types: BTreeMap<String, Token>,
+    /// Where the parameter list ends if it is known.
+    pub arg_end: Option<SpanEnd>,
     pub out_expr: Option<Box<Expression>>,
     pub arg_tuple: Option<Rc<Val>>,
     pub statements: Vec<Statement>,
@@ -554,6 +636,8 @@ impl ModuleDef {
             scope: None,
             pos: pos.into(),
             arg_set: arg_set,
+            arg_types: BTreeMap::new(),
+            arg_end: None,
             out_expr: None,
             arg_tuple: None,
             statements: stmts,
@@ -692,6 +776,28 @@ impl Expression {
             &Expression::Debug(ref def) => &def.pos,
         }
     }
+
+    /// Returns a mutable reference to the position of the Expression.
+    pub fn pos_mut(&mut self) -> &mut Position {
+        match self {
+            Expression::Simple(ref mut v) => v.pos_mut(),
+            Expression::Binary(ref mut def) => &mut def.pos,
+            Expression::Copy(ref mut def) => &mut def.pos,
+            Expression::Range(ref mut def) => &mut def.pos,
+            Expression::Grouped(_, ref mut pos) => pos,
+            Expression::Format(ref mut def) => &mut def.pos,
+            Expression::Call(ref mut def) => &mut def.pos,
+            Expression::Func(ref mut def) => &mut def.pos,
+            Expression::Module(ref mut def) => &mut def.pos,
+            Expression::Select(ref mut def) => &mut def.pos,
+            Expression::FuncOp(ref mut def) => def.pos_mut(),
+            Expression::Include(ref mut def) => &mut def.pos,
+            Expression::Import(ref mut def) => &mut def.pos,
+            Expression::Fail(ref mut def) => &mut def.pos,
+            Expression::Not(ref mut def) => &mut def.pos,
+            Expression::Debug(ref mut def) => &mut def.pos,
+        }
+    }
 }
 
 impl fmt::Display for Expression {
@@ -769,6 +875,6 @@ pub enum Statement {
     // Assert statement
     Assert(Expression),
 
-    // Identify an Expression for output.
-    Output(Position, Token, Expression),
+    // Identify an Expression for output with an optional target path.
+    Output(Position, Token, Option<Token>, Expression),
 }
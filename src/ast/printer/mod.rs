@@ -0,0 +1,567 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! A pretty printer for the ucg AST.
+//!
+//! The printer produces the canonical source form of a list of statements.
+//! Comments collected by the tokenizer into a `CommentMap` are written back out
+//! in front of the statement, field, or list element that follows them. Lists
+//! with a comment inside of them are printed one element per line so the
+//! comment stays with its element.
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::ast::*;
+use crate::tokenizer::CommentMap;
+
+pub struct AstPrinter<'a, W>
+where
+    W: Write,
+{
+    indent_size: usize,
+    curr_indent: usize,
+    w: W,
+    comment_map: Option<&'a CommentMap>,
+    source_lines: Option<Vec<&'a str>>,
+    // All comments on lines before this one have already been written.
+    next_comment_line: usize,
+    // True when nothing has been written in the current block yet.
+    block_start: bool,
+}
+
+impl<'a, W> AstPrinter<'a, W>
+where
+    W: Write,
+{
+    pub fn new(indent: usize, w: W) -> Self {
+        AstPrinter {
+            indent_size: indent,
+            curr_indent: 0,
+            w,
+            comment_map: None,
+            source_lines: None,
+            next_comment_line: 0,
+            block_start: true,
+        }
+    }
+
+    /// Write the comments in this CommentMap back out along with the AST.
+    pub fn with_comment_map(mut self, map: &'a CommentMap) -> Self {
+        self.comment_map = Some(map);
+        self
+    }
+
+    /// Use the original source to preserve blank lines between statements.
+    pub fn with_source(mut self, src: &'a str) -> Self {
+        self.source_lines = Some(src.lines().collect());
+        self
+    }
+
+    fn make_indent(&self) -> String {
+        " ".repeat(self.curr_indent)
+    }
+
+    fn is_blank_line_before(&self, line: usize) -> bool {
+        match self.source_lines {
+            Some(ref lines) if line >= 2 && line - 2 < lines.len() => {
+                lines[line - 2].trim().is_empty()
+            }
+            _ => false,
+        }
+    }
+
+    // Writes a blank line if the source had one right before this line.
+    fn render_blank_line(&mut self, line: usize) -> std::io::Result<()> {
+        if !self.block_start && self.is_blank_line_before(line) {
+            writeln!(self.w)?;
+        }
+        Ok(())
+    }
+
+    // Writes out all of the comments that occur before the line provided
+    // each on their own line.
+    fn render_comments_before(&mut self, line: usize) -> std::io::Result<()> {
+        if let Some(map) = self.comment_map {
+            if line > self.next_comment_line {
+                let indent = self.make_indent();
+                for (cmt_line, tok) in map.range(self.next_comment_line..line) {
+                    self.render_blank_line(*cmt_line)?;
+                    writeln!(self.w, "{}//{}", indent, tok.fragment)?;
+                    self.block_start = false;
+                }
+                self.next_comment_line = line;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes a comment found on the line provided as a trailing comment.
+    fn render_trailing_comment(&mut self, line: usize) -> std::io::Result<()> {
+        if let Some(map) = self.comment_map {
+            if line >= self.next_comment_line {
+                if let Some(tok) = map.get(&line) {
+                    write!(self.w, " //{}", tok.fragment)?;
+                    self.next_comment_line = line + 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns true if there is a comment after start and before end. Without
+    // an end anything up to the end of last_line counts.
+    fn has_comment_within(&self, start: &Position, end: Option<SpanEnd>, last_line: usize) -> bool {
+        let map = match self.comment_map {
+            Some(map) => map,
+            None => return false,
+        };
+        let end = end
+            .map(|end| (end.line, end.column))
+            .unwrap_or((last_line + 1, 0));
+        map.range(start.line..=end.0)
+            .map(|(line, tok)| (*line, tok.pos.column))
+            .any(|cmt| cmt > (start.line, start.column) && cmt < end)
+    }
+
+    // Ends a block of items writing out any comments left before its end.
+    fn render_block_end(&mut self, end: Option<SpanEnd>) -> std::io::Result<()> {
+        if let Some(end) = end {
+            self.render_comments_before(end.line)?;
+        }
+        self.curr_indent -= self.indent_size;
+        self.block_start = false;
+        let indent = self.make_indent();
+        write!(self.w, "{}", indent)
+    }
+
+    // Starts an item that lives on its own line in a block.
+    fn render_item_start(&mut self, line: usize) -> std::io::Result<()> {
+        self.render_comments_before(line)?;
+        self.render_blank_line(line)?;
+        self.block_start = false;
+        let indent = self.make_indent();
+        write!(self.w, "{}", indent)
+    }
+
+    fn render_str(&mut self, s: &str) -> std::io::Result<()> {
+        write!(self.w, "\"{}\"", escape_quotes(s))
+    }
+
+    fn render_token(&mut self, t: &Token) -> std::io::Result<()> {
+        if t.typ == TokenType::QUOTED {
+            self.render_str(&t.fragment)
+        } else {
+            write!(self.w, "{}", t.fragment)
+        }
+    }
+
+    fn render_list_def(&mut self, def: &ListDef) -> std::io::Result<()> {
+        write!(self.w, "[")?;
+        self.render_expr_list(&def.elems, &def.pos, def.pos.end, true)?;
+        write!(self.w, "]")
+    }
+
+    // Renders a comma separated list of expressions without the surrounding
+    // delimiters. The list is printed one expression per line if it started
+    // on a new line after start or has a comment inside of it. Every
+    // expression but the last is followed by a comma unless trailing_comma is
+    // set.
+    fn render_expr_list(
+        &mut self,
+        elems: &[Expression],
+        start: &Position,
+        end: Option<SpanEnd>,
+        trailing_comma: bool,
+    ) -> std::io::Result<()> {
+        let multiline = match (elems.first(), elems.last()) {
+            (Some(first), Some(last)) => {
+                let last_line = last.pos().end.map(|e| e.line).unwrap_or(last.pos().line);
+                first.pos().line > start.line || self.has_comment_within(start, end, last_line)
+            }
+            _ => false,
+        };
+        if multiline {
+            writeln!(self.w)?;
+            self.curr_indent += self.indent_size;
+            self.block_start = true;
+            for (i, e) in elems.iter().enumerate() {
+                let line = e.pos().line;
+                self.render_item_start(line)?;
+                self.render_expr(e)?;
+                if trailing_comma || i + 1 < elems.len() {
+                    write!(self.w, ",")?;
+                }
+                self.render_trailing_comment(line)?;
+                writeln!(self.w)?;
+            }
+            self.render_block_end(end)?;
+        } else {
+            let mut first = true;
+            for e in elems.iter() {
+                if !first {
+                    write!(self.w, ", ")?;
+                }
+                first = false;
+                self.render_expr(e)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Renders the fields of a tuple including the surrounding braces.
+    fn render_field_list(
+        &mut self,
+        fields: &FieldList,
+        start: &Position,
+        end: Option<SpanEnd>,
+    ) -> std::io::Result<()> {
+        self.render_typed_field_list(fields, &BTreeMap::new(), start, end)
+    }
+
+    fn render_type_annotation(
+        &mut self,
+        name: &str,
+        types: &BTreeMap<String, Token>,
+    ) -> std::io::Result<()> {
+        if let Some(typ) = types.get(name) {
+            write!(self.w, ": {}", typ.fragment)?;
+        }
+        Ok(())
+    }
+
+    fn render_typed_field_list(
+        &mut self,
+        fields: &FieldList,
+        types: &BTreeMap<String, Token>,
+        start: &Position,
+        end: Option<SpanEnd>,
+    ) -> std::io::Result<()> {
+        write!(self.w, "{{")?;
+        let multiline = match (fields.first(), fields.last()) {
+            (Some(first), Some(last)) => {
+                let last_line = last.1.pos().end.map(|e| e.line).unwrap_or(last.0.pos.line);
+                first.0.pos.line > start.line || self.has_comment_within(start, end, last_line)
+            }
+            _ => false,
+        };
+        if multiline {
+            writeln!(self.w)?;
+            self.curr_indent += self.indent_size;
+            self.block_start = true;
+            for (name, expr) in fields.iter() {
+                let line = name.pos.line;
+                self.render_item_start(line)?;
+                self.render_token(name)?;
+                self.render_type_annotation(&name.fragment, types)?;
+                write!(self.w, " = ")?;
+                self.render_expr(expr)?;
+                write!(self.w, ",")?;
+                self.render_trailing_comment(line)?;
+                writeln!(self.w)?;
+            }
+            self.render_block_end(end)?;
+        } else {
+            let mut first = true;
+            for (name, expr) in fields.iter() {
+                if !first {
+                    write!(self.w, ", ")?;
+                }
+                first = false;
+                self.render_token(name)?;
+                self.render_type_annotation(&name.fragment, types)?;
+                write!(self.w, " = ")?;
+                self.render_expr(expr)?;
+            }
+        }
+        write!(self.w, "}}")
+    }
+
+    fn render_value(&mut self, v: &Value) -> std::io::Result<()> {
+        match v {
+            Value::Boolean(b) => write!(self.w, "{}", if b.val { "true" } else { "false" }),
+            Value::Empty(_) => write!(self.w, "NULL"),
+            Value::Float(f) => {
+                let s = format!("{}", f.val);
+                if s.contains('.') {
+                    write!(self.w, "{}", s)
+                } else {
+                    write!(self.w, "{}.0", s)
+                }
+            }
+            Value::Int(i) => write!(self.w, "{}", i.val),
+            Value::Str(s) => self.render_str(&s.val),
+            Value::Symbol(s) => write!(self.w, "{}", s.val),
+            Value::List(def) => self.render_list_def(def),
+            Value::Tuple(def) => self.render_field_list(&def.val, &def.pos, def.pos.end),
+        }
+    }
+
+    fn render_binary_op(&mut self, def: &BinaryOpDef) -> std::io::Result<()> {
+        self.render_expr(&def.left)?;
+        let op = match def.kind {
+            BinaryExprType::DOT => {
+                write!(self.w, ".")?;
+                return self.render_expr(&def.right);
+            }
+            BinaryExprType::Add => "+",
+            BinaryExprType::Sub => "-",
+            BinaryExprType::Mul => "*",
+            BinaryExprType::Div => "/",
+            BinaryExprType::Mod => "%%",
+            BinaryExprType::AND => "&&",
+            BinaryExprType::OR => "||",
+            BinaryExprType::Equal => "==",
+            BinaryExprType::NotEqual => "!=",
+            BinaryExprType::GT => ">",
+            BinaryExprType::LT => "<",
+            BinaryExprType::GTEqual => ">=",
+            BinaryExprType::LTEqual => "<=",
+            BinaryExprType::REMatch => "~",
+            BinaryExprType::NotREMatch => "!~",
+            BinaryExprType::IN => "in",
+            BinaryExprType::IS => "is",
+        };
+        write!(self.w, " {} ", op)?;
+        self.render_expr(&def.right)
+    }
+
+    fn render_func_op(&mut self, def: &FuncOpDef) -> std::io::Result<()> {
+        let (name, args, pos) = match def {
+            FuncOpDef::Map(ref def) => (
+                "map",
+                vec![def.func.as_ref().clone(), def.target.as_ref().clone()],
+                &def.pos,
+            ),
+            FuncOpDef::Filter(ref def) => (
+                "filter",
+                vec![def.func.as_ref().clone(), def.target.as_ref().clone()],
+                &def.pos,
+            ),
+            FuncOpDef::Reduce(ref def) => (
+                "reduce",
+                vec![
+                    def.func.as_ref().clone(),
+                    def.acc.as_ref().clone(),
+                    def.target.as_ref().clone(),
+                ],
+                &def.pos,
+            ),
+        };
+        write!(self.w, "{}(", name)?;
+        self.render_expr_list(&args, pos, pos.end, true)?;
+        write!(self.w, ")")
+    }
+
+    fn render_module_def(&mut self, def: &ModuleDef) -> std::io::Result<()> {
+        write!(self.w, "module ")?;
+        self.render_typed_field_list(&def.arg_set, &def.arg_types, &def.pos, def.arg_end)?;
+        write!(self.w, " => ")?;
+        if let Some(ref expr) = def.out_expr {
+            write!(self.w, "(")?;
+            self.render_expr(expr)?;
+            write!(self.w, ") ")?;
+        }
+        writeln!(self.w, "{{")?;
+        self.curr_indent += self.indent_size;
+        self.block_start = true;
+        for stmt in def.statements.iter() {
+            self.render_stmt(stmt)?;
+        }
+        self.curr_indent -= self.indent_size;
+        self.block_start = false;
+        let indent = self.make_indent();
+        write!(self.w, "{}}}", indent)
+    }
+
+    /// Renders an expression.
+    pub fn render_expr(&mut self, expr: &Expression) -> std::io::Result<()> {
+        match expr {
+            Expression::Binary(ref def) => self.render_binary_op(def),
+            Expression::Call(ref def) => {
+                self.render_value(&def.funcref)?;
+                write!(self.w, "(")?;
+                self.render_expr_list(&def.arglist, def.funcref.pos(), def.pos.end, true)?;
+                write!(self.w, ")")
+            }
+            Expression::Copy(ref def) => {
+                self.render_value(&def.selector)?;
+                self.render_field_list(&def.fields, &def.pos, def.pos.end)
+            }
+            Expression::Debug(ref def) => {
+                write!(self.w, "TRACE ")?;
+                self.render_expr(&def.expr)
+            }
+            Expression::Fail(ref def) => {
+                write!(self.w, "fail ")?;
+                self.render_expr(&def.message)
+            }
+            Expression::Format(ref def) => {
+                self.render_str(&def.template)?;
+                write!(self.w, " % ")?;
+                match def.args {
+                    FormatArgs::List(ref args) => {
+                        write!(self.w, "(")?;
+                        self.render_expr_list(args, &def.pos, def.pos.end, false)?;
+                        write!(self.w, ")")
+                    }
+                    FormatArgs::Single(ref expr) => self.render_expr(expr),
+                }
+            }
+            Expression::Func(ref def) => {
+                write!(self.w, "func (")?;
+                // The args end somewhere before the body starts.
+                let end = SpanEnd::from(def.fields.pos());
+                let last_line = def.argdefs.last().map(|a| a.pos.line).unwrap_or(0);
+                if !def.argdefs.is_empty()
+                    && self.has_comment_within(&def.pos, Some(end), last_line)
+                {
+                    writeln!(self.w)?;
+                    self.curr_indent += self.indent_size;
+                    self.block_start = true;
+                    // Func args don't allow a trailing comma.
+                    let last = def.argdefs.len() - 1;
+                    for (i, arg) in def.argdefs.iter().enumerate() {
+                        self.render_item_start(arg.pos.line)?;
+                        write!(self.w, "{}", arg.val)?;
+                        self.render_type_annotation(&arg.val, &def.arg_types)?;
+                        if i < last {
+                            write!(self.w, ",")?;
+                        }
+                        self.render_trailing_comment(arg.pos.line)?;
+                        writeln!(self.w)?;
+                    }
+                    self.render_block_end(None)?;
+                } else {
+                    let mut first = true;
+                    for arg in def.argdefs.iter() {
+                        if !first {
+                            write!(self.w, ", ")?;
+                        }
+                        first = false;
+                        write!(self.w, "{}", arg.val)?;
+                        self.render_type_annotation(&arg.val, &def.arg_types)?;
+                    }
+                }
+                write!(self.w, ") => ")?;
+                self.render_expr(&def.fields)
+            }
+            Expression::FuncOp(ref def) => self.render_func_op(def),
+            Expression::Grouped(ref expr, _) => {
+                write!(self.w, "(")?;
+                self.render_expr(expr)?;
+                write!(self.w, ")")
+            }
+            Expression::Import(ref def) => {
+                write!(self.w, "import ")?;
+                self.render_str(&def.path.fragment)
+            }
+            Expression::Include(ref def) => {
+                write!(self.w, "include {} ", def.typ.fragment)?;
+                self.render_str(&def.path.fragment)
+            }
+            Expression::Module(ref def) => self.render_module_def(def),
+            Expression::Not(ref def) => {
+                write!(self.w, "not ")?;
+                self.render_expr(&def.expr)
+            }
+            Expression::Range(ref def) => {
+                self.render_expr(&def.start)?;
+                write!(self.w, ":")?;
+                if let Some(ref step) = def.step {
+                    self.render_expr(step)?;
+                    write!(self.w, ":")?;
+                }
+                self.render_expr(&def.end)
+            }
+            Expression::Select(ref def) => {
+                write!(self.w, "select ")?;
+                self.render_expr(&def.val)?;
+                write!(self.w, ", ")?;
+                if let Some(ref e) = def.default {
+                    self.render_expr(e)?;
+                    write!(self.w, ", ")?;
+                }
+                self.render_field_list(&def.tuple, def.val.pos(), def.pos.end)
+            }
+            Expression::Simple(ref v) => self.render_value(v),
+        }
+    }
+
+    /// Renders a statement on its own line along with any preceding comments.
+    pub fn render_stmt(&mut self, stmt: &Statement) -> std::io::Result<()> {
+        let line = stmt_line(stmt);
+        self.render_item_start(line)?;
+        match stmt {
+            Statement::Let(ref def) => {
+                write!(self.w, "let {} = ", def.name.fragment)?;
+                self.render_expr(&def.value)?;
+            }
+            Statement::Expression(ref expr) => {
+                self.render_expr(expr)?;
+            }
+            Statement::Assert(ref expr) => {
+                write!(self.w, "assert ")?;
+                self.render_expr(expr)?;
+            }
+            Statement::Output(_, ref typ, ref path, ref expr) => {
+                write!(self.w, "out {} ", typ.fragment)?;
+                if let Some(ref path) = path {
+                    self.render_str(&path.fragment)?;
+                    write!(self.w, " ")?;
+                }
+                self.render_expr(expr)?;
+            }
+        };
+        write!(self.w, ";")?;
+        self.render_trailing_comment(line)?;
+        writeln!(self.w)
+    }
+
+    /// Renders a list of statements followed by any remaining comments.
+    pub fn render(&mut self, stmts: &[Statement]) -> std::io::Result<()> {
+        for stmt in stmts.iter() {
+            self.render_stmt(stmt)?;
+        }
+        self.render_comments_before(usize::MAX)?;
+        self.w.flush()
+    }
+
+    /// Consumes the printer and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+fn stmt_line(stmt: &Statement) -> usize {
+    match stmt {
+        Statement::Let(ref def) => def.name.pos.line,
+        Statement::Expression(ref expr) => expr.pos().line,
+        Statement::Assert(ref expr) => expr.pos().line,
+        Statement::Output(ref pos, _, _, _) => pos.line,
+    }
+}
+
+fn escape_quotes(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,176 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+use super::*;
+
+use crate::iter::OffsetStrIter;
+use crate::parse::parse;
+
+fn format_src(input: &str) -> String {
+    let mut comment_map = CommentMap::new();
+    let stmts = parse(OffsetStrIter::new(input), Some(&mut comment_map)).unwrap();
+    let mut buffer: Vec<u8> = Vec::new();
+    {
+        let mut printer = AstPrinter::new(4, &mut buffer)
+            .with_comment_map(&comment_map)
+            .with_source(input);
+        printer.render(&stmts).unwrap();
+    }
+    String::from_utf8(buffer).unwrap()
+}
+
+fn count_comments(input: &str) -> usize {
+    let mut comment_map = CommentMap::new();
+    parse(OffsetStrIter::new(input), Some(&mut comment_map)).unwrap();
+    comment_map.len()
+}
+
+fn assert_idempotent(input: &str) {
+    let formatted = format_src(input);
+    assert_eq!(count_comments(input), count_comments(&formatted));
+    assert_eq!(formatted, format_src(&formatted));
+}
+
+#[test]
+fn test_simple_value_printing() {
+    assert_eq!(
+        format_src("let   foo=1;let bar = 1.0 ;let baz=\"quux \\\"q\\\"\" ; NULL;true;"),
+        "let foo = 1;\nlet bar = 1.0;\nlet baz = \"quux \\\"q\\\"\";\nNULL;\ntrue;\n"
+    );
+}
+
+#[test]
+fn test_binary_and_dot_printing() {
+    assert_eq!(
+        format_src("foo.bar.0 + 1*(2 - 3) %% 4;"),
+        "foo.bar.0 + 1 * (2 - 3) %% 4;\n"
+    );
+}
+
+#[test]
+fn test_tuple_and_list_printing() {
+    assert_eq!(
+        format_src("let t = {a=1,b=[1,2,3]};"),
+        "let t = {a = 1, b = [1, 2, 3]};\n"
+    );
+    assert_eq!(
+        format_src("let t = {\na=1,\n   b=[\n1, 2]};"),
+        "let t = {\n    a = 1,\n    b = [\n        1,\n        2,\n    ],\n};\n"
+    );
+}
+
+#[test]
+fn test_comment_printing() {
+    let input = "// leading comment\nlet foo = 1; // trailing\n\n// inner\nlet t = {\n    // field comment\n    a = 1,\n};\n// final comment\n";
+    assert_eq!(format_src(input), input);
+}
+
+#[test]
+fn test_keyword_expression_printing() {
+    assert_eq!(
+        format_src("let f=func(a,b)=>a+b;out json {};assert {ok=not true,desc=\"\"};"),
+        "let f = func (a, b) => a + b;\nout json {};\nassert {ok = not true, desc = \"\"};\n"
+    );
+    assert_eq!(
+        format_src("out json   \"deploy/app.json\" {};"),
+        "out json \"deploy/app.json\" {};\n"
+    );
+    assert_eq!(
+        format_src("let s = select foo,1,{bar=2};let r = 1:2:10;let m = \"@\" % (1,2);"),
+        "let s = select foo, 1, {bar = 2};\nlet r = 1:2:10;\nlet m = \"@\" % (1, 2);\n"
+    );
+}
+
+#[test]
+fn test_module_printing() {
+    assert_eq!(
+        format_src("let m = module{a=1}=>(out) {\nlet out = mod.a;\n};"),
+        "let m = module {a = 1} => (out) {\n    let out = mod.a;\n};\n"
+    );
+}
+
+#[test]
+fn test_type_annotation_printing() {
+    assert_eq!(
+        format_src("let f=func(a:str,b)=>a;let m = module{n:int=1,h=NULL}=>{};"),
+        "let f = func (a: str, b) => a;\nlet m = module {n: int = 1, h = NULL} => {\n};\n"
+    );
+}
+
+#[test]
+fn test_printer_is_idempotent_on_std_lib() {
+    assert_idempotent(include_str!("../../../std/functional.ucg"));
+    assert_idempotent(include_str!("../../../std/lists.ucg"));
+    assert_idempotent(include_str!("../../../std/schema.ucg"));
+    assert_idempotent(include_str!("../../../std/strings.ucg"));
+    assert_idempotent(include_str!("../../../std/testing.ucg"));
+    assert_idempotent(include_str!("../../../std/tuples.ucg"));
+    assert_idempotent(include_str!("../../../std/xml.ucg"));
+}
+
+#[test]
+fn test_printer_is_idempotent_on_integration_tests() {
+    assert_idempotent(include_str!(
+        "../../../integration_tests/comparisons_test.ucg"
+    ));
+    assert_idempotent(include_str!("../../../integration_tests/format_test.ucg"));
+    assert_idempotent(include_str!(
+        "../../../integration_tests/functional_processing_test.ucg"
+    ));
+    assert_idempotent(include_str!("../../../integration_tests/list_test.ucg"));
+    assert_idempotent(include_str!("../../../integration_tests/modules_test.ucg"));
+    assert_idempotent(include_str!(
+        "../../../integration_tests/operator_precedence_test.ucg"
+    ));
+    assert_idempotent(include_str!(
+        "../../../integration_tests/select_expressions_test.ucg"
+    ));
+    assert_idempotent(include_str!(
+        "../../../integration_tests/selectors_test.ucg"
+    ));
+    assert_idempotent(include_str!("../../../integration_tests/tuple_test.ucg"));
+    assert_idempotent(include_str!("../../../integration_tests/types_test.ucg"));
+}
+
+#[test]
+fn test_comments_in_module_params_and_func_args() {
+    let input = "let m = module { p = 1, // param\n} => {\n};\n";
+    assert_eq!(
+        format_src(input),
+        "let m = module {\n    p = 1, // param\n} => {\n};\n"
+    );
+    assert_idempotent(input);
+    let input = "let x = f(x, // arg comment\n y);\n";
+    assert_eq!(
+        format_src(input),
+        "let x = f(\n    x, // arg comment\n    y,\n);\n"
+    );
+    assert_idempotent(input);
+    let input = "let f = func(a, // a\n b) => a;\n";
+    assert_eq!(
+        format_src(input),
+        "let f = func (\n    a, // a\n    b\n) => a;\n"
+    );
+    assert_idempotent(input);
+    let input = "let l = [1,\n // last\n];\nlet s = \"@ @\" % (1, // one\n 2);\n";
+    assert_eq!(
+        format_src(input),
+        "let l = [\n    1,\n    // last\n];\nlet s = \"@ @\" % (\n    1, // one\n    2\n);\n"
+    );
+    assert_idempotent(input);
+    // A comment after a list stays a trailing comment of the statement.
+    assert_eq!(
+        format_src("let l = [1, 2]; // c\n"),
+        "let l = [1, 2]; // c\n"
+    );
+}
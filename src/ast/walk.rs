@@ -42,7 +42,7 @@ impl<'a> AstWalker<'a> {
             Statement::Assert(ref mut expr) => {
                 self.walk_expression(expr);
             }
-            Statement::Output(_, _, ref mut expr) => {
+            Statement::Output(_, _, _, ref mut expr) => {
                 self.walk_expression(expr);
             }
         }
@@ -58,11 +58,13 @@ impl<'a> AstWalker<'a> {
         self.visit_expression(expr);
         match expr {
             Expression::Call(ref mut def) => {
+                self.walk_value(&mut def.funcref);
                 for expr in def.arglist.iter_mut() {
                     self.walk_expression(expr);
                 }
             }
             Expression::Copy(ref mut def) => {
+                self.walk_value(&mut def.selector);
                 self.walk_fieldset(&mut def.fields);
             }
             Expression::Format(ref mut def) => match def.args {
@@ -77,13 +79,16 @@ impl<'a> AstWalker<'a> {
             },
             Expression::FuncOp(ref mut def) => match def {
                 FuncOpDef::Reduce(ref mut def) => {
+                    self.walk_expression(def.func.as_mut());
                     self.walk_expression(def.target.as_mut());
                     self.walk_expression(def.acc.as_mut())
                 }
                 FuncOpDef::Map(ref mut def) => {
+                    self.walk_expression(def.func.as_mut());
                     self.walk_expression(def.target.as_mut());
                 }
                 FuncOpDef::Filter(ref mut def) => {
+                    self.walk_expression(def.func.as_mut());
                     self.walk_expression(def.target.as_mut());
                 }
             },
@@ -100,6 +105,9 @@ impl<'a> AstWalker<'a> {
                 for stmt in def.statements.iter_mut() {
                     self.walk_statement(stmt);
                 }
+                if let Some(ref mut expr) = def.out_expr {
+                    self.walk_expression(expr.as_mut());
+                }
             }
             Expression::Range(ref mut def) => {
                 self.walk_expression(def.start.as_mut());
@@ -121,11 +129,14 @@ impl<'a> AstWalker<'a> {
                 self.walk_fieldset(&mut def.tuple);
             }
             Expression::Simple(ref mut val) => {
-                self.visit_value(val);
+                self.walk_value(val);
             }
-            Expression::Import(_) | Expression::Include(_) | Expression::Fail(_) => {
+            Expression::Import(_) | Expression::Include(_) => {
                 //noop
             }
+            Expression::Fail(ref mut def) => {
+                self.walk_expression(def.message.as_mut());
+            }
             Expression::Not(ref mut def) => {
                 self.walk_expression(def.expr.as_mut());
             }
@@ -135,6 +146,21 @@ impl<'a> AstWalker<'a> {
         }
     }
 
+    fn walk_value(&self, val: &mut Value) {
+        self.visit_value(val);
+        match val {
+            Value::Tuple(ref mut fs) => self.walk_fieldset(&mut fs.val),
+            Value::List(ref mut def) => {
+                for expr in def.elems.iter_mut() {
+                    self.walk_expression(expr);
+                }
+            }
+            _ => {
+                // noop
+            }
+        }
+    }
+
     fn visit_value(&self, val: &mut Value) {
         if let Some(h) = self.handle_value {
             h(val);
@@ -153,3 +179,130 @@ impl<'a> AstWalker<'a> {
         }
     }
 }
+
+/// A read-only visitor for the AST.
+///
+/// Each node is passed to the matching `visit_` method before its children
+/// are walked and to the `leave_` method after. Use the node's `pos` for its
+/// position.
+pub trait Visitor {
+    fn visit_statement(&mut self, _stmt: &Statement) {}
+    fn leave_statement(&mut self, _stmt: &Statement) {}
+    fn visit_expression(&mut self, _expr: &Expression) {}
+    fn leave_expression(&mut self, _expr: &Expression) {}
+    fn visit_value(&mut self, _val: &Value) {}
+    fn leave_value(&mut self, _val: &Value) {}
+}
+
+/// Walks a statement without changing it in the same order `AstWalker` does.
+pub fn visit_statement(v: &mut dyn Visitor, stmt: &Statement) {
+    v.visit_statement(stmt);
+    match stmt {
+        Statement::Let(ref def) => visit_expression(v, &def.value),
+        Statement::Expression(ref expr) => visit_expression(v, expr),
+        Statement::Assert(ref expr) => visit_expression(v, expr),
+        Statement::Output(_, _, _, ref expr) => visit_expression(v, expr),
+    }
+    v.leave_statement(stmt);
+}
+
+fn visit_fieldset(v: &mut dyn Visitor, fs: &FieldList) {
+    for (_, expr) in fs.iter() {
+        visit_expression(v, expr);
+    }
+}
+
+/// Walks an expression without changing it in the same order `AstWalker`
+/// does.
+pub fn visit_expression(v: &mut dyn Visitor, expr: &Expression) {
+    v.visit_expression(expr);
+    match expr {
+        Expression::Call(ref def) => {
+            visit_value(v, &def.funcref);
+            for expr in def.arglist.iter() {
+                visit_expression(v, expr);
+            }
+        }
+        Expression::Copy(ref def) => {
+            visit_value(v, &def.selector);
+            visit_fieldset(v, &def.fields);
+        }
+        Expression::Format(ref def) => match def.args {
+            FormatArgs::List(ref args) => {
+                for expr in args.iter() {
+                    visit_expression(v, expr);
+                }
+            }
+            FormatArgs::Single(ref expr) => visit_expression(v, expr),
+        },
+        Expression::FuncOp(ref def) => match def {
+            FuncOpDef::Reduce(ref def) => {
+                visit_expression(v, &def.func);
+                visit_expression(v, &def.target);
+                visit_expression(v, &def.acc);
+            }
+            FuncOpDef::Map(ref def) => {
+                visit_expression(v, &def.func);
+                visit_expression(v, &def.target);
+            }
+            FuncOpDef::Filter(ref def) => {
+                visit_expression(v, &def.func);
+                visit_expression(v, &def.target);
+            }
+        },
+        Expression::Binary(ref def) => {
+            visit_expression(v, &def.left);
+            visit_expression(v, &def.right);
+        }
+        Expression::Grouped(ref expr, _) => visit_expression(v, expr),
+        Expression::Func(ref def) => visit_expression(v, &def.fields),
+        Expression::Module(ref def) => {
+            visit_fieldset(v, &def.arg_set);
+            for stmt in def.statements.iter() {
+                visit_statement(v, stmt);
+            }
+            if let Some(ref expr) = def.out_expr {
+                visit_expression(v, expr);
+            }
+        }
+        Expression::Range(ref def) => {
+            visit_expression(v, &def.start);
+            visit_expression(v, &def.end);
+            if let Some(ref expr) = def.step {
+                visit_expression(v, expr);
+            }
+        }
+        Expression::Select(ref def) => {
+            if let Some(ref expr) = def.default {
+                visit_expression(v, expr);
+            }
+            visit_expression(v, &def.val);
+            visit_fieldset(v, &def.tuple);
+        }
+        Expression::Simple(ref val) => visit_value(v, val),
+        Expression::Import(_) | Expression::Include(_) => {
+            //noop
+        }
+        Expression::Fail(ref def) => visit_expression(v, &def.message),
+        Expression::Not(ref def) => visit_expression(v, &def.expr),
+        Expression::Debug(ref def) => visit_expression(v, &def.expr),
+    }
+    v.leave_expression(expr);
+}
+
+/// Walks a value without changing it in the same order `AstWalker` does.
+pub fn visit_value(v: &mut dyn Visitor, val: &Value) {
+    v.visit_value(val);
+    match val {
+        Value::Tuple(ref fs) => visit_fieldset(v, &fs.val),
+        Value::List(ref def) => {
+            for expr in def.elems.iter() {
+                visit_expression(v, expr);
+            }
+        }
+        _ => {
+            // noop
+        }
+    }
+    v.leave_value(val);
+}
@@ -0,0 +1,101 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Parsing for the values passed to a build with `-D name=value`.
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+
+use simple_error::SimpleError;
+
+use super::assets::MemoryCache;
+use super::{FileBuilder, Val};
+use crate::ast::*;
+use crate::iter::OffsetStrIter;
+use crate::parse::parse;
+
+/// Parses a `name=value` definition.
+///
+/// The value is parsed as a ucg expression made of literals like `3`,
+/// `"a string"`, `[1, 2]` or `{replicas = 3}`. Anything else, such as
+/// `us-east`, is taken as a string so simple values don't need quoting.
+pub fn parse_define(define: &str) -> Result<(String, Rc<Val>), Box<dyn Error>> {
+    let mut parts = define.splitn(2, '=');
+    let name = parts.next().unwrap().trim();
+    let src = match parts.next() {
+        Some(src) => src,
+        None => {
+            return Err(Box::new(SimpleError::new(format!(
+                "Expected name=value but got {}",
+                define
+            ))));
+        }
+    };
+    if name.is_empty() || name.chars().any(char::is_whitespace) {
+        return Err(Box::new(SimpleError::new(format!(
+            "Invalid name in {}",
+            define
+        ))));
+    }
+    Ok((name.to_string(), eval_literal(src)?))
+}
+
+/// Sets each of the defined values in args replacing any existing value with
+/// the same name.
+pub fn merge(args: &mut Vec<(String, Rc<Val>)>, defines: Vec<(String, Rc<Val>)>) {
+    for (name, val) in defines {
+        match args.iter_mut().find(|&&mut (ref n, _)| *n == name) {
+            Some(existing) => existing.1 = val,
+            None => args.push((name, val)),
+        }
+    }
+}
+
+fn eval_literal(src: &str) -> Result<Rc<Val>, Box<dyn Error>> {
+    let as_str = Rc::new(Val::Str(src.to_string()));
+    let input = format!("{};", src);
+    let mut stmts = match parse(OffsetStrIter::new(&input), None) {
+        Ok(stmts) => stmts,
+        Err(_) => return Ok(as_str),
+    };
+    let expr = match (stmts.pop(), stmts.is_empty()) {
+        (Some(Statement::Expression(expr)), true) => expr,
+        _ => return Ok(as_str),
+    };
+    if !is_literal(&expr) {
+        return Ok(as_str);
+    }
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(".", &i_paths, cache);
+    b.set_env(Vec::new());
+    b.eval_expr(&expr, &b.scope)
+}
+
+fn is_literal(expr: &Expression) -> bool {
+    match expr {
+        Expression::Simple(ref val) => match val {
+            Value::Symbol(_) => false,
+            Value::Tuple(ref fs) => fs.val.iter().all(|(_, e)| is_literal(e)),
+            Value::List(ref def) => def.elems.iter().all(is_literal),
+            _ => true,
+        },
+        Expression::Grouped(ref expr, _) => is_literal(expr),
+        Expression::Not(ref def) => is_literal(&def.expr),
+        Expression::Binary(ref def) => {
+            def.kind != BinaryExprType::DOT && is_literal(&def.left) && is_literal(&def.right)
+        }
+        _ => false,
+    }
+}
@@ -452,7 +452,7 @@ fn test_out_multiple_times_compile_failure() {
     assert_build_failure(
         "out json {};\nout json {};",
         vec![
-            Regex::new(r"You can only have one output per file").unwrap(),
+            Regex::new(r"There is already an unnamed json output").unwrap(),
             Regex::new(r"at <eval> line: 2, column: 1").unwrap(),
         ],
     )
@@ -540,14 +540,67 @@ fn test_func_call_wrong_argument_type_compile_failure() {
     assert_build_failure(
         "let foo = func(i) => 1 + i;\nfoo(\"bar\");",
         vec![
-            Regex::new(r"Func evaluation failed").unwrap(),
-            Regex::new(r"at <eval> line: 1, column: 26").unwrap(),
             Regex::new(r"Expected Integer but got \(.bar.\)").unwrap(),
+            Regex::new(r"at <eval> line: 1, column: 26").unwrap(),
+            Regex::new(r"Stack Trace:").unwrap(),
+            Regex::new(r"0: func defined at <eval> line: 1, column: 11").unwrap(),
+            Regex::new(r"called at <eval> line: 2, column: 1").unwrap(),
+        ],
+    )
+}
+
+#[test]
+fn test_func_call_annotated_argument_type_compile_failure() {
+    assert_build_failure(
+        "let foo = func(name: str, port: int) => name;\nfoo(\"host\", \"80\");",
+        vec![
+            Regex::new(r"Func argument port must be int but got \(String\)").unwrap(),
             Regex::new(r"at <eval> line: 2, column: 1").unwrap(),
         ],
     )
 }
 
+#[test]
+fn test_module_copy_annotated_param_type_compile_failure() {
+    assert_build_failure(
+        "let m = module{host: str = NULL} => (h) {\nlet h = mod.host;\n};\nm{host = 1};",
+        vec![
+            Regex::new(r"Module parameter host must be str but got \(Integer\)").unwrap(),
+            Regex::new(r"at <eval> line: 4, column: 3").unwrap(),
+        ],
+    )
+}
+
+#[test]
+fn test_module_copy_missing_annotated_param_compile_failure() {
+    assert_build_failure(
+        "let m = module{host: str = NULL} => (h) {\nlet h = mod.host;\n};\nm{};",
+        vec![
+            Regex::new(r"Module parameter host must be str but got \(EmptyValue\)").unwrap(),
+            Regex::new(r"at <eval> line: 4, column: 1").unwrap(),
+        ],
+    )
+}
+
+#[test]
+fn test_unknown_type_annotation_compile_failure() {
+    assert_build_failure(
+        "let foo = func(port: integer) => port;",
+        vec![Regex::new(r"Unknown type integer").unwrap()],
+    )
+}
+
+#[test]
+fn test_range_step_must_be_positive_compile_failure() {
+    assert_build_failure(
+        "1:0:10;",
+        vec![
+            Regex::new(r"Range step must be greater than 0 but got 0").unwrap(),
+            Regex::new(r"at <eval> line: 1, column: 1").unwrap(),
+        ],
+    )
+}
+
 #[test]
 fn test_select_missed_case_string_no_default_compile_failure() {
     assert_build_failure(
@@ -0,0 +1,84 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Parsing for files of environment variables.
+use std::error::Error;
+
+use simple_error::SimpleError;
+
+/// Parses a single `KEY=VALUE` assignment.
+///
+/// The value is everything after the first `=`. A value wrapped in matching
+/// single or double quotes has them removed.
+pub fn parse_assignment(assignment: &str) -> Result<(String, String), Box<dyn Error>> {
+    let mut parts = assignment.splitn(2, '=');
+    let key = parts.next().unwrap().trim();
+    let val = match parts.next() {
+        Some(val) => val.trim(),
+        None => {
+            return Err(Box::new(SimpleError::new(format!(
+                "Expected KEY=VALUE but got {}",
+                assignment
+            ))));
+        }
+    };
+    if key.is_empty() || key.chars().any(char::is_whitespace) {
+        return Err(Box::new(SimpleError::new(format!(
+            "Invalid environment variable name in {}",
+            assignment
+        ))));
+    }
+    let unquoted = if val.len() >= 2
+        && ((val.starts_with('"') && val.ends_with('"'))
+            || (val.starts_with('\'') && val.ends_with('\'')))
+    {
+        &val[1..val.len() - 1]
+    } else {
+        val
+    };
+    Ok((key.to_string(), unquoted.to_string()))
+}
+
+/// Parses the contents of an env file.
+///
+/// Each line is a `KEY=VALUE` assignment optionally prefixed with `export`.
+/// Blank lines and lines starting with `#` are ignored.
+pub fn parse(src: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut vars = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_start_matches("export ");
+        match parse_assignment(line) {
+            Ok(var) => vars.push(var),
+            Err(e) => {
+                return Err(Box::new(SimpleError::new(format!("line {}: {}", i + 1, e))));
+            }
+        }
+    }
+    Ok(vars)
+}
+
+/// Sets each of the vars in env replacing any existing value with the same
+/// name.
+pub fn merge(env: &mut Vec<(String, String)>, vars: Vec<(String, String)>) {
+    for (key, val) in vars {
+        match env.iter_mut().find(|&&mut (ref k, _)| *k == key) {
+            Some(existing) => existing.1 = val,
+            None => env.push((key, val)),
+        }
+    }
+}
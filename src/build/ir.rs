@@ -6,6 +6,7 @@ use std::fmt::{Display, Formatter};
 use std::rc::Rc;
 use std::string::ToString;
 
+use super::native::NativeFunc;
 use crate::ast::*;
 use crate::error;
 
@@ -21,6 +22,7 @@ pub enum Val {
     Tuple(Vec<(String, Rc<Val>)>),
     Env(Vec<(String, String)>),
     Func(FuncDef),
+    Native(NativeFunc),
     Module(ModuleDef),
 }
 
@@ -36,7 +38,7 @@ impl Val {
             &Val::List(_) => "List".to_string(),
             &Val::Tuple(_) => "Tuple".to_string(),
             &Val::Env(_) => "Env".to_string(),
-            &Val::Func(_) => "Func".to_string(),
+            &Val::Func(_) | &Val::Native(_) => "Func".to_string(),
             &Val::Module(_) => "Module".to_string(),
         }
     }
@@ -55,10 +57,14 @@ impl Val {
             &Val::Tuple(_),
             &Val::Env(_),
             &Val::Func(_),
+            &Val::Native(_),
             &Val::Module(_)
         )
     }
 
+    // BuildError grew a stack trace but equal's signature is public so it
+    // keeps returning the error by value.
+    #[allow(clippy::result_large_err)]
     pub fn equal(&self, target: &Self) -> Result<bool, error::BuildError> {
         // first we do a type equality comparison
         match (self, target) {
@@ -99,7 +105,10 @@ impl Val {
                     Ok(true)
                 }
             }
-            (&Val::Func(_), &Val::Func(_)) => Err(error::BuildError::new(
+            (&Val::Func(_), &Val::Func(_))
+            | (&Val::Native(_), &Val::Native(_))
+            | (&Val::Func(_), &Val::Native(_))
+            | (&Val::Native(_), &Val::Func(_)) => Err(error::BuildError::new(
                 "Func are not comparable",
                 error::ErrorType::TypeFail,
             )),
@@ -183,10 +192,7 @@ impl Val {
     }
 
     pub fn is_func(&self) -> bool {
-        if let &Val::Func(_) = self {
-            return true;
-        }
-        return false;
+        matches!(self, Val::Func(_) | Val::Native(_))
     }
 
     pub fn is_str(&self) -> bool {
@@ -201,6 +207,24 @@ impl Val {
         }
         return false;
     }
+
+    /// Returns whether this value has the named type or None if the name
+    /// isn't one of the type names.
+    pub fn is_type(&self, name: &str) -> Option<bool> {
+        let result = match name {
+            "str" => self.is_str(),
+            "bool" => self.is_bool(),
+            "null" => self.is_empty(),
+            "int" => self.is_int(),
+            "float" => self.is_float(),
+            "tuple" => self.is_tuple(),
+            "list" => self.is_list(),
+            "func" => self.is_func(),
+            "module" => self.is_module(),
+            _ => return None,
+        };
+        Some(result)
+    }
 }
 
 impl Display for Val {
@@ -219,6 +243,7 @@ impl Display for Val {
                 write!(f, "]")
             }
             &Val::Func(_) => write!(f, "Func(..)"),
+            &Val::Native(ref def) => write!(f, "Func({})", def.name),
             &Val::Module(_) => write!(f, "Module{{..}}"),
             &Val::Tuple(ref def) => {
                 write!(f, "{{\n")?;
@@ -13,38 +13,47 @@
 //  limitations under the License.
 
 //! The build stage of the ucg compiler.
-use std::cell::RefCell;
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
 use std::error::Error;
 use std::fs::File;
 use std::io::Read;
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
 use std::string::ToString;
 
+use indexmap::map::Entry;
+use indexmap::IndexMap;
 use regex;
-use simple_error;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::ast::*;
 use crate::build::format::{ExpressionFormatter, FormatRenderer, SimpleFormatter};
-use crate::build::scope::{find_in_fieldlist, Scope, ValueMap};
+use crate::build::scope::{closest_match, find_in_fieldlist, Scope, ValueMap};
 use crate::convert::ImporterRegistry;
 use crate::error;
 use crate::iter::OffsetStrIter;
-use crate::parse::parse;
+use crate::parse::{parse, parse_all};
+use crate::tokenizer::tokenize;
 
+pub mod args;
 pub mod assets;
+pub mod envfile;
 pub mod format;
 pub mod ir;
+pub mod native;
+pub mod report;
+pub mod sandbox;
 pub mod scope;
+pub mod warnings;
 
-mod stdlib;
+pub(crate) mod stdlib;
 
 pub use self::ir::Val;
+pub use self::native::{NativeFunc, NativeRegistry};
+pub use self::sandbox::Sandbox;
 
 enum ProcessingOpType {
     Map,
@@ -57,6 +66,7 @@ impl FuncDef {
         &self,
         parent_builder: &FileBuilder,
         mut args: Vec<Rc<Val>>,
+        call_site: &Position,
     ) -> Result<Rc<Val>, Box<dyn Error>> {
         // Error conditions. If the args don't match the length and types of the argdefs then this is
         // func call error.
@@ -68,9 +78,28 @@ impl FuncDef {
             )
             .to_boxed());
         }
-        // If the args don't match the types required by the expressions then that is a TypeFail.
+        // If the args don't match their type annotations then the caller passed
+        // the wrong thing.
+        for (i, arg) in args.iter().enumerate() {
+            let name = &self.argdefs[i].val;
+            if let Some(typ) = self.arg_types.get(name) {
+                if arg.is_type(&typ.fragment) != Some(true) {
+                    return Err(error::BuildError::with_pos(
+                        format!(
+                            "Func argument {} must be {} but got ({})",
+                            name,
+                            typ.fragment,
+                            arg.type_name()
+                        ),
+                        error::ErrorType::TypeFail,
+                        call_site.clone(),
+                    )
+                    .to_boxed());
+                }
+            }
+        }
         // If the expressions reference Symbols not defined in the FuncDef that is also an error.
-        let mut build_output = HashMap::<PositionedItem<String>, Rc<Val>>::new();
+        let mut build_output = ValueMap::new();
         for (i, arg) in args.drain(0..).enumerate() {
             build_output
                 .entry(self.argdefs[i].clone())
@@ -80,21 +109,88 @@ impl FuncDef {
         if let Some(ref scope) = self.scope {
             b.scope = scope.spawn_child();
         }
+        if let Some(ref funcs) = self.rec_funcs {
+            b.scope.rec_funcs = Some(funcs.clone());
+        }
         // We clobber anything that used to be in the scope with the arguments.
         b.merge_build_output(build_output, true);
         Ok(b.eval_expr(self.fields.as_ref(), &b.scope.spawn_child())?)
     }
 }
 
+/// How deeply func calls and module copies may nest before the build fails
+/// unless `FileBuilder::set_max_call_depth` says otherwise. It leaves room to
+/// spare in the 2MB stack Rust gives new threads even in debug builds. The
+/// ucg commands run on a larger stack and default to a much deeper limit.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 32;
+
+/// About the most stack one level of nested func calls or module copies uses
+/// in a debug build. Release builds use less.
+pub const STACK_PER_CALL: usize = 64 * 1024;
+
+// How much work a build may do. The counters are shared with sub-builders
+// so work done in imports, modules and funcs counts against the same limits.
+#[derive(Debug, Clone)]
+struct Limits {
+    max_call_depth: usize,
+    max_steps: Option<u64>,
+    max_range: Option<usize>,
+    max_size: Option<usize>,
+    call_depth: Rc<Cell<usize>>,
+    steps: Rc<Cell<u64>>,
+}
+
+impl Limits {
+    fn new() -> Self {
+        Limits {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_steps: None,
+            max_range: None,
+            max_size: None,
+            call_depth: Rc::new(Cell::new(0)),
+            steps: Rc::new(Cell::new(0)),
+        }
+    }
+}
+
+fn limit_error<S: Into<String>>(msg: S, pos: &Position) -> Box<dyn Error> {
+    error::BuildError::with_pos(msg, error::ErrorType::LimitExceeded, pos.clone()).to_boxed()
+}
+
 /// The result of a build.
 type BuildResult = Result<(), Box<dyn Error>>;
 
+/// An artifact defined by an out statement.
+#[derive(Debug, Clone)]
+pub struct Output {
+    /// The name of the converter for this output.
+    pub typ: String,
+    /// The path named in the out statement if there was one.
+    pub path: Option<String>,
+    pub val: Rc<Val>,
+    pub pos: Position,
+}
+
+/// The result of a single assert statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertResult {
+    /// The position of the asserted expression. The file is on the position.
+    pub pos: Position,
+    /// The desc field of the assertion or a description of why it could not
+    /// be checked.
+    pub desc: String,
+    pub success: bool,
+    /// The error if the asserted expression failed to compile.
+    pub error: Option<String>,
+}
+
 /// AssertCollector collects the results of assertions in the UCG AST.
 pub struct AssertCollector {
     pub counter: i32,
     pub success: bool,
     pub summary: String,
     pub failures: String,
+    pub results: Vec<AssertResult>,
 }
 
 /// Builder handles building ucg code for a single file.
@@ -103,6 +199,7 @@ pub struct FileBuilder<'a> {
     std: Rc<HashMap<String, &'static str>>,
     import_path: &'a Vec<PathBuf>,
     validate_mode: bool,
+    keep_going: bool,
     pub assert_collector: AssertCollector,
     scope: Scope,
     import_registry: ImporterRegistry,
@@ -118,9 +215,24 @@ pub struct FileBuilder<'a> {
     // so multiple imports of the same file don't have to be parsed
     // multiple times.
     assets: Rc<RefCell<assets::Cache>>,
+    // Every file this build and any of its sub-builders has read or tried
+    // to read. Shared with sub-builders the same way the assets are.
+    touched_files: Rc<RefCell<BTreeSet<PathBuf>>>,
+    // Where each imported file was first imported from. Shared with
+    // sub-builders so stack frames can say how a definition got in scope.
+    import_sites: Rc<RefCell<BTreeMap<PathBuf, Position>>>,
+    // The warnings for every file this build and its sub-builders built.
+    warnings: Rc<RefCell<Vec<error::Warning>>>,
+    // The funcs bound by let in this builder's statements. Every one of
+    // them shares this set so they can call each other.
+    rec_funcs: RecFuncs,
+    limits: Limits,
+    sandbox: Option<Rc<Sandbox>>,
+    // The native funcs ucg code can import. Sub-builders share them.
+    natives: Rc<NativeRegistry>,
     pub is_module: bool,
     pub last: Option<Rc<Val>>,
-    pub out_lock: Option<(String, Rc<Val>)>,
+    pub outputs: Vec<Output>,
 }
 
 macro_rules! eval_binary_expr {
@@ -143,6 +255,9 @@ macro_rules! eval_binary_expr {
 
 impl<'a> FileBuilder<'a> {
     /// Constructs a new Builder.
+    ///
+    /// `env` starts out as the environment of the process. Use `set_env` to
+    /// give the build a different one.
     pub fn new<P: Into<PathBuf>>(
         working_dir: P,
         import_paths: &'a Vec<PathBuf>,
@@ -156,16 +271,25 @@ impl<'a> FileBuilder<'a> {
             std: Rc::new(stdlib::get_libs()),
             import_path: import_paths,
             validate_mode: false,
+            keep_going: false,
             assert_collector: AssertCollector {
                 counter: 0,
                 success: true,
                 summary: String::new(),
                 failures: String::new(),
+                results: Vec::new(),
             },
             scope: scope,
             import_registry: ImporterRegistry::make_registry(),
             assets: cache,
-            out_lock: None,
+            touched_files: Rc::new(RefCell::new(BTreeSet::new())),
+            import_sites: Rc::new(RefCell::new(BTreeMap::new())),
+            warnings: Rc::new(RefCell::new(Vec::new())),
+            rec_funcs: Rc::new(RefCell::new(BTreeMap::new())),
+            limits: Limits::new(),
+            sandbox: None,
+            natives: Rc::new(NativeRegistry::make_registry()),
+            outputs: Vec::new(),
             is_module: false,
             last: None,
         }
@@ -177,17 +301,26 @@ impl<'a> FileBuilder<'a> {
             std: self.std.clone(),
             import_path: self.import_path,
             validate_mode: false,
+            keep_going: false,
             assert_collector: AssertCollector {
                 counter: 0,
                 success: true,
                 summary: String::new(),
                 failures: String::new(),
+                results: Vec::new(),
             },
             assets: self.assets.clone(),
+            touched_files: self.touched_files.clone(),
+            import_sites: self.import_sites.clone(),
+            warnings: self.warnings.clone(),
+            rec_funcs: Rc::new(RefCell::new(BTreeMap::new())),
+            limits: self.limits.clone(),
+            sandbox: self.sandbox.clone(),
+            natives: self.natives.clone(),
             // This is admittedly a little wasteful but we can live with it for now.
             import_registry: ImporterRegistry::make_registry(),
             scope: self.scope.spawn_clean(),
-            out_lock: None,
+            outputs: Vec::new(),
             is_module: false,
             last: None,
         }
@@ -196,29 +329,46 @@ impl<'a> FileBuilder<'a> {
     /// Builds a ucg file at the named path.
     pub fn build<P: Into<PathBuf>>(&mut self, file: P) -> BuildResult {
         let file = file.into();
-        self.working_dir = file.parent().unwrap().to_path_buf();
+        // Track the file before opening it so that a missing file is
+        // still watched.
+        self.touch_file(file.clone());
         let mut f = self.open_file(&Position::new(0, 0, 0), &file)?;
         let mut s = String::new();
         f.read_to_string(&mut s)?;
-        let input = OffsetStrIter::new(&s).with_src_file(file.clone());
-        let eval_result = self.eval_input(input);
-        match eval_result {
-            Ok(v) => {
-                self.last = Some(v);
-                Ok(())
-            }
-            Err(e) => {
-                let err = simple_error::SimpleError::new(
-                    format!(
-                        "Error building file: {}\n{}",
-                        file.to_string_lossy(),
-                        e.as_ref()
-                    )
-                    .as_ref(),
-                );
-                Err(Box::new(err))
-            }
-        }
+        self.build_str(file, &s)
+    }
+
+    /// Builds the given source as though it were the contents of the file at
+    /// the named path. Imports are resolved relative to that file.
+    pub fn build_str<P: Into<PathBuf>>(&mut self, file: P, src: &str) -> BuildResult {
+        let file = file.into();
+        self.working_dir = file.parent().unwrap().to_path_buf();
+        self.touch_file(file.clone());
+        let input = OffsetStrIter::new(src).with_src_file(file.clone());
+        // NOTE(jwall): The error is returned as is rather than wrapped so that
+        // callers can still inspect it. The positions it carries already
+        // point at this file.
+        let stmts = self.parse_input(input)?;
+        self.warnings.borrow_mut().extend(warnings::check(&stmts));
+        self.last = Some(self.eval_parsed(&stmts)?);
+        Ok(())
+    }
+
+    /// Returns the warnings for the files this builder and its sub-builders
+    /// have built. The std library is not checked.
+    pub fn warnings(&self) -> Vec<error::Warning> {
+        self.warnings.borrow().clone()
+    }
+
+    /// Returns every file that builds with this builder or its sub-builders have
+    /// depended on. This includes imports, includes, and std library imports
+    /// as well as files that were looked for but could not be found.
+    pub fn touched_files(&self) -> BTreeSet<PathBuf> {
+        self.touched_files.borrow().clone()
+    }
+
+    fn touch_file(&self, path: PathBuf) {
+        self.touched_files.borrow_mut().insert(path);
     }
 
     pub fn merge_build_output(&mut self, scope: ValueMap, clobber: bool) {
@@ -235,6 +385,121 @@ impl<'a> FileBuilder<'a> {
         self.scope.strict = to;
     }
 
+    /// Sets how deeply func calls and module copies may nest. Deeper calls
+    /// fail with a LimitExceeded error instead of overflowing the stack.
+    ///
+    /// How much stack each level uses depends on how deeply the expressions
+    /// in the func body nest. Builds with a limit above
+    /// `DEFAULT_MAX_CALL_DEPTH` should run on a thread with about
+    /// `to * STACK_PER_CALL` bytes of stack.
+    pub fn set_max_call_depth(&mut self, to: usize) {
+        self.limits.max_call_depth = to;
+    }
+
+    /// Sets how many expressions a build may evaluate before it fails with a
+    /// LimitExceeded error. Unlimited by default.
+    pub fn set_max_steps(&mut self, to: u64) {
+        self.limits.max_steps = Some(to);
+    }
+
+    /// Sets how many elements a range may produce before it fails with a
+    /// LimitExceeded error. Unlimited by default.
+    pub fn set_max_range(&mut self, to: usize) {
+        self.limits.max_range = Some(to);
+    }
+
+    /// Sets how many bytes a string and how many elements a list may hold
+    /// before the build fails with a LimitExceeded error. Unlimited by default.
+    pub fn set_max_size(&mut self, to: usize) {
+        self.limits.max_size = Some(to);
+        self.natives.set_max_size(Some(to));
+    }
+
+    /// Sets the environment variables `env` refers to.
+    pub fn set_env(&mut self, vars: Vec<(String, String)>) {
+        let vars = match self.sandbox {
+            Some(ref sandbox) => sandbox.filter_env(&vars),
+            None => vars,
+        };
+        self.scope.env = Rc::new(Val::Env(vars));
+    }
+
+    /// Registers a native func that ucg code can call after importing
+    /// `std/native`. It replaces any func already registered with that name.
+    pub fn register_native<S, F>(&mut self, name: S, f: F)
+    where
+        S: Into<String>,
+        F: Fn(&[Rc<Val>], &Position) -> native::NativeResult + 'static,
+    {
+        Rc::make_mut(&mut self.natives).register(name, f);
+    }
+
+    /// Sets the values `args` refers to in this build and the files it
+    /// imports.
+    pub fn set_args(&mut self, args: Vec<(String, Rc<Val>)>) {
+        self.scope.args = Rc::new(Val::Tuple(args));
+    }
+
+    /// Restricts the build to the environment variables and files the
+    /// sandbox allows. Anything else fails with a SandboxViolation error.
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        if let Val::Env(ref vars) = self.scope.env.as_ref() {
+            self.scope.env = Rc::new(Val::Env(sandbox.filter_env(vars)));
+        }
+        self.sandbox = Some(Rc::new(sandbox));
+    }
+
+    // Fails if the sandbox doesn't allow reading the canonicalized path. This
+    // catches symlinks out of the roots that file_candidates can't see.
+    fn check_sandbox(&self, path: &Path, pos: &Position) -> Result<(), Box<dyn Error>> {
+        match self.sandbox {
+            Some(ref sandbox) if !sandbox.allows_path(path) => Err(error::BuildError::with_pos(
+                format!("{} is outside of the sandbox roots", path.to_string_lossy()),
+                error::ErrorType::SandboxViolation,
+                pos.clone(),
+            )
+            .to_boxed()),
+            _ => Ok(()),
+        }
+    }
+
+    // Fails if the sandbox hides the environment variable a dot lookup on
+    // env is for. Hidden variables are left out of env so without this they
+    // would look like they aren't set.
+    fn check_sandbox_env(
+        &self,
+        name: &str,
+        scope: &Scope,
+        pos: &Position,
+    ) -> Result<(), Box<dyn Error>> {
+        let sandbox = match self.sandbox {
+            Some(ref sandbox) => sandbox,
+            None => return Ok(()),
+        };
+        match scope.curr_val.as_ref().map(|v| v.as_ref()) {
+            Some(Val::Env(_)) if !sandbox.allows_env(name) => Err(error::BuildError::with_pos(
+                format!(
+                    "Environment variable {} is not allowed by the sandbox",
+                    name
+                ),
+                error::ErrorType::SandboxViolation,
+                pos.clone(),
+            )
+            .to_boxed()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets whether a build keeps going after an error.
+    ///
+    /// When set every statement that fails to parse is reported. If the
+    /// file parses then every let binding that doesn't depend on a failed
+    /// binding is still evaluated and all of the failures are reported
+    /// together in an `error::MultiError`.
+    pub fn set_keep_going(&mut self, to: bool) {
+        self.keep_going = to;
+    }
+
     fn eval_tuple(
         &self,
         fields: &Vec<(Token, Expression)>,
@@ -263,15 +528,9 @@ impl<'a> FileBuilder<'a> {
             &Value::Int(ref i) => Ok(Rc::new(Val::Int(i.val))),
             &Value::Float(ref f) => Ok(Rc::new(Val::Float(f.val))),
             &Value::Str(ref s) => Ok(Rc::new(Val::Str(s.val.to_string()))),
-            &Value::Symbol(ref s) => {
-                scope
-                    .lookup_sym(&(s.into()), true)
-                    .ok_or(Box::new(error::BuildError::with_pos(
-                        format!("Unable to find binding {}", s.val,),
-                        error::ErrorType::NoSuchSymbol,
-                        v.pos().clone(),
-                    )))
-            }
+            &Value::Symbol(ref s) => scope
+                .lookup_sym(&(s.into()), true)
+                .ok_or_else(|| self.no_such_binding(&s.val, v.pos(), scope, true)),
             &Value::List(ref def) => self.eval_list(def, scope),
             &Value::Tuple(ref tuple) => self.eval_tuple(&tuple.val, scope),
         }
@@ -302,32 +561,97 @@ impl<'a> FileBuilder<'a> {
         Ok(())
     }
 
-    fn eval_input(&mut self, input: OffsetStrIter) -> Result<Rc<Val>, Box<dyn Error>> {
-        match parse(input.clone()) {
-            Ok(stmts) => {
-                //panic!("Successfully parsed {}", input);
-                let mut out: Option<Rc<Val>> = None;
-                for stmt in stmts.iter() {
-                    out = Some(self.eval_stmt(stmt)?);
+    fn parse_error(pos: Position, err: error::StackPrinter<Position>) -> Box<dyn Error> {
+        error::BuildError::with_pos("Unable to parse input", error::ErrorType::ParseError, pos)
+            .wrap_cause(Box::new(err))
+            .to_boxed()
+    }
+
+    fn collect_errors(mut errors: Vec<Box<dyn Error>>) -> Box<dyn Error> {
+        if errors.len() == 1 {
+            errors.remove(0)
+        } else {
+            Box::new(error::MultiError { errors })
+        }
+    }
+
+    // Whether any symbol in the statement refers to one of the names.
+    fn references_any(stmt: &Statement, names: &BTreeSet<String>) -> bool {
+        !warnings::referenced_names(std::slice::from_ref(stmt), None).is_disjoint(names)
+    }
+
+    fn eval_stmts_keep_going(&mut self, stmts: &[Statement]) -> Result<Rc<Val>, Box<dyn Error>> {
+        let mut failed = BTreeSet::new();
+        let mut errors = Vec::new();
+        let mut out: Option<Rc<Val>> = None;
+        for stmt in stmts.iter() {
+            // Statements that use a failed binding would only repeat its
+            // error so we skip them.
+            let ok = if !failed.is_empty() && Self::references_any(stmt, &failed) {
+                false
+            } else {
+                match self.eval_stmt(stmt) {
+                    Ok(val) => {
+                        out = Some(val);
+                        true
+                    }
+                    Err(err) => {
+                        errors.push(err);
+                        false
+                    }
                 }
-                match out {
-                    None => return Ok(Rc::new(Val::Empty)),
-                    Some(val) => Ok(val),
+            };
+            match stmt {
+                Statement::Let(ref def) if !ok => {
+                    failed.insert(def.name.fragment.clone());
                 }
+                _ => {}
             }
-            Err(err) => {
-                let cause = Box::new(simple_error::SimpleError::new(err));
-                Err(error::BuildError::with_pos(
-                    "Unable to parse input",
-                    error::ErrorType::ParseError,
-                    (&input).into(),
+        }
+        if !errors.is_empty() {
+            return Err(Self::collect_errors(errors));
+        }
+        Ok(out.unwrap_or_else(|| Rc::new(Val::Empty)))
+    }
+
+    fn parse_input(&self, input: OffsetStrIter) -> Result<Vec<Statement>, Box<dyn Error>> {
+        if self.keep_going {
+            return parse_all(input.clone(), None).map_err(|errs| {
+                Self::collect_errors(
+                    errs.into_iter()
+                        .map(|e| {
+                            // Point at the broken statement rather than the
+                            // start of the file so the errors can be told
+                            // apart.
+                            let pos = e.err.get_context().clone();
+                            Self::parse_error(pos, e)
+                        })
+                        .collect(),
                 )
-                .wrap_cause(cause)
-                .to_boxed())
-            }
+            });
+        }
+        parse(input.clone(), None).map_err(|err| Self::parse_error((&input).into(), err))
+    }
+
+    fn eval_parsed(&mut self, stmts: &[Statement]) -> Result<Rc<Val>, Box<dyn Error>> {
+        if self.keep_going {
+            return self.eval_stmts_keep_going(stmts);
+        }
+        let mut out: Option<Rc<Val>> = None;
+        for stmt in stmts.iter() {
+            out = Some(self.eval_stmt(stmt)?);
+        }
+        match out {
+            None => return Ok(Rc::new(Val::Empty)),
+            Some(val) => Ok(val),
         }
     }
 
+    fn eval_input(&mut self, input: OffsetStrIter) -> Result<Rc<Val>, Box<dyn Error>> {
+        let stmts = self.parse_input(input)?;
+        self.eval_parsed(&stmts)
+    }
+
     /// Evaluate an input string as UCG.
     pub fn eval_string(&mut self, input: &str) -> Result<Rc<Val>, Box<dyn Error>> {
         self.eval_input(OffsetStrIter::new(input))
@@ -349,42 +673,227 @@ impl<'a> FileBuilder<'a> {
             .is_some()
     }
 
-    fn find_file<P: Into<PathBuf>>(
+    // Returns the places find_file should look for path in order. Places the
+    // sandbox doesn't allow are left out and if that leaves none this fails
+    // before anything has looked at the filesystem. Otherwise whether a file
+    // outside of the roots exists would change the error.
+    fn file_candidates(
         &self,
-        path: P,
+        path: &str,
         use_import_path: bool,
-    ) -> Result<PathBuf, Box<dyn Error>> {
-        // Try a relative path first.
-        let path = path.into();
-        let mut normalized = self.working_dir.clone();
+        pos: &Position,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let path = Path::new(path);
+        // The sandbox roots are canonicalized so the directories we look in
+        // have to be as well.
+        let join = |dir: &PathBuf| {
+            dir.canonicalize()
+                .unwrap_or_else(|_| dir.clone())
+                .join(path)
+        };
+        let mut candidates = Vec::new();
         if path.is_relative() {
-            normalized.push(&path);
-            // First see if the normalized file exists or not.
-            if !normalized.exists() && use_import_path {
-                // If it does not then look for it in the list of import_paths
-                for mut p in self.import_path.iter().cloned() {
-                    p.push(&path);
-                    if p.exists() {
-                        normalized = p;
-                        break;
-                    }
-                }
+            // Try a relative path first.
+            candidates.push(join(&self.working_dir));
+            if use_import_path {
+                // Then look for it in the list of import_paths.
+                candidates.extend(self.import_path.iter().map(join));
             }
         } else {
-            normalized = path;
+            candidates.push(path.to_path_buf());
+        }
+        if let Some(ref sandbox) = self.sandbox {
+            candidates.retain(|p| sandbox.may_allow_path(p));
+            if candidates.is_empty() {
+                return Err(error::BuildError::with_pos(
+                    format!("{} is outside of the sandbox roots", path.to_string_lossy()),
+                    error::ErrorType::SandboxViolation,
+                    pos.clone(),
+                )
+                .to_boxed());
+            }
+        }
+        Ok(candidates)
+    }
+
+    fn find_file(&self, candidates: Vec<PathBuf>) -> Result<PathBuf, Box<dyn Error>> {
+        let mut normalized = candidates[0].clone();
+        for p in candidates {
+            if p.exists() {
+                normalized = p;
+                break;
+            }
         }
         match normalized.canonicalize() {
-            Ok(p) => Ok(p),
-            Err(e) => Err(error::BuildError::new(
-                format!("Path not found {}", normalized.to_string_lossy()),
-                error::ErrorType::OSError,
+            Ok(p) => {
+                self.touch_file(p.clone());
+                Ok(p)
+            }
+            Err(e) => {
+                // Track the missing file too so that creating it counts as a change.
+                self.touch_file(normalized.clone());
+                Err(error::BuildError::new(
+                    format!("Path not found {}", normalized.to_string_lossy()),
+                    error::ErrorType::OSError,
+                )
+                .wrap_cause(Box::new(e))
+                .to_boxed())
+            }
+        }
+    }
+
+    fn record_import_site(&self, path: &Path, pos: &Position) {
+        self.import_sites
+            .borrow_mut()
+            .entry(path.to_path_buf())
+            .or_insert_with(|| pos.clone());
+    }
+
+    // Records a frame for a func call or module copy on an error.
+    fn call_frame(
+        &self,
+        err: Box<dyn Error>,
+        kind: error::FrameKind,
+        call_site: &Position,
+        definition: &Position,
+    ) -> Box<dyn Error> {
+        // Definitions from the file being built were not imported.
+        let imported_at = match definition.file {
+            Some(ref file) if definition.file != call_site.file => {
+                self.import_sites.borrow().get(file).cloned()
+            }
+            _ => None,
+        };
+        error::with_frame(
+            err,
+            error::StackFrame {
+                kind,
+                call_site: call_site.clone(),
+                definition: definition.clone(),
+                imported_at,
+            },
+        )
+    }
+
+    fn import_frame(&self, err: Box<dyn Error>, path: &Path, pos: &Position) -> Box<dyn Error> {
+        error::with_frame(
+            err,
+            error::StackFrame {
+                kind: error::FrameKind::Import,
+                call_site: pos.clone(),
+                definition: Position::new(1, 1, 0).with_file(path),
+                imported_at: None,
+            },
+        )
+    }
+
+    // Runs a func call or module copy failing instead if they are already
+    // nested as deep as they are allowed to go.
+    fn nested_call<F>(&self, call_site: &Position, f: F) -> Result<Rc<Val>, Box<dyn Error>>
+    where
+        F: FnOnce() -> Result<Rc<Val>, Box<dyn Error>>,
+    {
+        let depth = self.limits.call_depth.get();
+        if depth >= self.limits.max_call_depth {
+            return Err(limit_error(
+                format!(
+                    "Func calls and module copies nested more than {} deep. Is there unbounded recursion?",
+                    self.limits.max_call_depth
+                ),
+                call_site,
+            ));
+        }
+        self.limits.call_depth.set(depth + 1);
+        let result = f();
+        self.limits.call_depth.set(depth);
+        result
+    }
+
+    // Counts an evaluation step failing once there have been too many.
+    fn count_step(&self, pos: &Position) -> Result<(), Box<dyn Error>> {
+        let steps = self.limits.steps.get() + 1;
+        self.limits.steps.set(steps);
+        match self.limits.max_steps {
+            Some(max) if steps > max => Err(limit_error(
+                format!("Evaluation took more than the maximum of {} steps", max),
+                pos,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Fails if a value is larger than the maximum size.
+    fn check_size(&self, val: &Val, pos: &Position) -> Result<(), Box<dyn Error>> {
+        let max = match self.limits.max_size {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        match val {
+            Val::Str(ref s) if s.len() > max => Err(limit_error(
+                format!(
+                    "String of {} bytes is larger than the maximum size of {}",
+                    s.len(),
+                    max
+                ),
+                pos,
+            )),
+            Val::List(ref l) if l.len() > max => Err(limit_error(
+                format!(
+                    "List of {} elements is larger than the maximum size of {}",
+                    l.len(),
+                    max
+                ),
+                pos,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Calls a func recording a frame for the call on any error.
+    fn call_func(
+        &self,
+        def: &FuncDef,
+        args: Vec<Rc<Val>>,
+        call_site: &Position,
+    ) -> Result<Rc<Val>, Box<dyn Error>> {
+        self.nested_call(call_site, || {
+            def.eval(self, args, call_site)
+                .map_err(|e| self.call_frame(e, error::FrameKind::Func, call_site, &def.pos))
+        })
+    }
+
+    // Calls a func or native func.
+    fn call_val(
+        &self,
+        f: &Val,
+        args: Vec<Rc<Val>>,
+        call_site: &Position,
+    ) -> Result<Rc<Val>, Box<dyn Error>> {
+        match f {
+            &Val::Func(ref def) => self.call_func(def, args, call_site),
+            &Val::Native(ref native) => native.call(&args, call_site),
+            other => Err(error::BuildError::with_pos(
+                format!("{} is not a Function", other),
+                error::ErrorType::TypeFail,
+                call_site.clone(),
             )
-            .wrap_cause(Box::new(e))
             .to_boxed()),
         }
     }
 
+    // Where errors about what a func returned point. Native funcs have no
+    // definition so they point at the call.
+    fn func_pos<'b>(&self, f: &'b Val, call_site: &'b Position) -> &'b Position {
+        match f {
+            &Val::Func(ref def) => &def.pos,
+            _ => call_site,
+        }
+    }
+
     fn eval_import(&self, def: &ImportDef) -> Result<Rc<Val>, Box<dyn Error>> {
+        if def.path.fragment == native::NATIVE_IMPORT {
+            return Ok(Rc::new(self.natives.to_val()));
+        }
         // Look for a std file first.
         if def.path.fragment.starts_with("std/") {
             if self.std.contains_key(&def.path.fragment) {
@@ -393,15 +902,19 @@ impl<'a> FileBuilder<'a> {
                 // the cache below.
                 // Only parse the file once on import.
                 let path = PathBuf::from(&def.path.fragment);
+                self.touch_file(path.clone());
                 let maybe_asset = self.assets.borrow().get(&path)?;
                 let result = match maybe_asset {
                     Some(v) => v.clone(),
                     None => {
+                        self.record_import_site(&path, &def.pos);
                         let mut b = self.clone_builder();
-                        b.eval_input(
+                        if let Err(e) = b.eval_input(
                             OffsetStrIter::new(self.std.get(&def.path.fragment).unwrap())
                                 .with_src_file(&def.path.fragment),
-                        )?;
+                        ) {
+                            return Err(self.import_frame(e, &path, &def.pos));
+                        }
                         b.get_outputs_as_val()
                     }
                 };
@@ -419,8 +932,8 @@ impl<'a> FileBuilder<'a> {
         }
         let sep = format!("{}", std::path::MAIN_SEPARATOR);
         let raw_path = def.path.fragment.replace("/", &sep);
-        // Try a relative path first.
-        let normalized = match self.find_file(&raw_path, true) {
+        let candidates = self.file_candidates(&raw_path, true, &def.path.pos)?;
+        let normalized = match self.find_file(candidates) {
             Ok(p) => p,
             Err(e) => {
                 return Err(error::BuildError::with_pos(
@@ -432,6 +945,7 @@ impl<'a> FileBuilder<'a> {
                 .to_boxed());
             }
         };
+        self.check_sandbox(&normalized, &def.path.pos)?;
         if self.detect_import_cycle(normalized.to_string_lossy().as_ref()) {
             return Err(error::BuildError::with_pos(
                 format!(
@@ -451,6 +965,7 @@ impl<'a> FileBuilder<'a> {
         let result = match maybe_asset {
             Some(v) => v.clone(),
             None => {
+                self.record_import_site(&normalized, &def.pos);
                 let mut b = self.clone_builder();
                 b.scope
                     .import_stack
@@ -459,13 +974,7 @@ impl<'a> FileBuilder<'a> {
                 match b.build(&normalized) {
                     Ok(_) => b.get_outputs_as_val(),
                     Err(e) => {
-                        return Err(error::BuildError::with_pos(
-                            "Import failed",
-                            error::ErrorType::ImportError,
-                            def.pos.clone(),
-                        )
-                        .wrap_cause(e)
-                        .to_boxed());
+                        return Err(self.import_frame(e, &normalized, &def.pos));
                     }
                 }
             }
@@ -505,6 +1014,106 @@ impl<'a> FileBuilder<'a> {
                 e.insert(val.clone());
             }
         }
+        if let (Expression::Func(_), Val::Func(fdef)) = (&def.value, val.as_ref()) {
+            let mut fdef = fdef.clone();
+            // The copy in the set leaves out the funcs bound before it from
+            // its captured scope. They are found in the set anyway and
+            // keeping them would make the set own itself through them.
+            let mut shared = fdef.clone();
+            if let Some(ref mut scope) = shared.scope {
+                let funcs = self.rec_funcs.borrow();
+                scope
+                    .build_output
+                    .retain(|k, _| !funcs.contains_key(&k.val));
+            }
+            self.rec_funcs
+                .borrow_mut()
+                .insert(name.fragment.clone(), shared);
+            fdef.rec_funcs = Some(self.rec_funcs.clone());
+            let val = Rc::new(Val::Func(fdef));
+            self.scope
+                .build_output
+                .insert(PositionedItem::from(name), val.clone());
+            return Ok(val);
+        }
+        Ok(val)
+    }
+
+    // Fails unless the path names a file inside the directory outputs are
+    // written to.
+    fn check_output_path(path: &Token) -> Result<(), Box<dyn Error>> {
+        let mut names_file = false;
+        for part in Path::new(&path.fragment).components() {
+            match part {
+                Component::Normal(_) => names_file = true,
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                    return Err(error::BuildError::with_pos(
+                        format!(
+                            "Output path {} must be relative and can not contain ..",
+                            path.fragment
+                        ),
+                        error::ErrorType::OutputError,
+                        path.pos.clone(),
+                    )
+                    .to_boxed());
+                }
+            }
+        }
+        if !names_file {
+            return Err(error::BuildError::with_pos(
+                format!("Output path \"{}\" does not name a file", path.fragment),
+                error::ErrorType::OutputError,
+                path.pos.clone(),
+            )
+            .to_boxed());
+        }
+        Ok(())
+    }
+
+    fn eval_output(
+        &mut self,
+        pos: &Position,
+        typ: &Token,
+        path: &Option<Token>,
+        expr: &Expression,
+        scope: &Scope,
+    ) -> Result<Rc<Val>, Box<dyn Error>> {
+        if let Some(ref path) = path {
+            Self::check_output_path(path)?;
+        }
+        let path = path.as_ref().map(|p| p.fragment.clone());
+        // Outputs without a path are named after the file so two of them with
+        // the same converter would clobber each other.
+        for out in self.outputs.iter() {
+            let clobbers = match (&path, &out.path) {
+                (Some(p), Some(other)) => p == other,
+                (None, None) => out.typ == typ.fragment,
+                _ => false,
+            };
+            if clobbers {
+                let msg = match path {
+                    Some(ref p) => format!("There is already an output for the path {}", p),
+                    None => format!(
+                        "There is already an unnamed {} output. Give this one a path",
+                        typ.fragment
+                    ),
+                };
+                return Err(error::BuildError::with_pos(
+                    msg,
+                    error::ErrorType::OutputError,
+                    pos.clone(),
+                )
+                .to_boxed());
+            }
+        }
+        let val = self.eval_expr(expr, scope)?;
+        self.outputs.push(Output {
+            typ: typ.fragment.to_string(),
+            path,
+            val: val.clone(),
+            pos: pos.clone(),
+        });
         Ok(val)
     }
 
@@ -514,21 +1123,8 @@ impl<'a> FileBuilder<'a> {
             &Statement::Assert(ref expr) => self.eval_assert(&expr, &child_scope),
             &Statement::Let(ref def) => self.eval_let(def),
             &Statement::Expression(ref expr) => self.eval_expr(expr, &child_scope),
-            // Only one output can be used per file. Right now we enforce this by
-            // having a single builder per file.
-            &Statement::Output(ref pos, ref typ, ref expr) => {
-                if let None = self.out_lock {
-                    let val = self.eval_expr(expr, &child_scope)?;
-                    self.out_lock = Some((typ.fragment.to_string(), val.clone()));
-                    Ok(val)
-                } else {
-                    Err(error::BuildError::with_pos(
-                        format!("You can only have one output per file."),
-                        error::ErrorType::Unsupported,
-                        pos.clone(),
-                    )
-                    .to_boxed())
-                }
+            &Statement::Output(ref pos, ref typ, ref path, ref expr) => {
+                self.eval_output(pos, typ, path, expr, &child_scope)
             }
         }
     }
@@ -824,6 +1420,75 @@ impl<'a> FileBuilder<'a> {
         .to_boxed())
     }
 
+    // The top level bindings of each std library file paired with the
+    // import path of the file sorted by path.
+    fn std_exports(&self) -> Vec<(String, String)> {
+        let mut libs: Vec<(&String, &&'static str)> = self.std.iter().collect();
+        libs.sort();
+        let mut exports = Vec::new();
+        for (path, src) in libs {
+            // We only need the names so scanning the tokens is enough and is
+            // a lot cheaper than a full parse.
+            let toks = match tokenize(OffsetStrIter::new(src), None) {
+                Ok(toks) => toks,
+                Err(_) => continue,
+            };
+            let mut depth = 0;
+            for (i, tok) in toks.iter().enumerate() {
+                match (&tok.typ, tok.fragment.as_str()) {
+                    (TokenType::PUNCT, "{") | (TokenType::PUNCT, "(") | (TokenType::PUNCT, "[") => {
+                        depth += 1
+                    }
+                    (TokenType::PUNCT, "}") | (TokenType::PUNCT, ")") | (TokenType::PUNCT, "]") => {
+                        depth -= 1
+                    }
+                    (TokenType::BAREWORD, "let") if depth == 0 => {
+                        if let Some(name) = toks.get(i + 1) {
+                            exports.push((name.fragment.clone(), path.clone()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        exports
+    }
+
+    // Builds the error for a failed lookup with a suggestion for what was
+    // probably meant. Bare symbols also get suggestions from the std library
+    // since they may just be missing an import.
+    fn no_such_binding(
+        &self,
+        name: &str,
+        pos: &Position,
+        scope: &Scope,
+        bare_symbol: bool,
+    ) -> Box<dyn Error> {
+        let candidates = scope.candidate_names();
+        let mut msg = format!("Unable to find binding {}", name);
+        if let Some(suggestion) = closest_match(name, candidates.iter().map(|n| n.as_str())) {
+            msg.push_str(&format!(". Did you mean `{}`?", suggestion));
+        } else if bare_symbol {
+            let exports = self.std_exports();
+            let found = exports
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(n, path)| (n.as_str(), path))
+                .or_else(|| {
+                    closest_match(name, exports.iter().map(|(n, _)| n.as_str()))
+                        .and_then(|n| exports.iter().find(|(e, _)| e == n))
+                        .map(|(n, path)| (n.as_str(), path))
+                });
+            if let Some((export, path)) = found {
+                msg.push_str(&format!(
+                    ". Did you mean `{}` from `import \"{}\"`?",
+                    export, path
+                ));
+            }
+        }
+        error::BuildError::with_pos(msg, error::ErrorType::NoSuchSymbol, pos.clone()).to_boxed()
+    }
+
     fn do_dot_lookup(&self, right: &Expression, scope: &Scope) -> Result<Rc<Val>, Box<dyn Error>> {
         let pos = right.pos().clone();
         let scope = scope.clone().use_curr_val();
@@ -831,22 +1496,16 @@ impl<'a> FileBuilder<'a> {
             Expression::Copy(_) => return self.eval_expr(right, &scope),
             Expression::Call(_) => return self.eval_expr(right, &scope),
             Expression::Simple(Value::Symbol(ref s)) => {
+                self.check_sandbox_env(&s.val, &scope, &pos)?;
                 scope
                     .lookup_sym(s, true)
-                    .ok_or(Box::new(error::BuildError::with_pos(
-                        format!("Unable to find binding {}", s.val,),
-                        error::ErrorType::NoSuchSymbol,
-                        pos,
-                    )))
+                    .ok_or_else(|| self.no_such_binding(&s.val, &pos, &scope, false))
             }
             Expression::Simple(Value::Str(ref s)) => {
+                self.check_sandbox_env(&s.val, &scope, &pos)?;
                 scope
                     .lookup_sym(s, false)
-                    .ok_or(Box::new(error::BuildError::with_pos(
-                        format!("Unable to find binding {}", s.val,),
-                        error::ErrorType::NoSuchSymbol,
-                        pos,
-                    )))
+                    .ok_or_else(|| self.no_such_binding(&s.val, &pos, &scope, false))
             }
             Expression::Simple(Value::Int(ref i)) => {
                 scope.lookup_idx(right.pos(), &Val::Int(i.val))
@@ -858,13 +1517,12 @@ impl<'a> FileBuilder<'a> {
                 let val = self.eval_expr(right, &eval_scope)?;
                 match val.as_ref() {
                     Val::Int(i) => scope.lookup_idx(right.pos(), &Val::Int(*i)),
-                    Val::Str(ref s) => scope
-                        .lookup_sym(&PositionedItem::new(s.clone(), pos.clone()), false)
-                        .ok_or(Box::new(error::BuildError::with_pos(
-                            format!("Unable to find binding {}", s,),
-                            error::ErrorType::NoSuchSymbol,
-                            pos,
-                        ))),
+                    Val::Str(ref s) => {
+                        self.check_sandbox_env(s, &scope, &pos)?;
+                        scope
+                            .lookup_sym(&PositionedItem::new(s.clone(), pos.clone()), false)
+                            .ok_or_else(|| self.no_such_binding(s, &pos, &scope, false))
+                    }
                     _ => Err(error::BuildError::with_pos(
                         format!("Invalid selector lookup {}", val.type_name(),),
                         error::ErrorType::NoSuchSymbol,
@@ -1068,7 +1726,7 @@ impl<'a> FileBuilder<'a> {
         let fields: Vec<(String, Rc<Val>)> = self
             .scope
             .build_output
-            .drain()
+            .drain(..)
             .map(|v| (v.0.val, v.1))
             .collect();
         Rc::new(Val::Tuple(fields))
@@ -1080,13 +1738,12 @@ impl<'a> FileBuilder<'a> {
         overrides: &Vec<(Token, Expression)>,
         scope: &Scope,
     ) -> Result<Rc<Val>, Box<dyn Error>> {
-        let mut m = HashMap::<String, (i32, Rc<Val>)>::new();
-        // loop through fields and build  up a hashmap
-        let mut count = 0;
+        // IndexMap keeps our fields in their original order and an override
+        // keeps the slot of the field it replaces.
+        let mut m = IndexMap::<String, Rc<Val>>::new();
         for &(ref key, ref val) in src_fields.iter() {
             if let Entry::Vacant(v) = m.entry(key.clone()) {
-                v.insert((count, val.clone()));
-                count += 1;
+                v.insert(val.clone());
             } else {
                 return Err(error::BuildError::new(
                     format!(
@@ -1105,23 +1762,22 @@ impl<'a> FileBuilder<'a> {
             match m.entry(key.fragment.clone()) {
                 // brand new field here.
                 Entry::Vacant(v) => {
-                    v.insert((count, expr_result));
-                    count += 1;
+                    v.insert(expr_result);
                 }
                 Entry::Occupied(mut v) => {
                     // overriding field here.
                     // Ensure that the new type matches the old type.
                     let src_val = v.get().clone();
-                    if src_val.1.type_equal(&expr_result)
-                        || src_val.1.is_empty()
+                    if src_val.type_equal(&expr_result)
+                        || src_val.is_empty()
                         || expr_result.is_empty()
                     {
-                        v.insert((src_val.0, expr_result));
+                        v.insert(expr_result);
                     } else {
                         return Err(error::BuildError::with_pos(
                             format!(
                                 "Expected type {} for field {} but got ({})",
-                                src_val.1.type_name(),
+                                src_val.type_name(),
                                 key.fragment,
                                 expr_result.type_name()
                             ),
@@ -1133,24 +1789,48 @@ impl<'a> FileBuilder<'a> {
                 }
             };
         }
-        let mut new_fields: Vec<(String, (i32, Rc<Val>))> = m.drain().collect();
-        // We want to maintain our order for the fields to make comparing tuples
-        // easier in later code. So we sort by the field order before constructing a new tuple.
-        new_fields.sort_by(|a, b| {
-            let ta = a.1.clone();
-            let tb = b.1.clone();
-            ta.0.cmp(&tb.0)
-        });
-        return Ok(Rc::new(Val::Tuple(
-            new_fields
+        return Ok(Rc::new(Val::Tuple(m.drain(..).collect())));
+    }
+
+    // Checks the parameters a module was copied with against their type
+    // annotations. Violations point at the overriding field or the copy.
+    fn check_module_params(
+        &self,
+        def: &CopyDef,
+        mod_def: &ModuleDef,
+        mod_args: &Rc<Val>,
+    ) -> Result<(), Box<dyn Error>> {
+        let fields = match mod_args.as_ref() {
+            Val::Tuple(ref fields) => fields,
+            _ => return Ok(()),
+        };
+        for (name, typ) in mod_def.arg_types.iter() {
+            let val = match fields.iter().find(|f| &f.0 == name) {
+                Some((_, val)) => val,
+                None => continue,
+            };
+            if val.is_type(&typ.fragment) == Some(true) {
+                continue;
+            }
+            let pos = def
+                .fields
                 .iter()
-                .map(|a| {
-                    let first = a.0.clone();
-                    let t = a.1.clone();
-                    (first, t.1)
-                })
-                .collect(),
-        )));
+                .find(|f| &f.0.fragment == name)
+                .map(|f| f.0.pos.clone())
+                .unwrap_or_else(|| def.pos.clone());
+            return Err(error::BuildError::with_pos(
+                format!(
+                    "Module parameter {} must be {} but got ({})",
+                    name,
+                    typ.fragment,
+                    val.type_name()
+                ),
+                error::ErrorType::TypeFail,
+                pos,
+            )
+            .to_boxed());
+        }
+        Ok(())
     }
 
     fn eval_module_copy(
@@ -1176,6 +1856,7 @@ impl<'a> FileBuilder<'a> {
                     Expression::Func(FuncDef {
                         scope: None,
                         argdefs: Vec::new(),
+                        arg_types: BTreeMap::new(),
                         fields: Box::new(Expression::Import(ImportDef {
                             pos: def.pos.clone(),
                             path: Token::new(
@@ -1184,6 +1865,7 @@ impl<'a> FileBuilder<'a> {
                                 def.pos.clone(),
                             ),
                         })),
+                        rec_funcs: None,
                         pos: def.pos.clone(),
                     }),
                 ));
@@ -1194,6 +1876,7 @@ impl<'a> FileBuilder<'a> {
             ));
             overrides.extend(def.fields.iter().cloned());
             let mod_args = self.copy_fields_from_base(src_fields, &overrides, &child_scope)?;
+            self.check_module_params(def, mod_def, &mod_args)?;
             // put our copied parameters tuple in our builder under the mod key.
             let mod_key = PositionedItem::new_with_pos(String::from("mod"), def.pos.clone());
             match b.scope.build_output.entry(mod_key) {
@@ -1215,16 +1898,24 @@ impl<'a> FileBuilder<'a> {
                 }
             }
             // 4. Evaluate all the statements using the builder.
-            b.eval_stmts(&mod_def.statements)?;
-            if let Some(ref expr) = mod_def.out_expr {
-                // 5. Produce the out expression in the context of the statements
-                // we evaluated previously.
-                return b.eval_expr(expr, &b.scope);
-            } else {
-                // 5. Take all of the bindings in the module and construct a new
-                //    tuple using them.
-                return Ok(b.get_outputs_as_val());
-            }
+            return self.nested_call(&def.pos, || {
+                let result = match b.eval_stmts(&mod_def.statements) {
+                    Ok(_) => match mod_def.out_expr {
+                        // 5. Produce the out expression in the context of the statements
+                        // we evaluated previously.
+                        Some(ref expr) => b.eval_expr(expr, &b.scope),
+                        // 5. Take all of the bindings in the module and construct a new
+                        //    tuple using them.
+                        None => Ok(b.get_outputs_as_val()),
+                    },
+                    Err(e) => Err(e),
+                };
+                // Errors inside the module point at the module definition so we
+                // record where it was copied from as well.
+                result.map_err(|e| {
+                    self.call_frame(e, error::FrameKind::Module, &def.pos, &mod_def.pos)
+                })
+            });
         } else {
             return Err(error::BuildError::with_pos(
                 format!(
@@ -1284,21 +1975,13 @@ impl<'a> FileBuilder<'a> {
         let args = &def.arglist;
         let v = self.eval_value(&def.funcref, scope)?;
         let call_pos = def.pos.clone();
-        if let &Val::Func(ref def) = v.deref() {
+        if v.is_func() {
             // Congratulations this is actually a function.
             let mut argvals: Vec<Rc<Val>> = Vec::new();
             for arg in args.iter() {
                 argvals.push(self.eval_expr(arg, scope)?);
             }
-            return match def.eval(self, argvals) {
-                Ok(v) => Ok(v),
-                Err(e) => Err(error::BuildError::with_pos(
-                    format!("Func evaluation failed\nCaused by:\n\t{}", e),
-                    error::ErrorType::TypeFail,
-                    call_pos,
-                )
-                .to_boxed()),
-            };
+            return self.call_val(v.deref(), argvals, &call_pos);
         }
         Err(error::BuildError::with_pos(
             // We should pretty print the selectors here.
@@ -1405,13 +2088,14 @@ impl<'a> FileBuilder<'a> {
     fn eval_functional_list_processing(
         &self,
         elems: &Vec<Rc<Val>>,
-        def: &FuncDef,
+        def: &Val,
         typ: ProcessingOpType,
+        call_site: &Position,
     ) -> Result<Rc<Val>, Box<dyn Error>> {
         let mut out = Vec::new();
         for item in elems.iter() {
             let argvals = vec![item.clone()];
-            let val = def.eval(self, argvals)?;
+            let val = self.call_val(def, argvals, call_site)?;
             match typ {
                 ProcessingOpType::Map => {
                     out.push(val.clone());
@@ -1434,13 +2118,14 @@ impl<'a> FileBuilder<'a> {
     fn eval_functional_tuple_processing(
         &self,
         fs: &Vec<(String, Rc<Val>)>,
-        def: &FuncDef,
+        def: &Val,
         typ: ProcessingOpType,
+        call_site: &Position,
     ) -> Result<Rc<Val>, Box<dyn Error>> {
         let mut out = Vec::new();
         for &(ref name, ref val) in fs {
             let argvals = vec![Rc::new(Val::Str(name.clone())), val.clone()];
-            let result = def.eval(self, argvals)?;
+            let result = self.call_val(def, argvals, call_site)?;
             match typ {
                 ProcessingOpType::Map => {
                     if let &Val::List(ref fs) = result.as_ref() {
@@ -1456,7 +2141,7 @@ impl<'a> FileBuilder<'a> {
                                         fs[0].type_name()
                                     ),
                                     error::ErrorType::TypeFail,
-                                    def.pos.clone(),
+                                    self.func_pos(def, call_site).clone(),
                                 ).to_boxed());
                             };
                             out.push((new_name, fs[1].clone()));
@@ -1467,7 +2152,7 @@ impl<'a> FileBuilder<'a> {
                                     fs.len()
                                 ),
                                 error::ErrorType::TypeFail,
-                                def.pos.clone(),
+                                self.func_pos(def, call_site).clone(),
                             ).to_boxed());
                         }
                     } else {
@@ -1477,7 +2162,7 @@ impl<'a> FileBuilder<'a> {
                                 result.type_name()
                             ),
                             error::ErrorType::TypeFail,
-                            def.pos.clone(),
+                            self.func_pos(def, call_site).clone(),
                         )
                         .to_boxed());
                     }
@@ -1501,36 +2186,34 @@ impl<'a> FileBuilder<'a> {
         let maybe_target = self.eval_expr(&def.target, scope)?;
         let mut acc = self.eval_expr(&def.acc, scope)?;
         let maybe_mac = self.eval_expr(&def.func, scope)?;
-        let funcdef = match maybe_mac.as_ref() {
-            &Val::Func(ref funcdef) => funcdef,
-            _ => {
-                return Err(error::BuildError::with_pos(
-                    format!("Expected func but got {:?}", def.func),
-                    error::ErrorType::TypeFail,
-                    def.pos.clone(),
-                )
-                .to_boxed());
-            }
-        };
+        if !maybe_mac.is_func() {
+            return Err(error::BuildError::with_pos(
+                format!("Expected func but got {:?}", def.func),
+                error::ErrorType::TypeFail,
+                def.pos.clone(),
+            )
+            .to_boxed());
+        }
+        let funcdef = maybe_mac.as_ref();
         match maybe_target.as_ref() {
             &Val::List(ref elems) => {
                 for item in elems.iter() {
                     let argvals = vec![acc.clone(), item.clone()];
-                    let result = funcdef.eval(self, argvals)?;
+                    let result = self.call_val(funcdef, argvals, &def.pos)?;
                     acc = result;
                 }
             }
             &Val::Tuple(ref fs) => {
                 for &(ref name, ref val) in fs.iter() {
                     let argvals = vec![acc.clone(), Rc::new(Val::Str(name.clone())), val.clone()];
-                    let result = funcdef.eval(self, argvals)?;
+                    let result = self.call_val(funcdef, argvals, &def.pos)?;
                     acc = result;
                 }
             }
             &Val::Str(ref s) => {
                 for gc in s.graphemes(true) {
                     let argvals = vec![acc.clone(), Rc::new(Val::Str(gc.to_string()))];
-                    let result = funcdef.eval(self, argvals)?;
+                    let result = self.call_val(funcdef, argvals, &def.pos)?;
                     acc = result;
                 }
             }
@@ -1552,13 +2235,14 @@ impl<'a> FileBuilder<'a> {
     fn eval_functional_string_processing(
         &self,
         s: &str,
-        def: &FuncDef,
+        def: &Val,
         typ: ProcessingOpType,
+        call_site: &Position,
     ) -> Result<Rc<Val>, Box<dyn Error>> {
         let mut result = String::new();
         for gc in s.graphemes(true) {
             let arg = Rc::new(Val::Str(gc.to_string()));
-            let out = def.eval(self, vec![arg])?;
+            let out = self.call_val(def, vec![arg], call_site)?;
             match typ {
                 ProcessingOpType::Filter => {
                     match out.as_ref() {
@@ -1577,7 +2261,7 @@ impl<'a> FileBuilder<'a> {
                                     out.type_name()
                                 ),
                                 error::ErrorType::TypeFail,
-                                def.pos.clone(),
+                                self.func_pos(def, call_site).clone(),
                             )
                             .to_boxed());
                         }
@@ -1591,7 +2275,7 @@ impl<'a> FileBuilder<'a> {
                         return Err(error::BuildError::with_pos(
                             format!("Expected string map return but got ({})", out.type_name()),
                             error::ErrorType::TypeFail,
-                            def.pos.clone(),
+                            self.func_pos(def, call_site).clone(),
                         )
                         .to_boxed());
                     }
@@ -1609,21 +2293,21 @@ impl<'a> FileBuilder<'a> {
     ) -> Result<Rc<Val>, Box<dyn Error>> {
         let maybe_target = self.eval_expr(&def.target, scope)?;
         let maybe_mac = self.eval_expr(&def.func, scope)?;
-        let macdef = match maybe_mac.as_ref() {
-            &Val::Func(ref macdef) => macdef,
-            _ => {
-                return Err(error::BuildError::with_pos(
-                    format!("Expected func but got {:?}", def.func),
-                    error::ErrorType::TypeFail,
-                    def.pos.clone(),
-                )
-                .to_boxed());
-            }
-        };
+        if !maybe_mac.is_func() {
+            return Err(error::BuildError::with_pos(
+                format!("Expected func but got {:?}", def.func),
+                error::ErrorType::TypeFail,
+                def.pos.clone(),
+            )
+            .to_boxed());
+        }
+        let macdef = maybe_mac.as_ref();
         return match maybe_target.as_ref() {
-            &Val::List(ref elems) => self.eval_functional_list_processing(elems, macdef, typ),
-            &Val::Tuple(ref fs) => self.eval_functional_tuple_processing(fs, macdef, typ),
-            &Val::Str(ref s) => self.eval_functional_string_processing(s, macdef, typ),
+            &Val::List(ref elems) => {
+                self.eval_functional_list_processing(elems, macdef, typ, &def.pos)
+            }
+            &Val::Tuple(ref fs) => self.eval_functional_tuple_processing(fs, macdef, typ, &def.pos),
+            &Val::Str(ref s) => self.eval_functional_string_processing(s, macdef, typ, &def.pos),
             other => Err(error::BuildError::with_pos(
                 format!(
                     "Expected List or Tuple as target but got {:?}",
@@ -1636,7 +2320,23 @@ impl<'a> FileBuilder<'a> {
         };
     }
 
-    fn record_assert_result(&mut self, msg: &str, is_success: bool) {
+    fn record_assert_result(
+        &mut self,
+        pos: &Position,
+        msg: &str,
+        is_success: bool,
+        error: Option<String>,
+    ) {
+        let desc = match error {
+            Some(_) => "CompileError".to_string(),
+            None => msg.trim_end().to_string(),
+        };
+        self.assert_collector.results.push(AssertResult {
+            pos: pos.clone(),
+            desc,
+            success: is_success,
+            error,
+        });
         if !is_success {
             let msg = format!("{} - NOT OK: {}\n", self.assert_collector.counter, msg);
             self.assert_collector.summary.push_str(&msg);
@@ -1659,7 +2359,7 @@ impl<'a> FileBuilder<'a> {
             Err(e) => {
                 // failure!
                 let msg = format!("CompileError: {}\n", e);
-                self.record_assert_result(&msg, false);
+                self.record_assert_result(expr.pos(), &msg, false, Some(e.to_string()));
                 return Ok(Rc::new(Val::Empty));
             }
         };
@@ -1674,7 +2374,7 @@ impl<'a> FileBuilder<'a> {
                                     "TYPE FAIL - Expected Boolean field ok in tuple {}, line: {}, column: {}",
                                     ok.as_ref(), expr.pos().line, expr.pos().column
                                 );
-                            self.record_assert_result(&msg, false);
+                            self.record_assert_result(expr.pos(), &msg, false, None);
                             return Ok(Rc::new(Val::Empty));
                         }
                     },
@@ -1683,7 +2383,7 @@ impl<'a> FileBuilder<'a> {
                             "TYPE FAIL - Expected Boolean field ok in tuple {}, line: {}, column: {}",
                             ok.as_ref(), expr.pos().line, expr.pos().column
                         );
-                        self.record_assert_result(&msg, false);
+                        self.record_assert_result(expr.pos(), &msg, false, None);
                         return Ok(Rc::new(Val::Empty));
                     }
                 };
@@ -1695,7 +2395,7 @@ impl<'a> FileBuilder<'a> {
                                     "TYPE FAIL - Expected String field desc in tuple {} line: {}, column: {}",
                                     ok, expr.pos().line, expr.pos().column
                                 );
-                            self.record_assert_result(&msg, false);
+                            self.record_assert_result(expr.pos(), &msg, false, None);
                             return Ok(Rc::new(Val::Empty));
                         }
                     },
@@ -1704,11 +2404,11 @@ impl<'a> FileBuilder<'a> {
                             "TYPE FAIL - Expected String field desc in tuple {} line: {}, column: {}\n",
                             ok, expr.pos().line, expr.pos().column
                         );
-                        self.record_assert_result(&msg, false);
+                        self.record_assert_result(expr.pos(), &msg, false, None);
                         return Ok(Rc::new(Val::Empty));
                     }
                 };
-                self.record_assert_result(&desc, ok_field);
+                self.record_assert_result(expr.pos(), &desc, ok_field, None);
             }
             &Val::Empty
             | &Val::Boolean(_)
@@ -1718,13 +2418,14 @@ impl<'a> FileBuilder<'a> {
             | &Val::Str(_)
             | &Val::List(_)
             | &Val::Func(_)
+            | &Val::Native(_)
             | &Val::Module(_) => {
                 // record an assertion type-failure result.
                 let msg = format!(
                     "TYPE FAIL - Expected tuple with ok and desc fields got {} at line: {} column: {}\n",
                     ok, expr.pos().line, expr.pos().column
                 );
-                self.record_assert_result(&msg, false);
+                self.record_assert_result(expr.pos(), &msg, false, None);
                 return Ok(Rc::new(Val::Empty));
             }
         }
@@ -1747,7 +2448,8 @@ impl<'a> FileBuilder<'a> {
     fn get_file_as_string(&self, pos: &Position, path: &str) -> Result<String, Box<dyn Error>> {
         let sep = format!("{}", std::path::MAIN_SEPARATOR);
         let raw_path = path.replace("/", &sep);
-        let normalized = match self.find_file(raw_path, false) {
+        let candidates = self.file_candidates(&raw_path, false, pos)?;
+        let normalized = match self.find_file(candidates) {
             Ok(p) => p,
             Err(e) => {
                 return Err(error::BuildError::with_pos(
@@ -1758,6 +2460,7 @@ impl<'a> FileBuilder<'a> {
                 .to_boxed());
             }
         };
+        self.check_sandbox(&normalized, pos)?;
         let mut f = self.open_file(pos, normalized)?;
         let mut contents = String::new();
         f.read_to_string(&mut contents)?;
@@ -1873,7 +2576,31 @@ impl<'a> FileBuilder<'a> {
             }
         };
 
-        let vec = (start..end + 1)
+        if step <= 0 {
+            return Err(error::BuildError::with_pos(
+                format!("Range step must be greater than 0 but got {}", step),
+                error::ErrorType::TypeFail,
+                def.pos.clone(),
+            )
+            .to_boxed());
+        }
+        if let Some(max) = self.limits.max_range {
+            let len = if end < start {
+                0
+            } else {
+                (end as i128 - start as i128) / step as i128 + 1
+            };
+            if len > max as i128 {
+                return Err(limit_error(
+                    format!(
+                        "Range of {} elements is larger than the maximum of {}",
+                        len, max
+                    ),
+                    &def.pos,
+                ));
+            }
+        }
+        let vec = (start..=end)
             .step_by(step as usize)
             .map(|i| Rc::new(Val::Int(i)))
             .collect();
@@ -1898,19 +2625,11 @@ impl<'a> FileBuilder<'a> {
             }
         };
         let val = self.eval_expr(def.left.as_ref(), scope)?;
-        let result = match typ.as_str() {
-            "str" => val.is_str(),
-            "bool" => val.is_bool(),
-            "null" => val.is_empty(),
-            "int" => val.is_int(),
-            "float" => val.is_float(),
-            "tuple" => val.is_tuple(),
-            "list" => val.is_list(),
-            "func" => val.is_func(),
-            "module" => val.is_module(),
-            other => {
+        let result = match val.is_type(&typ) {
+            Some(result) => result,
+            None => {
                 return Err(error::BuildError::with_pos(
-                    format!("Expected valid type name but got ({})", other),
+                    format!("Expected valid type name but got ({})", typ),
                     error::ErrorType::TypeFail,
                     def.right.pos().clone(),
                 )
@@ -1923,6 +2642,13 @@ impl<'a> FileBuilder<'a> {
     // Evals a single Expression in the context of a running Builder.
     // It does not mutate the builders collected state at all.
     pub fn eval_expr(&self, expr: &Expression, scope: &Scope) -> Result<Rc<Val>, Box<dyn Error>> {
+        self.count_step(expr.pos())?;
+        let val = self.do_eval_expr(expr, scope)?;
+        self.check_size(&val, expr.pos())?;
+        Ok(val)
+    }
+
+    fn do_eval_expr(&self, expr: &Expression, scope: &Scope) -> Result<Rc<Val>, Box<dyn Error>> {
         match expr {
             &Expression::Simple(ref val) => self.eval_value(val, scope),
             &Expression::Binary(ref def) => self.eval_binary(def, scope),
@@ -0,0 +1,487 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Funcs implemented in Rust that ucg code can call.
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::Val;
+use crate::ast::Position;
+use crate::error;
+
+/// The path ucg code imports the registered native funcs from.
+pub const NATIVE_IMPORT: &str = "std/native";
+
+/// The result of calling a native func.
+pub type NativeResult = Result<Rc<Val>, Box<dyn Error>>;
+
+type NativeFn = dyn Fn(&[Rc<Val>], &Position) -> NativeResult;
+
+/// A func implemented in Rust.
+///
+/// It is called with the evaluated arguments and the position of the call
+/// to report errors at.
+#[derive(Clone)]
+pub struct NativeFunc {
+    pub name: String,
+    f: Rc<NativeFn>,
+}
+
+impl NativeFunc {
+    pub fn new<S, F>(name: S, f: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(&[Rc<Val>], &Position) -> NativeResult + 'static,
+    {
+        NativeFunc {
+            name: name.into(),
+            f: Rc::new(f),
+        }
+    }
+
+    pub fn call(&self, args: &[Rc<Val>], pos: &Position) -> NativeResult {
+        (self.f)(args, pos)
+    }
+}
+
+impl fmt::Debug for NativeFunc {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "NativeFunc({})", self.name)
+    }
+}
+
+impl PartialEq for NativeFunc {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Rc::ptr_eq(&self.f, &other.f)
+    }
+}
+
+/// The native funcs available to a build.
+#[derive(Clone, Debug, Default)]
+pub struct NativeRegistry {
+    funcs: BTreeMap<String, NativeFunc>,
+    // Shared with the builtin funcs that build strings so they can fail
+    // before allocating something larger than the build allows.
+    max_size: Rc<Cell<Option<usize>>>,
+}
+
+impl NativeRegistry {
+    /// Creates a NativeRegistry with no funcs in it.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a NativeRegistry with the builtin funcs.
+    ///
+    /// * len
+    /// * chars
+    /// * to_upper
+    /// * to_lower
+    /// * trim
+    /// * replace
+    /// * starts_with
+    /// * ends_with
+    /// * contains
+    /// * split
+    /// * join
+    /// * pad_left
+    /// * pad_right
+    /// * repeat
+    pub fn make_registry() -> Self {
+        let mut registry = Self::new();
+        let max_size = registry.max_size.clone();
+        registry.register("len", len);
+        registry.register("chars", chars);
+        registry.register("to_upper", |args: &[Rc<Val>], pos: &Position| {
+            expect_args("to_upper", args, 1, pos)?;
+            Ok(str_val(
+                expect_str("to_upper", &args[0], pos)?.to_uppercase(),
+            ))
+        });
+        registry.register("to_lower", |args: &[Rc<Val>], pos: &Position| {
+            expect_args("to_lower", args, 1, pos)?;
+            Ok(str_val(
+                expect_str("to_lower", &args[0], pos)?.to_lowercase(),
+            ))
+        });
+        registry.register("trim", |args: &[Rc<Val>], pos: &Position| {
+            expect_args("trim", args, 1, pos)?;
+            Ok(str_val(expect_str("trim", &args[0], pos)?.trim()))
+        });
+        let max = max_size.clone();
+        registry.register("replace", move |args: &[Rc<Val>], pos: &Position| {
+            replace(args, pos, max.get())
+        });
+        registry.register("starts_with", |args: &[Rc<Val>], pos: &Position| {
+            let (s, prefix) = str_pair("starts_with", args, pos)?;
+            Ok(Rc::new(Val::Boolean(s.starts_with(prefix))))
+        });
+        registry.register("ends_with", |args: &[Rc<Val>], pos: &Position| {
+            let (s, suffix) = str_pair("ends_with", args, pos)?;
+            Ok(Rc::new(Val::Boolean(s.ends_with(suffix))))
+        });
+        registry.register("contains", |args: &[Rc<Val>], pos: &Position| {
+            let (s, sub) = str_pair("contains", args, pos)?;
+            Ok(Rc::new(Val::Boolean(s.contains(sub))))
+        });
+        registry.register("split", split);
+        registry.register("join", join);
+        let max = max_size.clone();
+        registry.register("pad_left", move |args: &[Rc<Val>], pos: &Position| {
+            pad("pad_left", args, pos, true, max.get())
+        });
+        let max = max_size.clone();
+        registry.register("pad_right", move |args: &[Rc<Val>], pos: &Position| {
+            pad("pad_right", args, pos, false, max.get())
+        });
+        registry.register("repeat", move |args: &[Rc<Val>], pos: &Position| {
+            repeat(args, pos, max_size.get())
+        });
+        registry
+    }
+
+    /// Sets how many bytes a string built by the builtin funcs may hold.
+    pub fn set_max_size(&self, to: Option<usize>) {
+        self.max_size.set(to);
+    }
+
+    /// Registers a func under name replacing any func already registered
+    /// with that name.
+    pub fn register<S, F>(&mut self, name: S, f: F)
+    where
+        S: Into<String>,
+        F: Fn(&[Rc<Val>], &Position) -> NativeResult + 'static,
+    {
+        let name = name.into();
+        self.funcs
+            .insert(name.clone(), NativeFunc::new(name.clone(), f));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NativeFunc> {
+        self.funcs.get(name)
+    }
+
+    /// Returns the funcs as the tuple ucg code imports.
+    pub fn to_val(&self) -> Val {
+        Val::Tuple(
+            self.funcs
+                .iter()
+                .map(|(name, f)| (name.clone(), Rc::new(Val::Native(f.clone()))))
+                .collect(),
+        )
+    }
+}
+
+/// Fails unless there are exactly count args.
+pub fn expect_args(
+    name: &str,
+    args: &[Rc<Val>],
+    count: usize,
+    pos: &Position,
+) -> Result<(), Box<dyn Error>> {
+    if args.len() != count {
+        return Err(error::BuildError::with_pos(
+            format!(
+                "Func {} expects {} arguments but got {}",
+                name,
+                count,
+                args.len()
+            ),
+            error::ErrorType::BadArgLen,
+            pos.clone(),
+        )
+        .to_boxed());
+    }
+    Ok(())
+}
+
+/// Returns the string arg or fails.
+pub fn expect_str<'a>(name: &str, arg: &'a Val, pos: &Position) -> Result<&'a str, Box<dyn Error>> {
+    match arg {
+        Val::Str(s) => Ok(s),
+        other => Err(error::BuildError::with_pos(
+            format!("Func {} expected a string but got ({})", name, other),
+            error::ErrorType::TypeFail,
+            pos.clone(),
+        )
+        .to_boxed()),
+    }
+}
+
+/// Returns the integer arg or fails.
+pub fn expect_int(name: &str, arg: &Val, pos: &Position) -> Result<i64, Box<dyn Error>> {
+    match arg {
+        Val::Int(i) => Ok(*i),
+        other => Err(error::BuildError::with_pos(
+            format!("Func {} expected an integer but got ({})", name, other),
+            error::ErrorType::TypeFail,
+            pos.clone(),
+        )
+        .to_boxed()),
+    }
+}
+
+fn str_val<S: Into<String>>(s: S) -> Rc<Val> {
+    Rc::new(Val::Str(s.into()))
+}
+
+fn str_pair<'a>(
+    name: &str,
+    args: &'a [Rc<Val>],
+    pos: &Position,
+) -> Result<(&'a str, &'a str), Box<dyn Error>> {
+    expect_args(name, args, 2, pos)?;
+    Ok((
+        expect_str(name, &args[0], pos)?,
+        expect_str(name, &args[1], pos)?,
+    ))
+}
+
+// The number of elements in a list, fields in a tuple or characters in a
+// string.
+fn len(args: &[Rc<Val>], pos: &Position) -> NativeResult {
+    expect_args("len", args, 1, pos)?;
+    let len = match args[0].as_ref() {
+        Val::List(elems) => elems.len(),
+        Val::Tuple(fs) => fs.len(),
+        Val::Str(s) => s.graphemes(true).count(),
+        other => {
+            return Err(error::BuildError::with_pos(
+                format!(
+                    "Func len expected a list, tuple or string but got ({})",
+                    other
+                ),
+                error::ErrorType::TypeFail,
+                pos.clone(),
+            )
+            .to_boxed());
+        }
+    };
+    Ok(Rc::new(Val::Int(len as i64)))
+}
+
+// The characters of a string as a list of strings.
+fn chars(args: &[Rc<Val>], pos: &Position) -> NativeResult {
+    expect_args("chars", args, 1, pos)?;
+    let s = expect_str("chars", &args[0], pos)?;
+    Ok(Rc::new(Val::List(
+        s.graphemes(true)
+            .map(|c| Rc::new(Val::Str(c.to_string())))
+            .collect(),
+    )))
+}
+
+// Replaces every occurrence of a string with another.
+fn replace(args: &[Rc<Val>], pos: &Position, max_size: Option<usize>) -> NativeResult {
+    expect_args("replace", args, 3, pos)?;
+    let s = expect_str("replace", &args[0], pos)?;
+    let from = expect_str("replace", &args[1], pos)?;
+    let to = expect_str("replace", &args[2], pos)?;
+    if from.is_empty() {
+        return Err(error::BuildError::with_pos(
+            "Func replace can not replace an empty string",
+            error::ErrorType::TypeFail,
+            pos.clone(),
+        )
+        .to_boxed());
+    }
+    let count = s.matches(from).count();
+    let size = if to.len() > from.len() {
+        count
+            .checked_mul(to.len() - from.len())
+            .and_then(|grow| grow.checked_add(s.len()))
+    } else {
+        Some(s.len() - count * (from.len() - to.len()))
+    };
+    let mut out = reserve_str("replace", size, max_size, pos)?;
+    let mut last = 0;
+    for (start, _) in s.match_indices(from) {
+        out.push_str(&s[last..start]);
+        out.push_str(to);
+        last = start + from.len();
+    }
+    out.push_str(&s[last..]);
+    Ok(str_val(out))
+}
+
+// Splits a string on a separator into a list of strings.
+fn split(args: &[Rc<Val>], pos: &Position) -> NativeResult {
+    let (s, sep) = str_pair("split", args, pos)?;
+    if sep.is_empty() {
+        return Err(error::BuildError::with_pos(
+            "Func split can not split on an empty string. Use chars instead",
+            error::ErrorType::TypeFail,
+            pos.clone(),
+        )
+        .to_boxed());
+    }
+    Ok(Rc::new(Val::List(s.split(sep).map(str_val).collect())))
+}
+
+// Joins a list of strings, numbers or booleans with a separator.
+fn join(args: &[Rc<Val>], pos: &Position) -> NativeResult {
+    expect_args("join", args, 2, pos)?;
+    let sep = expect_str("join", &args[1], pos)?;
+    let elems = match args[0].as_ref() {
+        Val::List(elems) => elems,
+        other => {
+            return Err(error::BuildError::with_pos(
+                format!("Func join expected a list but got ({})", other),
+                error::ErrorType::TypeFail,
+                pos.clone(),
+            )
+            .to_boxed());
+        }
+    };
+    let mut parts = Vec::new();
+    for elem in elems.iter() {
+        parts.push(match elem.as_ref() {
+            Val::Str(s) => s.clone(),
+            Val::Int(_) | Val::Float(_) | Val::Boolean(_) => elem.to_string(),
+            other => {
+                return Err(error::BuildError::with_pos(
+                    format!(
+                        "Func join can only join strings, numbers and booleans but got ({})",
+                        other
+                    ),
+                    error::ErrorType::TypeFail,
+                    pos.clone(),
+                )
+                .to_boxed());
+            }
+        });
+    }
+    Ok(str_val(parts.join(sep)))
+}
+
+// Returns an empty string with room for size bytes. Fails if size is None
+// because it overflowed, if it's larger than the maximum size or if the
+// memory can't be allocated so a huge string is an error rather than an
+// abort.
+fn reserve_str(
+    name: &str,
+    size: Option<usize>,
+    max_size: Option<usize>,
+    pos: &Position,
+) -> Result<String, Box<dyn Error>> {
+    let too_large = || -> Box<dyn Error> {
+        error::BuildError::with_pos(
+            format!("Func {} would produce a string too large to hold", name),
+            error::ErrorType::LimitExceeded,
+            pos.clone(),
+        )
+        .to_boxed()
+    };
+    let size = match size {
+        Some(size) if size <= isize::MAX as usize => size,
+        _ => return Err(too_large()),
+    };
+    if let Some(max) = max_size {
+        if size > max {
+            return Err(error::BuildError::with_pos(
+                format!(
+                    "Func {} would produce a string of {} bytes which is larger than the maximum size of {}",
+                    name, size, max
+                ),
+                error::ErrorType::LimitExceeded,
+                pos.clone(),
+            )
+            .to_boxed());
+        }
+    }
+    let mut out = String::new();
+    out.try_reserve_exact(size).map_err(|_| too_large())?;
+    Ok(out)
+}
+
+// Pads a string with a character until it is width characters long.
+fn pad(
+    name: &str,
+    args: &[Rc<Val>],
+    pos: &Position,
+    left: bool,
+    max_size: Option<usize>,
+) -> NativeResult {
+    expect_args(name, args, 3, pos)?;
+    let s = expect_str(name, &args[0], pos)?;
+    let width = expect_int(name, &args[1], pos)?;
+    let fill = expect_str(name, &args[2], pos)?;
+    if fill.graphemes(true).count() != 1 {
+        return Err(error::BuildError::with_pos(
+            format!(
+                "Func {} expected a single character to pad with but got \"{}\"",
+                name, fill
+            ),
+            error::ErrorType::TypeFail,
+            pos.clone(),
+        )
+        .to_boxed());
+    }
+    let len = s.graphemes(true).count() as i64;
+    if width <= len {
+        return Ok(str_val(s));
+    }
+    let count = (width - len) as u64;
+    let size = usize::try_from(count)
+        .ok()
+        .and_then(|count| count.checked_mul(fill.len()))
+        .and_then(|size| size.checked_add(s.len()));
+    let mut out = reserve_str(name, size, max_size, pos)?;
+    if !left {
+        out.push_str(s);
+    }
+    for _ in 0..count {
+        out.push_str(fill);
+    }
+    if left {
+        out.push_str(s);
+    }
+    Ok(str_val(out))
+}
+
+// Repeats a string count times.
+fn repeat(args: &[Rc<Val>], pos: &Position, max_size: Option<usize>) -> NativeResult {
+    expect_args("repeat", args, 2, pos)?;
+    let s = expect_str("repeat", &args[0], pos)?;
+    let count = expect_int("repeat", &args[1], pos)?;
+    if count < 0 {
+        return Err(error::BuildError::with_pos(
+            format!(
+                "Func repeat expected a count of 0 or more but got {}",
+                count
+            ),
+            error::ErrorType::TypeFail,
+            pos.clone(),
+        )
+        .to_boxed());
+    }
+    // Nothing to repeat so skip the loop rather than run it count times.
+    if s.is_empty() || count == 0 {
+        return Ok(str_val(""));
+    }
+    let size = usize::try_from(count)
+        .ok()
+        .and_then(|count| count.checked_mul(s.len()));
+    let mut out = reserve_str("repeat", size, max_size, pos)?;
+    for _ in 0..count {
+        out.push_str(s);
+    }
+    Ok(str_val(out))
+}
@@ -0,0 +1,288 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Reporters for the results of the assertions in ucg test files.
+use std::error::Error;
+use std::io::Write;
+
+use serde_json;
+use xml::common::XmlVersion;
+use xml::writer::events::XmlEvent;
+use xml::EmitterConfig;
+
+use super::AssertResult;
+
+/// The assertion results for a single test file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileResults {
+    pub file: String,
+    pub results: Vec<AssertResult>,
+    /// The error if the file failed to build outside of an assertion.
+    pub error: Option<String>,
+}
+
+impl FileResults {
+    fn failures(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| !r.success && r.error.is_none())
+            .count()
+    }
+
+    fn errors(&self) -> usize {
+        let errors = self.results.iter().filter(|r| r.error.is_some()).count();
+        if self.error.is_some() {
+            errors + 1
+        } else {
+            errors
+        }
+    }
+
+    fn tests(&self) -> usize {
+        if self.error.is_some() {
+            self.results.len() + 1
+        } else {
+            self.results.len()
+        }
+    }
+}
+
+/// Writes the results as a JUnit XML document.
+pub fn write_junit<W: Write>(files: &[FileResults], w: W) -> Result<(), Box<dyn Error>> {
+    let mut writer = EmitterConfig::new().perform_indent(true).create_writer(w);
+    writer.write(XmlEvent::StartDocument {
+        version: XmlVersion::Version10,
+        encoding: Some("UTF-8"),
+        standalone: None,
+    })?;
+    let tests: usize = files.iter().map(|f| f.tests()).sum();
+    let failures: usize = files.iter().map(|f| f.failures()).sum();
+    let errors: usize = files.iter().map(|f| f.errors()).sum();
+    writer.write(
+        XmlEvent::start_element("testsuites")
+            .attr("tests", &tests.to_string())
+            .attr("failures", &failures.to_string())
+            .attr("errors", &errors.to_string()),
+    )?;
+    for f in files.iter() {
+        writer.write(
+            XmlEvent::start_element("testsuite")
+                .attr("name", &f.file)
+                .attr("tests", &f.tests().to_string())
+                .attr("failures", &f.failures().to_string())
+                .attr("errors", &f.errors().to_string()),
+        )?;
+        for r in f.results.iter() {
+            let line = r.pos.line.to_string();
+            writer.write(
+                XmlEvent::start_element("testcase")
+                    .attr("name", &r.desc)
+                    .attr("classname", &f.file)
+                    .attr("file", &f.file)
+                    .attr("line", &line),
+            )?;
+            if let Some(ref err) = r.error {
+                writer.write(XmlEvent::start_element("error").attr("message", err))?;
+                writer.write(XmlEvent::end_element())?;
+            } else if !r.success {
+                writer.write(XmlEvent::start_element("failure").attr("message", &r.desc))?;
+                writer.write(XmlEvent::end_element())?;
+            }
+            writer.write(XmlEvent::end_element())?;
+        }
+        if let Some(ref err) = f.error {
+            writer.write(
+                XmlEvent::start_element("testcase")
+                    .attr("name", "build")
+                    .attr("classname", &f.file)
+                    .attr("file", &f.file),
+            )?;
+            writer.write(XmlEvent::start_element("error").attr("message", err))?;
+            writer.write(XmlEvent::end_element())?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        writer.write(XmlEvent::end_element())?;
+    }
+    writer.write(XmlEvent::end_element())?;
+    writer.into_inner().write_all(b"\n")?;
+    Ok(())
+}
+
+fn tap_desc(desc: &str) -> String {
+    desc.replace('\n', " ").replace('#', "\\#")
+}
+
+fn write_tap_diagnostic<W: Write>(
+    w: &mut W,
+    file: &str,
+    pos: Option<(usize, usize)>,
+    message: &str,
+) -> Result<(), Box<dyn Error>> {
+    // NOTE(jwall): JSON strings are valid YAML scalars so we use them to
+    // avoid having to escape the YAML block by hand.
+    writeln!(w, "  ---")?;
+    writeln!(w, "  file: {}", serde_json::Value::from(file))?;
+    if let Some((line, column)) = pos {
+        writeln!(w, "  line: {}", line)?;
+        writeln!(w, "  column: {}", column)?;
+    }
+    writeln!(w, "  message: {}", serde_json::Value::from(message))?;
+    writeln!(w, "  ...")?;
+    Ok(())
+}
+
+/// Writes the results as a TAP version 13 stream.
+pub fn write_tap<W: Write>(files: &[FileResults], mut w: W) -> Result<(), Box<dyn Error>> {
+    let tests: usize = files.iter().map(|f| f.tests()).sum();
+    writeln!(w, "TAP version 13")?;
+    writeln!(w, "1..{}", tests)?;
+    let mut counter = 0;
+    for f in files.iter() {
+        for r in f.results.iter() {
+            counter += 1;
+            if r.success {
+                writeln!(w, "ok {} - {}", counter, tap_desc(&r.desc))?;
+            } else {
+                writeln!(w, "not ok {} - {}", counter, tap_desc(&r.desc))?;
+                let message = r.error.as_ref().unwrap_or(&r.desc);
+                write_tap_diagnostic(&mut w, &f.file, Some((r.pos.line, r.pos.column)), message)?;
+            }
+        }
+        if let Some(ref err) = f.error {
+            counter += 1;
+            writeln!(w, "not ok {} - {}", counter, tap_desc(&f.file))?;
+            write_tap_diagnostic(&mut w, &f.file, None, err)?;
+        }
+    }
+    Ok(())
+}
+
+fn optional_str(s: &Option<String>) -> serde_json::Value {
+    match s {
+        Some(ref s) => s.clone().into(),
+        None => serde_json::Value::Null,
+    }
+}
+
+/// Writes the results as a JSON list with one entry per file.
+pub fn write_json<W: Write>(files: &[FileResults], mut w: W) -> Result<(), Box<dyn Error>> {
+    let mut file_list = Vec::new();
+    for f in files.iter() {
+        let mut results = Vec::new();
+        for r in f.results.iter() {
+            let mut result = serde_json::Map::new();
+            result.insert("line".to_string(), r.pos.line.into());
+            result.insert("column".to_string(), r.pos.column.into());
+            result.insert("desc".to_string(), r.desc.clone().into());
+            result.insert("success".to_string(), r.success.into());
+            result.insert("error".to_string(), optional_str(&r.error));
+            results.push(serde_json::Value::Object(result));
+        }
+        let mut entry = serde_json::Map::new();
+        entry.insert("file".to_string(), f.file.clone().into());
+        entry.insert("error".to_string(), optional_str(&f.error));
+        entry.insert("results".to_string(), serde_json::Value::Array(results));
+        file_list.push(serde_json::Value::Object(entry));
+    }
+    serde_json::to_writer_pretty(&mut w, &serde_json::Value::Array(file_list))?;
+    writeln!(w)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ast::Position;
+
+    fn results() -> Vec<FileResults> {
+        vec![
+            FileResults {
+                file: "foo_test.ucg".to_string(),
+                results: vec![
+                    AssertResult {
+                        pos: Position::new(1, 8, 7),
+                        desc: "passes".to_string(),
+                        success: true,
+                        error: None,
+                    },
+                    AssertResult {
+                        pos: Position::new(2, 8, 30),
+                        desc: "fails <badly>".to_string(),
+                        success: false,
+                        error: None,
+                    },
+                    AssertResult {
+                        pos: Position::new(3, 8, 60),
+                        desc: "CompileError".to_string(),
+                        success: false,
+                        error: Some("NoSuchSymbol: nope".to_string()),
+                    },
+                ],
+                error: None,
+            },
+            FileResults {
+                file: "bar_test.ucg".to_string(),
+                results: Vec::new(),
+                error: Some("ParseError: bad".to_string()),
+            },
+        ]
+    }
+
+    fn render<F>(f: F) -> String
+    where
+        F: Fn(&[FileResults], &mut Vec<u8>) -> Result<(), Box<dyn Error>>,
+    {
+        let mut buffer: Vec<u8> = Vec::new();
+        f(&results(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn test_junit_report() {
+        let out = render(|r, w| write_junit(r, w));
+        assert!(out.contains("<testsuites tests=\"4\" failures=\"1\" errors=\"2\">"));
+        assert!(out.contains("<testcase name=\"passes\" classname=\"foo_test.ucg\" file=\"foo_test.ucg\" line=\"1\" />"));
+        assert!(out.contains("<failure message=\"fails &lt;badly&gt;\" />"));
+        assert!(out.contains("<error message=\"NoSuchSymbol: nope\" />"));
+        assert!(out.contains("<error message=\"ParseError: bad\" />"));
+    }
+
+    #[test]
+    fn test_tap_report() {
+        let out = render(|r, w| write_tap(r, w));
+        let lines: Vec<&str> = out.lines().filter(|l| !l.starts_with("  ")).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "TAP version 13",
+                "1..4",
+                "ok 1 - passes",
+                "not ok 2 - fails <badly>",
+                "not ok 3 - CompileError",
+                "not ok 4 - bar_test.ucg",
+            ]
+        );
+        assert!(out.contains("  message: \"NoSuchSymbol: nope\"\n"));
+    }
+
+    #[test]
+    fn test_json_report() {
+        let out = render(|r, w| write_json(r, w));
+        let val: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(val[0]["file"], "foo_test.ucg");
+        assert_eq!(val[0]["results"][1]["success"], false);
+        assert_eq!(val[0]["results"][2]["error"], "NoSuchSymbol: nope");
+        assert_eq!(val[1]["error"], "ParseError: bad");
+    }
+}
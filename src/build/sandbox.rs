@@ -0,0 +1,88 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Restrictions for building configs from untrusted sources.
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+
+/// What a sandboxed build may see of the outside world.
+///
+/// Only the allowed environment variables are visible through `env` and
+/// imports and includes may only read files under one of the roots.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    env: BTreeSet<String>,
+    roots: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// Constructs a sandbox that allows no environment variables and no
+    /// files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows the named environment variable.
+    pub fn with_env_var<S: Into<String>>(mut self, name: S) -> Self {
+        self.env.insert(name.into());
+        self
+    }
+
+    /// Allows reading the files under root.
+    pub fn with_root<P: Into<PathBuf>>(mut self, root: P) -> Self {
+        let root = root.into();
+        // Imported paths are canonicalized so the roots have to be as well.
+        self.roots.push(root.canonicalize().unwrap_or(root));
+        self
+    }
+
+    /// Returns whether the named environment variable is allowed.
+    pub fn allows_env(&self, name: &str) -> bool {
+        self.env.contains(name)
+    }
+
+    /// Returns the allowed variables out of vars.
+    pub fn filter_env(&self, vars: &[(String, String)]) -> Vec<(String, String)> {
+        vars.iter()
+            .filter(|(name, _)| self.allows_env(name))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns whether the canonicalized path is under one of the roots.
+    pub fn allows_path(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    /// Returns whether the path is under one of the roots once its `.` and
+    /// `..` components are resolved.
+    ///
+    /// Unlike allows_path this never touches the filesystem so a path can be
+    /// rejected before anything finds out whether it exists. Symlinks aren't
+    /// resolved so the canonicalized path still has to be checked with
+    /// allows_path.
+    pub fn may_allow_path(&self, path: &Path) -> bool {
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                c => normalized.push(c.as_os_str()),
+            }
+        }
+        self.allows_path(&normalized)
+    }
+}
@@ -1,12 +1,14 @@
 use std::clone::Clone;
-use std::collections::HashMap;
 use std::convert::AsRef;
 use std::convert::Into;
 use std::error::Error;
 use std::rc::Rc;
 
+use indexmap::IndexMap;
+
 use crate::ast::Position;
 use crate::ast::PositionedItem;
+use crate::ast::RecFuncs;
 use crate::build::ir::Val;
 use crate::error;
 
@@ -19,8 +21,63 @@ pub fn find_in_fieldlist(target: &str, fs: &Vec<(String, Rc<Val>)>) -> Option<Rc
     return None;
 }
 
+// The number of single character insertions, deletions, substitutions or
+// swaps of adjacent characters it takes to turn a into b.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Returns the candidate most likely to be a typo of name.
+///
+/// Candidates further than a third of the name's length away are not
+/// considered. Ties go to the earliest candidate.
+pub fn closest_match<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max_distance = std::cmp::max(1, name.chars().count() / 3);
+    let mut best: Option<(usize, &'a str)> = None;
+    for candidate in candidates {
+        if candidate == name {
+            continue;
+        }
+        let distance = edit_distance(name, candidate);
+        if distance > max_distance {
+            continue;
+        }
+        match best {
+            Some((d, _)) if d <= distance => {}
+            _ => best = Some((distance, candidate)),
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
 /// Defines a set of values in a parsed file.
-pub type ValueMap = HashMap<PositionedItem<String>, Rc<Val>>;
+///
+/// The bindings are kept in declaration order so that anything built from
+/// them (import results, module outputs) has a stable field order.
+pub type ValueMap = IndexMap<PositionedItem<String>, Rc<Val>>;
 
 /// Defines a scope for execution in ucg.
 ///
@@ -34,10 +91,14 @@ pub type ValueMap = HashMap<PositionedItem<String>, Rc<Val>>;
 pub struct Scope {
     pub import_stack: Vec<String>,
     pub env: Rc<Val>,
+    /// The values passed to the build. See `FileBuilder::set_args`.
+    pub args: Rc<Val>,
     pub curr_val: Option<Rc<Val>>,
     pub build_output: ValueMap,
     pub search_curr_val: bool,
     pub strict: bool,
+    /// The recursive funcs visible inside a func body. See `FuncDef::rec_funcs`.
+    pub rec_funcs: Option<RecFuncs>,
 }
 
 impl Scope {
@@ -46,12 +107,14 @@ impl Scope {
         Self {
             import_stack: Vec::new(),
             env: env,
+            args: Rc::new(Val::Tuple(Vec::new())),
             // CurrVal represents the currently processing value.
             // (eg: Tuple, List. left side of a dot selection.)
             curr_val: None,
-            build_output: HashMap::new(),
+            build_output: ValueMap::new(),
             search_curr_val: false,
             strict: false,
+            rec_funcs: None,
         }
     }
 
@@ -71,11 +134,13 @@ impl Scope {
         Self {
             import_stack: self.import_stack.clone(),
             env: self.env.clone(),
+            args: self.args.clone(),
             // Children start with no current val
             curr_val: None,
             build_output: self.build_output.clone(),
             search_curr_val: false,
             strict: self.strict,
+            rec_funcs: self.rec_funcs.clone(),
         }
     }
 
@@ -83,11 +148,13 @@ impl Scope {
         Self {
             import_stack: self.import_stack.clone(),
             env: self.env.clone(),
+            args: self.args.clone(),
             // Children start with no current val
             curr_val: None,
-            build_output: HashMap::new(),
+            build_output: ValueMap::new(),
             search_curr_val: false,
             strict: self.strict,
+            rec_funcs: None,
         }
     }
 
@@ -131,7 +198,9 @@ impl Scope {
     /// * `self` is always the current value. This symbol is only
     ///    valid when the current value is a tuple.
     /// * everything else is looked up in the currently accumulated build output
-    ///   for this execution context.
+    ///   for this execution context and then in the recursive funcs.
+    /// * `args` is the values passed to the build unless something else is
+    ///   bound to it.
     pub fn lookup_sym(&self, sym: &PositionedItem<String>, is_symbol: bool) -> Option<Rc<Val>> {
         if &sym.val == "env" && is_symbol {
             return Some(self.env.clone());
@@ -171,7 +240,8 @@ impl Scope {
                 | Val::Int(_)
                 | Val::Module(_)
                 | Val::Str(_)
-                | Val::Func(_) => {
+                | Val::Func(_)
+                | Val::Native(_) => {
                     // noop
                 }
             };
@@ -179,9 +249,42 @@ impl Scope {
         if self.build_output.contains_key(sym) {
             return Some(self.build_output[sym].clone());
         }
+        if is_symbol && !self.search_curr_val {
+            if let Some(ref funcs) = self.rec_funcs {
+                if let Some(def) = funcs.borrow().get(&sym.val) {
+                    let mut def = def.clone();
+                    def.rec_funcs = Some(funcs.clone());
+                    return Some(Rc::new(Val::Func(def)));
+                }
+            }
+            if &sym.val == "args" {
+                return Some(self.args.clone());
+            }
+        }
         None
     }
 
+    /// The names a failed lookup of a symbol could have meant.
+    ///
+    /// These are the fields of the current value when it is searched and
+    /// the bindings in the build output otherwise.
+    pub fn candidate_names(&self) -> Vec<String> {
+        if self.search_curr_val {
+            match self.curr_val.as_ref().map(|v| v.as_ref()) {
+                Some(Val::Tuple(ref fs)) => return fs.iter().map(|(n, _)| n.clone()).collect(),
+                Some(Val::Env(ref fs)) => return fs.iter().map(|(n, _)| n.clone()).collect(),
+                _ => {}
+            }
+        }
+        let mut names: Vec<String> = self.build_output.keys().map(|k| k.val.clone()).collect();
+        if let Some(ref funcs) = self.rec_funcs {
+            names.extend(funcs.borrow().keys().cloned());
+        }
+        names.push("env".to_string());
+        names.push("args".to_string());
+        names
+    }
+
     fn lookup_in_tuple(
         pos: &Position,
         field: &str,
@@ -190,8 +293,12 @@ impl Scope {
         if let Some(vv) = find_in_fieldlist(&field, fs) {
             Ok(vv)
         } else {
+            let suggestion = match closest_match(field, fs.iter().map(|(n, _)| n.as_str())) {
+                Some(name) => format!(" Did you mean `{}`?", name),
+                None => String::new(),
+            };
             Err(error::BuildError::with_pos(
-                format!("Unable to {} match element in tuple.", field,),
+                format!("Unable to {} match element in tuple.{}", field, suggestion),
                 error::ErrorType::NoSuchSymbol,
                 pos.clone(),
             )
@@ -12,8 +12,9 @@
 //  See the License for the specific language governing permissions and
 //  limitations under the License.
 use super::assets::MemoryCache;
-use super::{FileBuilder, SelectDef, Val};
+use super::{args, envfile, native, FileBuilder, Sandbox, SelectDef, Val};
 use crate::ast::*;
+use crate::error::BuildError;
 
 use std;
 use std::cell::RefCell;
@@ -211,3 +212,762 @@ fn test_select_expr_not_a_string() {
         b,
     );
 }
+
+#[test]
+fn test_build_tracks_touched_files() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    let file = std::env::current_dir()
+        .unwrap()
+        .join("integration_tests/import_test.ucg");
+    b.build(&file).unwrap();
+    let touched = b.touched_files();
+    assert!(touched.contains(&file));
+    assert!(touched.contains(
+        &std::env::current_dir()
+            .unwrap()
+            .join("integration_tests/libs/shared.ucg")
+            .canonicalize()
+            .unwrap()
+    ));
+    assert!(touched.contains(
+        &std::env::current_dir()
+            .unwrap()
+            .join("integration_tests/include_example.sh")
+            .canonicalize()
+            .unwrap()
+    ));
+}
+
+#[test]
+fn test_out_statement_with_path() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.eval_string("out json \"deploy/app.json\" {a = 1};")
+        .unwrap();
+    assert_eq!(b.outputs.len(), 1);
+    assert_eq!(b.outputs[0].typ, "json");
+    assert_eq!(b.outputs[0].path, Some("deploy/app.json".to_string()));
+}
+
+#[test]
+fn test_out_statement_path_stays_in_output_dir() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.eval_string("out json \"./deploy/app.json\" {a = 1};")
+        .unwrap();
+    for &(input, msg) in &[
+        (
+            "out json \"/etc/app.json\" {a = 1};",
+            "Output path /etc/app.json must be relative and can not contain ..",
+        ),
+        (
+            "out json \"deploy/../../app.json\" {a = 1};",
+            "Output path deploy/../../app.json must be relative and can not contain ..",
+        ),
+        (
+            "out json \".\" {a = 1};",
+            "Output path \".\" does not name a file",
+        ),
+    ] {
+        let err = b.eval_string(input).unwrap_err();
+        let err = err.downcast::<BuildError>().unwrap();
+        assert_eq!(err.err_type.to_string(), "OutputError");
+        assert_eq!(err.msg, msg);
+    }
+}
+
+#[test]
+fn test_out_statement_with_string_value() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.eval_string("out json \"foo\" + \"bar\";").unwrap();
+    assert_eq!(b.outputs[0].path, None);
+    assert_eq!(b.outputs[0].val, Rc::new(Val::Str("foobar".to_string())));
+}
+
+#[test]
+fn test_multiple_outputs() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.eval_string("out json {a = 1};\nout env {a = 1};\nout json \"other.json\" {b = 2};")
+        .unwrap();
+    let types: Vec<&str> = b.outputs.iter().map(|o| o.typ.as_str()).collect();
+    assert_eq!(types, vec!["json", "env", "json"]);
+}
+
+#[test]
+fn test_duplicate_output_paths_fail() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    let err = b
+        .eval_string("out json \"a.json\" {a = 1};\nout yaml \"a.json\" {b = 2};")
+        .unwrap_err();
+    let err = err.downcast::<BuildError>().unwrap();
+    assert_eq!(err.err_type.to_string(), "OutputError");
+    assert_eq!(err.msg, "There is already an output for the path a.json");
+    let err = b
+        .eval_string("out json {a = 1};\nout json {b = 2};")
+        .unwrap_err();
+    let err = err.downcast::<BuildError>().unwrap();
+    assert_eq!(err.err_type.to_string(), "OutputError");
+}
+
+#[test]
+fn test_json_diagnostic_for_parse_error() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    let err = b.eval_string("let x = {\n").unwrap_err();
+    let diag = crate::error::to_json_diagnostic(err.as_ref());
+    assert_eq!(diag["type"], "ParseError");
+    assert_eq!(diag["message"], "Unable to parse input");
+    assert_eq!(diag["line"], 1);
+    let causes = diag["causes"].as_array().unwrap();
+    assert!(!causes.is_empty());
+    assert_eq!(causes[0]["type"], "ParseError");
+    assert_eq!(causes[0]["line"], 1);
+    assert_eq!(causes[0]["column"], 9);
+}
+
+#[test]
+fn test_json_diagnostic_for_build_error() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    let err = b.eval_string("let x = 1;\nlet y = x + \"a\";").unwrap_err();
+    let diag = crate::error::to_json_diagnostic(err.as_ref());
+    assert_eq!(diag["type"], "TypeFail");
+    assert_eq!(diag["line"], 2);
+    assert_eq!(diag["column"], 13);
+    assert_eq!(diag["causes"].as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn test_json_diagnostic_has_stack_trace() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    let err = b
+        .eval_string(
+            "let f = func(a) => a + \"x\";\nlet mk = module{a=1, f=NULL} => (out) {\n    let out = mod.f(mod.a);\n};\nlet y = mk{a=2, f=f};",
+        )
+        .unwrap_err();
+    let diag = crate::error::to_json_diagnostic(err.as_ref());
+    assert_eq!(diag["line"], 1);
+    assert_eq!(diag["column"], 24);
+    let trace = diag["trace"].as_array().unwrap();
+    assert_eq!(trace.len(), 2);
+    assert_eq!(trace[0]["kind"], "func");
+    assert_eq!(trace[0]["definition"]["line"], 1);
+    assert_eq!(trace[0]["call_site"]["line"], 3);
+    assert_eq!(trace[1]["kind"], "module");
+    assert_eq!(trace[1]["definition"]["line"], 2);
+    assert_eq!(trace[1]["call_site"]["line"], 5);
+    assert!(trace[1]["imported_at"].is_null());
+}
+
+#[test]
+fn test_assert_results_are_structured() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.enable_validate_mode();
+    b.eval_string(
+        "assert {ok = true, desc = \"passes\"};\nassert {ok = false, desc = \"fails\"};\nassert {ok = nope, desc = \"broken\"};",
+    )
+    .unwrap();
+    let results = &b.assert_collector.results;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].desc, "passes");
+    assert!(results[0].success);
+    assert_eq!(results[1].desc, "fails");
+    assert!(!results[1].success);
+    assert!(results[1].error.is_none());
+    assert_eq!(results[2].pos.line, 3);
+    assert!(!results[2].success);
+    assert!(results[2].error.is_some());
+}
+
+#[test]
+fn test_render_with_source_shows_snippet() {
+    let file = std::env::current_dir()
+        .unwrap()
+        .join("integration_tests/types_test.ucg");
+    let src = std::fs::read_to_string(&file).unwrap();
+    let line = src.lines().next().unwrap();
+    let pos = Position::new(1, 3, 0).with_file(file.clone());
+    let err = crate::error::BuildError::with_pos(
+        "outer",
+        crate::error::ErrorType::TypeFail,
+        Position::new(1, 1, 0),
+    )
+    .wrap_cause(
+        crate::error::BuildError::with_pos("inner", crate::error::ErrorType::NoSuchSymbol, pos)
+            .to_boxed(),
+    );
+    let rendered = crate::error::render_with_source(&err);
+    let expected = format!(
+        "error[TypeFail]: outer\n  --> <eval>:1:1\ncaused by[NoSuchSymbol]: inner\n  --> {}:1:3\n   |\n 1 | {}\n   |   ^\n",
+        file.to_string_lossy(),
+        line
+    );
+    assert_eq!(rendered, expected);
+}
+
+#[test]
+fn test_render_with_source_shows_backtrace() {
+    let err = crate::error::with_frame(
+        crate::error::BuildError::with_pos(
+            "inner",
+            crate::error::ErrorType::TypeFail,
+            Position::new(1, 20, 19),
+        )
+        .to_boxed(),
+        crate::error::StackFrame {
+            kind: crate::error::FrameKind::Func,
+            call_site: Position::new(2, 9, 30),
+            definition: Position::new(1, 9, 8),
+            imported_at: Some(Position::new(1, 1, 0)),
+        },
+    );
+    let rendered = crate::error::render_with_source(err.as_ref());
+    assert_eq!(
+        rendered,
+        "error[TypeFail]: inner\n  --> <eval>:1:20\nstack backtrace:\n   0: func defined at <eval>:1:9\n      called at <eval>:2:9\n      imported at <eval>:1:1\n"
+    );
+}
+
+#[test]
+fn test_expressions_have_spans() {
+    let stmts = crate::parse::parse(
+        crate::iter::OffsetStrIter::new("let x = 1 + foo.bar;\nlet t = {\n  a = 1,\n};"),
+        None,
+    )
+    .unwrap();
+    let binary = match &stmts[0] {
+        Statement::Let(def) => &def.value,
+        _ => panic!("Expected a let statement"),
+    };
+    assert_eq!(binary.pos().column, 9);
+    assert_eq!(binary.pos().end.unwrap().column, 20);
+    let tuple = match &stmts[1] {
+        Statement::Let(def) => &def.value,
+        _ => panic!("Expected a let statement"),
+    };
+    let end = tuple.pos().end.unwrap();
+    assert_eq!((tuple.pos().line, end.line, end.column), (2, 4, 2));
+}
+
+#[test]
+fn test_parse_all_resyncs_after_errors() {
+    let errs = crate::parse::parse_all(
+        crate::iter::OffsetStrIter::new(
+            "let a = 1 +;\nlet b = {\n  c = ;\n};\nlet d = 1;\nlet e = [1 2];",
+        ),
+        None,
+    )
+    .unwrap_err();
+    let lines: Vec<usize> = errs.iter().map(|e| e.err.get_context().line).collect();
+    assert_eq!(lines, vec![1, 2, 6]);
+}
+
+#[test]
+fn test_keep_going_reports_independent_errors() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.set_keep_going(true);
+    let err = b
+        .eval_string("let a = 1 + \"x\";\nlet b = a + 1;\nlet c = nope;\nlet d = {a = 2}.a;\n")
+        .unwrap_err();
+    let errs = crate::error::individual_errors(err.as_ref());
+    // b depends on a so it is skipped rather than reported.
+    assert_eq!(errs.len(), 2);
+    assert!(format!("{}", errs[0]).contains("line: 1"));
+    assert!(format!("{}", errs[1]).contains("Unable to find binding nope"));
+    assert_eq!(b.get_out_by_name("d"), Some(Rc::new(Val::Int(2))));
+}
+
+#[test]
+fn test_recursion_limit() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.set_max_call_depth(25);
+    b.eval_string("let count = func(n) => select n == 0, {true = 0, false = 1 + count(n - 1)};\nlet a = count(24);\n")
+        .unwrap();
+    assert_eq!(b.get_out_by_name("a"), Some(Rc::new(Val::Int(24))));
+    let err = b.eval_string("let b = count(25);\n").unwrap_err();
+    let err = err.downcast::<BuildError>().unwrap();
+    assert_eq!(err.err_type.to_string(), "LimitExceeded");
+    assert!(err
+        .msg
+        .contains("Func calls and module copies nested more than 25 deep"));
+    // Only the ends of the trace are rendered.
+    assert!(format!("{}", err).contains("... 5 frames omitted"));
+}
+
+#[test]
+fn test_default_call_depth_fits_thread_stack() {
+    // Embedders usually build on a thread with the default stack size.
+    let child = std::thread::Builder::new()
+        .stack_size(2 * 1024 * 1024)
+        .spawn(|| {
+            let i_paths = Vec::new();
+            let cache = Rc::new(RefCell::new(MemoryCache::new()));
+            let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+            let err = b
+                .eval_string("let f = func(n) => {a = [n, {b = select n > 0, {true = f(n + 1), false = f(n + 1)}}]};\nf(0);\n")
+                .unwrap_err();
+            let err = err.downcast::<BuildError>().unwrap();
+            assert_eq!(err.err_type.to_string(), "LimitExceeded");
+        })
+        .unwrap();
+    child.join().unwrap();
+}
+
+#[test]
+fn test_resource_limits() {
+    let i_paths = Vec::new();
+    let new_builder = || {
+        let cache = Rc::new(RefCell::new(MemoryCache::new()));
+        FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache)
+    };
+    let limit_error = |b: &mut FileBuilder, input: &str| {
+        let err = b.eval_string(input).unwrap_err();
+        let err = err.downcast::<BuildError>().unwrap();
+        assert_eq!(err.err_type.to_string(), "LimitExceeded");
+        err.msg
+    };
+
+    let mut b = new_builder();
+    b.set_max_steps(50);
+    let msg = limit_error(&mut b, "let l = map(func(i) => i + 1, 1:100);\n");
+    assert_eq!(msg, "Evaluation took more than the maximum of 50 steps");
+
+    let mut b = new_builder();
+    b.set_max_range(10);
+    b.eval_string("let ok = 1:10;\nlet stepped = 0:10:90;\n")
+        .unwrap();
+    let msg = limit_error(&mut b, "let l = 1:11;\n");
+    assert_eq!(msg, "Range of 11 elements is larger than the maximum of 10");
+
+    let mut b = new_builder();
+    b.set_max_size(4);
+    b.eval_string("let ok = \"ab\" + \"cd\";\n").unwrap();
+    let msg = limit_error(&mut b, "let s = ok + \"e\";\n");
+    assert_eq!(
+        msg,
+        "String of 5 bytes is larger than the maximum size of 4"
+    );
+    let msg = limit_error(&mut b, "let l = [1, 2] + [3, 4, 5];\n");
+    assert_eq!(
+        msg,
+        "List of 5 elements is larger than the maximum size of 4"
+    );
+
+    let mut b = new_builder();
+    b.set_max_call_depth(10);
+    let msg = limit_error(
+        &mut b,
+        "let m = module{n = 0} => (r) {\n    let r = select mod.n == 0, {true = 0, false = mod.this{n = mod.n - 1}};\n};\nlet x = m{n = 10};\n",
+    );
+    assert!(msg.contains("nested more than 10 deep"));
+}
+
+#[test]
+fn test_sandbox() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.set_sandbox(
+        Sandbox::new()
+            .with_env_var("PATH")
+            .with_root("integration_tests/libs"),
+    );
+    match b.eval_string("env;\n").unwrap().as_ref() {
+        Val::Env(vars) => assert!(vars.iter().all(|(name, _)| name == "PATH")),
+        v => panic!("Expected env but got {}", v),
+    }
+    b.eval_string(
+        "let shared = import \"integration_tests/libs/shared.ucg\";\nlet src = include str \"integration_tests/libs/shared.ucg\";\n",
+    )
+    .unwrap();
+    for input in &[
+        "let outside = import \"integration_tests/tuple_test.ucg\";\n",
+        "let outside = include json \"integration_tests/include_example.json\";\n",
+        "let outside = import \"integration_tests/libs/../tuple_test.ucg\";\n",
+        // Missing files fail the same way so nothing can probe for them.
+        "let outside = import \"integration_tests/no_such_file.ucg\";\n",
+        "let outside = include str \"/no/such/file.txt\";\n",
+    ] {
+        let err = b.eval_string(input).unwrap_err();
+        let err = err.downcast::<BuildError>().unwrap();
+        assert_eq!(err.err_type.to_string(), "SandboxViolation");
+        assert!(err.msg.contains("is outside of the sandbox roots"));
+    }
+}
+
+#[test]
+fn test_sandbox_env_lookup() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.set_env(vec![
+        ("SECRET".to_string(), "shh".to_string()),
+        ("REGION".to_string(), "us-east".to_string()),
+    ]);
+    b.set_sandbox(Sandbox::new().with_env_var("REGION"));
+    b.eval_string("let region = env.REGION;\n").unwrap();
+    for input in &[
+        "env.SECRET;\n",
+        "env.\"SECRET\";\n",
+        "env.(\"SEC\" + \"RET\");\n",
+    ] {
+        let err = b.eval_string(input).unwrap_err();
+        let err = err.downcast::<BuildError>().unwrap();
+        assert_eq!(err.err_type.to_string(), "SandboxViolation");
+        assert!(err.msg.contains("SECRET"));
+    }
+}
+
+#[test]
+fn test_set_env() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    let mut vars = envfile::parse(
+        "# A comment\n\nDEPLOY_ENV=prod\nexport REGION = \"us-east\"\nEMPTY=\nURL='a=b'\n",
+    )
+    .unwrap();
+    envfile::merge(
+        &mut vars,
+        vec![envfile::parse_assignment("DEPLOY_ENV=staging").unwrap()],
+    );
+    b.set_env(vars);
+    b.eval_string("let deploy = env.DEPLOY_ENV;\nlet region = env.REGION;\nlet empty = env.EMPTY;\nlet url = env.URL;\n")
+        .unwrap();
+    assert_eq!(
+        b.get_out_by_name("deploy"),
+        Some(Rc::new(Val::Str("staging".to_string())))
+    );
+    assert_eq!(
+        b.get_out_by_name("region"),
+        Some(Rc::new(Val::Str("us-east".to_string())))
+    );
+    assert_eq!(
+        b.get_out_by_name("empty"),
+        Some(Rc::new(Val::Str("".to_string())))
+    );
+    assert_eq!(
+        b.get_out_by_name("url"),
+        Some(Rc::new(Val::Str("a=b".to_string())))
+    );
+    b.eval_string("let home = env.HOME;\n").unwrap();
+    assert_eq!(b.get_out_by_name("home"), Some(Rc::new(Val::Empty)));
+    assert_eq!(
+        envfile::parse("A=1\nnope\n").unwrap_err().to_string(),
+        "line 2: Expected KEY=VALUE but got nope"
+    );
+}
+
+#[test]
+fn test_args() {
+    let define = |d: &str| args::parse_define(d).unwrap();
+    assert_eq!(
+        define("replicas=3"),
+        ("replicas".to_string(), Rc::new(Val::Int(3)))
+    );
+    assert_eq!(
+        define("region=us-east"),
+        (
+            "region".to_string(),
+            Rc::new(Val::Str("us-east".to_string()))
+        )
+    );
+    assert_eq!(
+        define("name=\"a=b\""),
+        ("name".to_string(), Rc::new(Val::Str("a=b".to_string())))
+    );
+    assert_eq!(
+        define("ports=[80, 1 + 442]"),
+        (
+            "ports".to_string(),
+            Rc::new(Val::List(vec![
+                Rc::new(Val::Int(80)),
+                Rc::new(Val::Int(443))
+            ]))
+        )
+    );
+    // Anything that isn't made of literals is a string.
+    assert_eq!(
+        define("path=import \"a.ucg\""),
+        (
+            "path".to_string(),
+            Rc::new(Val::Str("import \"a.ucg\"".to_string()))
+        )
+    );
+    assert!(args::parse_define("replicas").is_err());
+    assert!(args::parse_define("=3").is_err());
+    assert!(args::parse_define("bad=1 + \"a\"").is_err());
+
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    let mut vals = vec![define("replicas=3"), define("region=us-east")];
+    args::merge(&mut vals, vec![define("region=eu")]);
+    b.set_args(vals);
+    b.eval_string(
+        "let replicas = args.replicas;\nlet region = args.region;\nlet has_zone = \"zone\" in args;\n",
+    )
+    .unwrap();
+    assert_eq!(b.get_out_by_name("replicas"), Some(Rc::new(Val::Int(3))));
+    assert_eq!(
+        b.get_out_by_name("region"),
+        Some(Rc::new(Val::Str("eu".to_string())))
+    );
+    assert_eq!(
+        b.get_out_by_name("has_zone"),
+        Some(Rc::new(Val::Boolean(false)))
+    );
+    // A binding named args hides them.
+    b.eval_string("let f = func(args) => args;\nlet shadowed = f(1);\n")
+        .unwrap();
+    assert_eq!(b.get_out_by_name("shadowed"), Some(Rc::new(Val::Int(1))));
+}
+
+#[test]
+fn test_native_funcs() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.register_native("double", |args: &[Rc<Val>], pos: &Position| {
+        native::expect_args("double", args, 1, pos)?;
+        match args[0].as_ref() {
+            &Val::Int(i) => Ok(Rc::new(Val::Int(i * 2))),
+            _ => Ok(Rc::new(Val::Empty)),
+        }
+    });
+    b.eval_string(
+        "let n = import \"std/native\";\nlet four = n.double(2);\nlet doubled = map(n.double, [1, 2]);\nlet lens = [n.len([1, 2]), n.len({a = 1}), n.len(\"abc\")];\nlet chars = n.chars(\"ab\");\nlet is_func = n.double is \"func\";\n",
+    )
+    .unwrap();
+    assert_eq!(b.get_out_by_name("four"), Some(Rc::new(Val::Int(4))));
+    assert_eq!(
+        b.get_out_by_name("doubled"),
+        Some(Rc::new(Val::List(vec![
+            Rc::new(Val::Int(2)),
+            Rc::new(Val::Int(4))
+        ])))
+    );
+    assert_eq!(
+        b.get_out_by_name("lens"),
+        Some(Rc::new(Val::List(vec![
+            Rc::new(Val::Int(2)),
+            Rc::new(Val::Int(1)),
+            Rc::new(Val::Int(3))
+        ])))
+    );
+    assert_eq!(
+        b.get_out_by_name("chars"),
+        Some(Rc::new(Val::List(vec![
+            Rc::new(Val::Str("a".to_string())),
+            Rc::new(Val::Str("b".to_string()))
+        ])))
+    );
+    assert_eq!(
+        b.get_out_by_name("is_func"),
+        Some(Rc::new(Val::Boolean(true)))
+    );
+    let err = b.eval_string("let bad = n.double(1, 2);\n").unwrap_err();
+    let err = err.downcast::<BuildError>().unwrap();
+    assert_eq!(err.err_type.to_string(), "BadArgLen");
+    assert_eq!(err.msg, "Func double expects 1 arguments but got 2");
+}
+
+#[test]
+fn test_native_string_errors() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.eval_string("let n = import \"std/native\";\n").unwrap();
+    for (input, msg) in &[
+        (
+            "n.split(\"abc\", \"\");",
+            "Func split can not split on an empty string. Use chars instead",
+        ),
+        (
+            "n.repeat(\"a\", 0 - 1);",
+            "Func repeat expected a count of 0 or more but got -1",
+        ),
+        (
+            "n.pad_left(\"a\", 3, \"ab\");",
+            "Func pad_left expected a single character to pad with but got \"ab\"",
+        ),
+        (
+            "n.join([1, [2]], \",\");",
+            "Func join can only join strings, numbers and booleans but got ([2, ])",
+        ),
+        (
+            "n.to_upper(1);",
+            "Func to_upper expected a string but got (1)",
+        ),
+    ] {
+        let err = b.eval_string(input).unwrap_err();
+        let err = err.downcast::<BuildError>().unwrap();
+        assert_eq!(err.msg, *msg);
+    }
+}
+
+#[test]
+fn test_native_string_size() {
+    let i_paths = Vec::new();
+    let cache = Rc::new(RefCell::new(MemoryCache::new()));
+    let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+    b.eval_string("let n = import \"std/native\";\n").unwrap();
+    // Without a maximum size strings that can't be allocated still fail
+    // cleanly.
+    for (input, msg) in &[
+        (
+            "n.repeat(\"ab\", 9223372036854775807);",
+            "Func repeat would produce a string too large to hold",
+        ),
+        (
+            "n.repeat(\"x\", 1000000000000000000);",
+            "Func repeat would produce a string too large to hold",
+        ),
+        (
+            "n.pad_right(\"x\", 1000000000000000000, \" \");",
+            "Func pad_right would produce a string too large to hold",
+        ),
+    ] {
+        let err = b.eval_string(input).unwrap_err();
+        let err = err.downcast::<BuildError>().unwrap();
+        assert_eq!(err.err_type.to_string(), "LimitExceeded");
+        assert_eq!(err.msg, *msg);
+    }
+
+    b.set_max_size(1000);
+    b.eval_string("n.repeat(\"ab\", 500);").unwrap();
+    // An empty string repeated any number of times is empty and returns
+    // without looping over the count.
+    let val = b
+        .eval_string("n.repeat(\"\", 9223372036854775807);")
+        .unwrap();
+    assert_eq!(val.as_ref(), &Val::Str("".to_string()));
+    let val = b
+        .eval_string("n.replace(n.repeat(\"ab\", 500), \"ab\", \"c\");")
+        .unwrap();
+    assert_eq!(val.as_ref(), &Val::Str("c".repeat(500)));
+    b.eval_string("n.pad_left(\"ab\", 1000, \" \");").unwrap();
+    for (input, msg) in &[
+        (
+            "n.repeat(\"a\", 200000000);",
+            "Func repeat would produce a string of 200000000 bytes which is larger than the maximum size of 1000",
+        ),
+        (
+            "n.pad_left(\"a\", 200000000, \" \");",
+            "Func pad_left would produce a string of 200000000 bytes which is larger than the maximum size of 1000",
+        ),
+        (
+            "n.pad_right(\"a\", 9223372036854775807, \" \");",
+            "Func pad_right would produce a string of 9223372036854775807 bytes which is larger than the maximum size of 1000",
+        ),
+        (
+            "n.replace(n.repeat(\"a\", 1000), \"a\", n.repeat(\"b\", 1000));",
+            "Func replace would produce a string of 1000000 bytes which is larger than the maximum size of 1000",
+        ),
+    ] {
+        let err = b.eval_string(input).unwrap_err();
+        let err = err.downcast::<BuildError>().unwrap();
+        assert_eq!(err.err_type.to_string(), "LimitExceeded");
+        assert_eq!(err.msg, *msg);
+    }
+}
+
+#[test]
+fn test_closest_match() {
+    use super::scope::closest_match;
+    let names = vec!["hostname", "port", "config"];
+    assert_eq!(closest_match("hostnme", names.clone()), Some("hostname"));
+    assert_eq!(closest_match("prot", names.clone()), Some("port"));
+    assert_eq!(closest_match("database", names.clone()), None);
+    assert_eq!(closest_match("port", names), None);
+}
+
+#[test]
+fn test_no_such_symbol_suggestions() {
+    let i_paths = Vec::new();
+    let cases = vec![
+        (
+            "let config = {hostname = \"x\"};\nconfig.hostnme;",
+            "Unable to find binding hostnme. Did you mean `hostname`?",
+        ),
+        (
+            "let config = {hostname = \"x\"};\nconfg;",
+            "Unable to find binding confg. Did you mean `config`?",
+        ),
+        (
+            "str_jion;",
+            "Unable to find binding str_jion. Did you mean `str_join` from `import \"std/lists.ucg\"`?",
+        ),
+        ("nothing_like_it;", "Unable to find binding nothing_like_it at"),
+    ];
+    for (input, expected) in cases {
+        let cache = Rc::new(RefCell::new(MemoryCache::new()));
+        let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+        let err = b.eval_string(input).unwrap_err();
+        let msg = format!("{}", err);
+        assert!(
+            msg.contains(expected),
+            "{} does not contain {}",
+            msg,
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_warnings() {
+    let stmts = crate::parse::parse(
+        crate::iter::OffsetStrIter::new(
+            "let lists = import \"std/lists.ucg\";\nlet unused = 1;\nlet base = {a = 1};\nlet mk = module{a = 1, b = 2} => (result) {\n    let scratch = 1;\n    let result = mod.a;\n};\nout json base{a = NULL};\nlet loop = func(n) => loop(n);\n",
+        ),
+        None,
+    )
+    .unwrap();
+    let warnings: Vec<(String, usize)> = super::warnings::check(&stmts)
+        .into_iter()
+        .map(|w| (w.kind.to_string(), w.pos.line))
+        .collect();
+    assert_eq!(
+        warnings,
+        vec![
+            ("UnusedImport".to_string(), 1),
+            ("UnusedBinding".to_string(), 2),
+            ("UnusedBinding".to_string(), 4),
+            ("UnusedModuleParam".to_string(), 4),
+            ("UnusedBinding".to_string(), 5),
+            ("NullOverride".to_string(), 8),
+            ("UnusedBinding".to_string(), 9),
+        ]
+    );
+
+    // Library bindings are used by whatever imports them and passing mod
+    // along reads every parameter.
+    let stmts = crate::parse::parse(
+        crate::iter::OffsetStrIter::new(
+            "let mk = module{a = 1} => (result) {\n    let result = mod;\n};\n",
+        ),
+        None,
+    )
+    .unwrap();
+    assert_eq!(super::warnings::check(&stmts), Vec::new());
+}
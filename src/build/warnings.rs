@@ -0,0 +1,226 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Checks for configuration that is defined but never used.
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+
+use crate::ast::walk::AstWalker;
+use crate::ast::*;
+use crate::error::{Warning, WarningKind};
+
+/// Finds the warnings for the statements of a file.
+///
+/// Files without an out or assert statement are treated as libraries so
+/// their top level bindings are assumed to be used by whatever imports them.
+pub fn check(stmts: &[Statement]) -> Vec<Warning> {
+    let library = !stmts
+        .iter()
+        .any(|stmt| matches!(stmt, Statement::Output(..) | Statement::Assert(_)));
+    let mut warnings = Vec::new();
+    check_bindings(stmts, None, library, &mut warnings);
+
+    let modules = RefCell::new(Vec::new());
+    let nulls = RefCell::new(Vec::new());
+    let find = |e: &mut Expression| match e {
+        Expression::Module(ref def) => modules.borrow_mut().push(def.clone()),
+        Expression::Copy(ref def) => {
+            for (name, val) in def.fields.iter() {
+                if let Expression::Simple(Value::Empty(_)) = val {
+                    nulls.borrow_mut().push(Warning::new(
+                        format!("Copy overrides field {} with NULL", name.fragment),
+                        WarningKind::NullOverride,
+                        name.pos.clone(),
+                    ));
+                }
+            }
+        }
+        _ => {}
+    };
+    let walker = AstWalker::new().with_expr_handler(&find);
+    for stmt in stmts.iter() {
+        walker.walk_statement(&mut stmt.clone());
+    }
+    warnings.extend(nulls.into_inner());
+    for def in modules.into_inner() {
+        check_module(&def, &mut warnings);
+    }
+    warnings.sort_by_key(|w| w.pos.offset);
+    warnings
+}
+
+fn check_bindings(
+    stmts: &[Statement],
+    out_expr: Option<&Expression>,
+    exported: bool,
+    warnings: &mut Vec<Warning>,
+) {
+    // Funcs can refer to themselves so a binding is only used if another
+    // statement refers to it.
+    let mut used: Vec<BTreeSet<String>> = stmts
+        .iter()
+        .map(|stmt| referenced_names(std::slice::from_ref(stmt), None))
+        .collect();
+    used.push(referenced_names(&[], out_expr));
+    for (i, stmt) in stmts.iter().enumerate() {
+        let def = match stmt {
+            Statement::Let(ref def) => def,
+            _ => continue,
+        };
+        let name = &def.name.fragment;
+        if used
+            .iter()
+            .enumerate()
+            .any(|(j, names)| j != i && names.contains(name))
+        {
+            continue;
+        }
+        match def.value {
+            // An import nothing uses is dead even in a library.
+            Expression::Import(ref import) => warnings.push(Warning::new(
+                format!(
+                    "Import of {} bound to {} is never used",
+                    import.path.fragment, def.name.fragment
+                ),
+                WarningKind::UnusedImport,
+                def.name.pos.clone(),
+            )),
+            _ if !exported => warnings.push(Warning::new(
+                format!("Binding {} is never used", def.name.fragment),
+                WarningKind::UnusedBinding,
+                def.name.pos.clone(),
+            )),
+            _ => {}
+        }
+    }
+}
+
+fn check_module(def: &ModuleDef, warnings: &mut Vec<Warning>) {
+    // Without an out expression every binding in the module is part of its
+    // result.
+    check_bindings(
+        &def.statements,
+        def.out_expr.as_ref().map(|e| e.as_ref()),
+        def.out_expr.is_none(),
+        warnings,
+    );
+    let params = RefCell::new(BTreeSet::new());
+    let whole_mod = Cell::new(false);
+    let find_params = |e: &mut Expression| match e {
+        Expression::Module(ref nested) => {
+            // Nested modules have their own mod.
+            let pos = nested.pos.clone();
+            *e = Expression::Simple(Value::Empty(pos));
+        }
+        Expression::Binary(ref mut dot) if dot.kind == BinaryExprType::DOT => {
+            let sym = match dot.left.as_ref() {
+                Expression::Simple(Value::Symbol(ref s)) if s.val == "mod" => s.clone(),
+                _ => return,
+            };
+            if let Some(name) = selector_name(dot.right.as_ref()) {
+                params.borrow_mut().insert(name);
+            }
+            *dot.left = Expression::Simple(Value::Str(sym));
+        }
+        _ => {}
+    };
+    let find_whole_mod = |v: &mut Value| {
+        if let Value::Symbol(ref s) = v {
+            if s.val == "mod" {
+                whole_mod.set(true);
+            }
+        }
+    };
+    let walker = AstWalker::new()
+        .with_expr_handler(&find_params)
+        .with_value_handler(&find_whole_mod);
+    for stmt in def.statements.iter() {
+        walker.walk_statement(&mut stmt.clone());
+    }
+    if let Some(ref expr) = def.out_expr {
+        walker.walk_expression(&mut expr.as_ref().clone());
+    }
+    // Passing mod along as a whole could read any of them.
+    if whole_mod.get() {
+        return;
+    }
+    let params = params.into_inner();
+    for (name, _) in def.arg_set.iter() {
+        if !params.contains(&name.fragment) {
+            warnings.push(Warning::new(
+                format!("Module parameter {} is never read", name.fragment),
+                WarningKind::UnusedModuleParam,
+                name.pos.clone(),
+            ));
+        }
+    }
+}
+
+// The name a dot selector picks out of its left side.
+fn selector_name(right: &Expression) -> Option<String> {
+    let selector = match right {
+        Expression::Simple(ref v) => v,
+        Expression::Copy(ref copy) => &copy.selector,
+        Expression::Call(ref call) => &call.funcref,
+        _ => return None,
+    };
+    match selector {
+        Value::Symbol(ref s) | Value::Str(ref s) => Some(s.val.clone()),
+        _ => None,
+    }
+}
+
+/// Returns the names of the bindings the statements and out expression
+/// refer to.
+///
+/// The right side of a dot selector names a field rather than a binding so
+/// it isn't included. Neither is anything in the body of a module since
+/// modules can't see the bindings around them.
+pub fn referenced_names(stmts: &[Statement], out_expr: Option<&Expression>) -> BTreeSet<String> {
+    let names = RefCell::new(BTreeSet::new());
+    let collect = |v: &mut Value| {
+        if let Value::Symbol(ref s) = v {
+            names.borrow_mut().insert(s.val.clone());
+        }
+    };
+    let skip_fields = |e: &mut Expression| match e {
+        Expression::Module(ref def) => {
+            // The parameter defaults are still evaluated out here.
+            let fields = PositionedItem::new(def.arg_set.clone(), def.pos.clone());
+            *e = Expression::Simple(Value::Tuple(fields));
+        }
+        Expression::Binary(ref mut def) if def.kind == BinaryExprType::DOT => {
+            let selector = match def.right.as_mut() {
+                Expression::Simple(ref mut v) => v,
+                Expression::Copy(ref mut copy) => &mut copy.selector,
+                Expression::Call(ref mut call) => &mut call.funcref,
+                _ => return,
+            };
+            if let Value::Symbol(ref s) = selector {
+                *selector = Value::Str(s.clone());
+            }
+        }
+        _ => {}
+    };
+    let walker = AstWalker::new()
+        .with_expr_handler(&skip_fields)
+        .with_value_handler(&collect);
+    for stmt in stmts.iter() {
+        walker.walk_statement(&mut stmt.clone());
+    }
+    if let Some(expr) = out_expr {
+        walker.walk_expression(&mut expr.clone());
+    }
+    names.into_inner()
+}
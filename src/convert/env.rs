@@ -73,7 +73,7 @@ impl EnvConverter {
             &Val::Tuple(ref flds) => {
                 self.convert_tuple(flds, w)?;
             }
-            &Val::Func(ref _def) => {
+            &Val::Func(_) | &Val::Native(_) => {
                 // This is ignored
                 eprintln!("Skipping func...");
             }
@@ -110,7 +110,7 @@ impl FlagConverter {
                     }
                 }
             }
-            &Val::Func(ref _def) => {
+            &Val::Func(_) | &Val::Native(_) => {
                 // This is ignored
                 eprintln!("Skipping macro...");
             }
@@ -72,7 +72,7 @@ impl JsonConverter {
                 serde_json::Value::Number(n)
             }
             &Val::Str(ref s) => serde_json::Value::String(s.clone()),
-            &Val::Func(_) => {
+            &Val::Func(_) | &Val::Native(_) => {
                 eprintln!("Skipping func encoding as null...");
                 serde_json::Value::Null
             }
@@ -68,7 +68,7 @@ impl TomlConverter {
             &Val::Float(f) => toml::Value::Float(f),
             &Val::Int(i) => toml::Value::Integer(i),
             &Val::Str(ref s) => toml::Value::String(s.clone()),
-            &Val::Func(_) => {
+            &Val::Func(_) | &Val::Native(_) => {
                 let err = SimpleError::new("Functions are not allowed in Toml Conversions!");
                 return Err(Box::new(err));
             }
@@ -140,3 +140,58 @@ impl Importer for TomlConverter {
         Ok(Rc::new(self.convert_toml_val(&json_val)?))
     }
 }
+
+#[cfg(test)]
+mod toml_test {
+    use super::*;
+    use crate::build::assets::MemoryCache;
+    use crate::build::FileBuilder;
+    use crate::convert::traits::Converter;
+
+    use std;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+
+    #[test]
+    fn convert_keeps_field_order_test() {
+        let i_paths = Vec::new();
+        let cache = Rc::new(RefCell::new(MemoryCache::new()));
+        let mut b = FileBuilder::new(std::env::current_dir().unwrap(), &i_paths, cache);
+        let conv = TomlConverter::new();
+        b.eval_string(
+            "let config = {
+            zone = \"us-east\",
+            name = \"web\",
+            server = {
+                port = 8080,
+                host = \"localhost\",
+            },
+        };",
+        )
+        .unwrap();
+        let result = b.get_out_by_name("config").unwrap();
+        let mut expected = "zone = 'us-east'\n".to_string();
+        expected.push_str("name = 'web'\n");
+        expected.push_str("\n[server]\n");
+        expected.push_str("port = 8080\n");
+        expected.push_str("host = 'localhost'\n");
+        let mut buf = Cursor::new(vec![]);
+        conv.convert(result, &mut buf).unwrap();
+        assert_eq!(String::from_utf8_lossy(&buf.into_inner()), expected);
+    }
+
+    #[test]
+    fn import_keeps_field_order_test() {
+        let conv = TomlConverter::new();
+        let val = conv
+            .import(b"zone = \"us-east\"\nname = \"web\"\n")
+            .unwrap();
+        assert_eq!(
+            val,
+            Rc::new(Val::Tuple(vec![
+                ("zone".to_string(), Rc::new(Val::Str("us-east".to_string()))),
+                ("name".to_string(), Rc::new(Val::Str("web".to_string()))),
+            ]))
+        );
+    }
+}
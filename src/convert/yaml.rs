@@ -56,7 +56,7 @@ impl YamlConverter {
                 _ => panic!("Int is too large or not a Number {}", i),
             },
             &Val::Str(ref s) => serde_yaml::Value::String(s.clone()),
-            &Val::Func(_) => {
+            &Val::Func(_) | &Val::Native(_) => {
                 eprintln!("Skipping func encoding as null...");
                 serde_yaml::Value::Null
             }
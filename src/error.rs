@@ -13,9 +13,14 @@
 //  limitations under the License.
 
 //! Errors for use by the ucg compiler.
+use std::collections::BTreeMap;
 use std::error;
 use std::fmt;
 use std::fmt::Debug;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use serde_json;
 
 use crate::ast::*;
 use crate::iter::FilePositioned;
@@ -33,6 +38,9 @@ pub enum ErrorType {
     IncludeError,
     ImportError,
     ReservedWordError,
+    LimitExceeded,
+    SandboxViolation,
+    OutputError,
     // Parsing Errors
     ParseError,
     AssertError,
@@ -41,6 +49,8 @@ pub enum ErrorType {
     ConvertError,
     // User Defined Declarative Errors
     UserDefined,
+    // Warnings turned into errors by --deny-warnings
+    Warning,
 }
 
 impl fmt::Display for ErrorType {
@@ -55,22 +65,147 @@ impl fmt::Display for ErrorType {
             &ErrorType::IncludeError => "IncludeError",
             &ErrorType::ImportError => "ImportError",
             &ErrorType::ReservedWordError => "ReservedWordError",
+            &ErrorType::LimitExceeded => "LimitExceeded",
+            &ErrorType::SandboxViolation => "SandboxViolation",
+            &ErrorType::OutputError => "OutputError",
             &ErrorType::ParseError => "ParseError",
             &ErrorType::AssertError => "AssertError",
             &ErrorType::OSError => "OSError",
             &ErrorType::ConvertError => "ConvertError",
             &ErrorType::UserDefined => "UserDefined",
+            &ErrorType::Warning => "Warning",
+        };
+        w.write_str(name)
+    }
+}
+
+/// WarningKind defines the kinds of non-fatal problems a build can report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WarningKind {
+    UnusedBinding,
+    UnusedImport,
+    UnusedModuleParam,
+    NullOverride,
+    /// A finding from the named `ucg lint` rule.
+    Lint(&'static str),
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            &WarningKind::UnusedBinding => "UnusedBinding",
+            &WarningKind::UnusedImport => "UnusedImport",
+            &WarningKind::UnusedModuleParam => "UnusedModuleParam",
+            &WarningKind::NullOverride => "NullOverride",
+            &WarningKind::Lint(rule) => rule,
+        };
+        w.write_str(name)
+    }
+}
+
+/// A non-fatal problem found while building a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub msg: String,
+    pub pos: Position,
+}
+
+impl Warning {
+    pub fn new<S: Into<String>>(msg: S, kind: WarningKind, pos: Position) -> Self {
+        Warning {
+            kind,
+            msg: msg.into(),
+            pos,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            w,
+            "{}: {} at {} line: {}, column: {}",
+            self.kind,
+            self.msg,
+            file_name(&self.pos),
+            self.pos.line,
+            self.pos.column
+        )
+    }
+}
+
+/// The kind of evaluation a stack frame was recorded for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameKind {
+    Func,
+    Module,
+    Import,
+}
+
+impl fmt::Display for FrameKind {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            &FrameKind::Func => "func",
+            &FrameKind::Module => "module",
+            &FrameKind::Import => "import",
         };
         w.write_str(name)
     }
 }
 
+/// A single frame of the evaluation stack an error happened in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    pub kind: FrameKind,
+    /// Where the func was called, the module was copied or the file was
+    /// imported.
+    pub call_site: Position,
+    /// Where the func or module was defined or the start of the imported
+    /// file.
+    pub definition: Position,
+    /// The import that brought the definition in when it came from another
+    /// file.
+    pub imported_at: Option<Position>,
+}
+
+impl StackFrame {
+    // Describes what the frame evaluated with the given location format.
+    fn describe<F>(&self, location: F) -> String
+    where
+        F: Fn(&Position) -> String,
+    {
+        match self.kind {
+            FrameKind::Import => format!("import of {}", file_name(&self.definition)),
+            _ => format!("{} defined at {}", self.kind, location(&self.definition)),
+        }
+    }
+
+    fn call_verb(&self) -> &'static str {
+        match self.kind {
+            FrameKind::Func => "called",
+            FrameKind::Module => "copied",
+            FrameKind::Import => "imported",
+        }
+    }
+}
+
+fn file_name(pos: &Position) -> String {
+    match pos.file {
+        Some(ref pb) => pb.to_string_lossy().to_string(),
+        None => "<eval>".to_string(),
+    }
+}
+
 /// Error defines an Error type for parsing and building UCG code.
 pub struct BuildError {
     pub err_type: ErrorType,
     pub pos: Option<Position>,
     pub msg: String,
     pub cause: Option<Box<dyn error::Error>>,
+    /// The evaluation stack at the point the error happened. The innermost
+    /// frame is first.
+    pub trace: Vec<StackFrame>,
     // This field is only present to prevent people from constructing these
     // outside of the module they are defined in.
     _pkgonly: (),
@@ -83,6 +218,7 @@ impl BuildError {
             pos: Some(pos),
             msg: msg.into(),
             cause: None,
+            trace: Vec::new(),
             _pkgonly: (),
         }
     }
@@ -93,6 +229,7 @@ impl BuildError {
             pos: None,
             msg: msg.into(),
             cause: None,
+            trace: Vec::new(),
             _pkgonly: (),
         }
     }
@@ -123,10 +260,81 @@ impl BuildError {
         if let Some(ref cause) = self.cause {
             write!(w, "\nCaused By:\n\t{}", cause)?;
         }
+        if !self.trace.is_empty() {
+            write!(w, "\nStack Trace:")?;
+        }
+        let len = self.trace.len();
+        for (i, frame) in self.trace.iter().enumerate() {
+            if frame_hidden(i, len) {
+                if i == TRACE_EDGE_FRAMES {
+                    write!(w, "\n\t... {} frames omitted", len - 2 * TRACE_EDGE_FRAMES)?;
+                }
+                continue;
+            }
+            let location =
+                |p: &Position| format!("{} line: {}, column: {}", file_name(p), p.line, p.column);
+            write!(
+                w,
+                "\n\t{}: {}, {} at {}",
+                i,
+                frame.describe(location),
+                frame.call_verb(),
+                location(&frame.call_site),
+            )?;
+            if let Some(ref pos) = frame.imported_at {
+                write!(w, ", imported at {}", location(pos))?;
+            }
+        }
         Ok(())
     }
 }
 
+// How many frames are shown from each end of a trace. Deeper traces,
+// usually from runaway recursion, leave out the frames in the middle.
+const TRACE_EDGE_FRAMES: usize = 10;
+
+fn frame_hidden(i: usize, len: usize) -> bool {
+    len > 2 * TRACE_EDGE_FRAMES && i >= TRACE_EDGE_FRAMES && i < len - TRACE_EDGE_FRAMES
+}
+
+/// Records a frame of the evaluation stack on an error.
+///
+/// Errors that aren't a BuildError are wrapped in one positioned at the call
+/// site first.
+pub fn with_frame(err: Box<dyn error::Error>, frame: StackFrame) -> Box<dyn error::Error> {
+    let mut err = match err.downcast::<BuildError>() {
+        Ok(err) => err,
+        Err(err) => {
+            let (msg, typ) = match frame.kind {
+                FrameKind::Func => ("Func evaluation failed", ErrorType::TypeFail),
+                FrameKind::Module => ("Module evaluation failed", ErrorType::TypeFail),
+                FrameKind::Import => ("Import failed", ErrorType::ImportError),
+            };
+            BuildError::with_pos(msg, typ, frame.call_site.clone())
+                .wrap_cause(err)
+                .to_boxed()
+        }
+    };
+    err.trace.push(frame);
+    err
+}
+
+// The stack frames recorded anywhere in an error chain innermost first.
+fn error_trace<'a>(err: &'a (dyn error::Error + 'static)) -> Vec<&'a StackFrame> {
+    let mut groups = Vec::new();
+    let mut curr = Some(err);
+    while let Some(err) = curr {
+        match err.downcast_ref::<BuildError>() {
+            Some(err) => {
+                groups.push(&err.trace);
+                curr = err.cause.as_ref().map(|c| c.as_ref());
+            }
+            None => break,
+        }
+    }
+    groups.into_iter().rev().flat_map(|t| t.iter()).collect()
+}
+
 impl Debug for BuildError {
     fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
         self.render(w)
@@ -151,6 +359,16 @@ pub struct StackPrinter<C: FilePositioned> {
 }
 
 impl<C: FilePositioned> StackPrinter<C> {
+    /// Converts the error stack into one that no longer refers to the parser input.
+    pub fn to_positioned<F>(&self, pos_of: F) -> StackPrinter<Position>
+    where
+        F: Fn(&C) -> Position,
+    {
+        StackPrinter {
+            err: positioned_error(&self.err, &pos_of),
+        }
+    }
+
     pub fn render(&self, w: &mut fmt::Formatter) -> fmt::Result {
         let mut curr_err = Some(&self.err);
         let mut tabstop = "";
@@ -190,3 +408,344 @@ impl<C: FilePositioned> fmt::Display for StackPrinter<C> {
         self.render(w)
     }
 }
+
+impl<C: FilePositioned + Debug> error::Error for StackPrinter<C> {}
+
+/// The errors from a build that kept going after the first failure.
+pub struct MultiError {
+    pub errors: Vec<Box<dyn error::Error>>,
+}
+
+impl MultiError {
+    fn render(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        for (i, err) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(w)?;
+            }
+            write!(w, "{}", err)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for MultiError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        self.render(w)
+    }
+}
+
+impl fmt::Display for MultiError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        self.render(w)
+    }
+}
+
+impl error::Error for MultiError {}
+
+/// Splits an error into the independent errors it is made of.
+///
+/// This is the list of errors for a MultiError and the error itself for
+/// anything else.
+pub fn individual_errors<'a>(
+    err: &'a (dyn error::Error + 'static),
+) -> Vec<&'a (dyn error::Error + 'static)> {
+    match err.downcast_ref::<MultiError>() {
+        Some(multi) => multi.errors.iter().map(|e| e.as_ref()).collect(),
+        None => vec![err],
+    }
+}
+
+fn positioned_error<C, F>(
+    err: &abortable_parser::Error<C>,
+    pos_of: &F,
+) -> abortable_parser::Error<Position>
+where
+    F: Fn(&C) -> Position,
+{
+    let pos = Box::new(pos_of(err.get_context()));
+    match err.get_cause() {
+        Some(cause) => abortable_parser::Error::caused_by(
+            err.get_msg(),
+            Box::new(positioned_error(cause, pos_of)),
+            pos,
+        ),
+        None => abortable_parser::Error::new(err.get_msg(), pos),
+    }
+}
+
+// A single error from an error chain flattened for rendering.
+struct DiagnosticEntry {
+    typ: Option<String>,
+    msg: String,
+    pos: Option<Position>,
+}
+
+fn diagnostic_entries(err: &(dyn error::Error + 'static), out: &mut Vec<DiagnosticEntry>) {
+    if let Some(err) = err.downcast_ref::<BuildError>() {
+        out.push(DiagnosticEntry {
+            typ: Some(err.err_type.to_string()),
+            msg: err.msg.clone(),
+            pos: err.pos.clone(),
+        });
+        if let Some(ref cause) = err.cause {
+            diagnostic_entries(cause.as_ref(), out);
+        }
+    } else if let Some(stack) = err.downcast_ref::<StackPrinter<Position>>() {
+        let mut curr_err = Some(&stack.err);
+        while let Some(err) = curr_err {
+            out.push(DiagnosticEntry {
+                typ: Some(ErrorType::ParseError.to_string()),
+                msg: err.get_msg().to_string(),
+                pos: Some(err.get_context().clone()),
+            });
+            curr_err = err.get_cause();
+        }
+    } else {
+        out.push(DiagnosticEntry {
+            typ: None,
+            msg: err.to_string(),
+            pos: None,
+        });
+    }
+}
+
+fn json_diagnostic_entry(entry: &DiagnosticEntry) -> serde_json::Value {
+    let mut map = serde_json::Map::new();
+    map.insert(
+        "type".to_string(),
+        entry
+            .typ
+            .clone()
+            .map(serde_json::Value::String)
+            .unwrap_or(serde_json::Value::Null),
+    );
+    map.insert(
+        "message".to_string(),
+        serde_json::Value::String(entry.msg.clone()),
+    );
+    let (file, line, column) = match entry.pos {
+        Some(ref pos) => (
+            pos.file
+                .as_ref()
+                .map(|f| serde_json::Value::String(f.to_string_lossy().to_string()))
+                .unwrap_or(serde_json::Value::Null),
+            serde_json::Value::from(pos.line),
+            serde_json::Value::from(pos.column),
+        ),
+        None => (
+            serde_json::Value::Null,
+            serde_json::Value::Null,
+            serde_json::Value::Null,
+        ),
+    };
+    let (end_line, end_column) = match entry.pos.as_ref().and_then(|p| p.end) {
+        Some(end) => (
+            serde_json::Value::from(end.line),
+            serde_json::Value::from(end.column),
+        ),
+        None => (serde_json::Value::Null, serde_json::Value::Null),
+    };
+    map.insert("file".to_string(), file);
+    map.insert("line".to_string(), line);
+    map.insert("column".to_string(), column);
+    map.insert("end_line".to_string(), end_line);
+    map.insert("end_column".to_string(), end_column);
+    serde_json::Value::Object(map)
+}
+
+fn json_location(pos: &Position) -> serde_json::Value {
+    let mut map = serde_json::Map::new();
+    map.insert(
+        "file".to_string(),
+        pos.file
+            .as_ref()
+            .map(|f| serde_json::Value::String(f.to_string_lossy().to_string()))
+            .unwrap_or(serde_json::Value::Null),
+    );
+    map.insert("line".to_string(), serde_json::Value::from(pos.line));
+    map.insert("column".to_string(), serde_json::Value::from(pos.column));
+    serde_json::Value::Object(map)
+}
+
+fn json_stack_frame(frame: &StackFrame) -> serde_json::Value {
+    let mut map = serde_json::Map::new();
+    map.insert(
+        "kind".to_string(),
+        serde_json::Value::String(frame.kind.to_string()),
+    );
+    map.insert("call_site".to_string(), json_location(&frame.call_site));
+    map.insert("definition".to_string(), json_location(&frame.definition));
+    map.insert(
+        "imported_at".to_string(),
+        frame
+            .imported_at
+            .as_ref()
+            .map(json_location)
+            .unwrap_or(serde_json::Value::Null),
+    );
+    serde_json::Value::Object(map)
+}
+
+/// Renders an error and its chain of causes as a json diagnostic.
+///
+/// The diagnostic has the type, message, file, line and column of the error
+/// along with a list of causes in the same shape ordered from the outermost
+/// to the innermost and the stack trace ordered from the innermost frame.
+pub fn to_json_diagnostic(err: &(dyn error::Error + 'static)) -> serde_json::Value {
+    let mut entries = Vec::new();
+    diagnostic_entries(err, &mut entries);
+    let mut diagnostic = json_diagnostic_entry(&entries[0]);
+    let causes = entries[1..].iter().map(json_diagnostic_entry).collect();
+    let trace = error_trace(err).into_iter().map(json_stack_frame).collect();
+    if let serde_json::Value::Object(ref mut map) = diagnostic {
+        map.insert("causes".to_string(), serde_json::Value::Array(causes));
+        map.insert("trace".to_string(), serde_json::Value::Array(trace));
+    }
+    diagnostic
+}
+
+/// Renders a warning as a json diagnostic.
+///
+/// Warnings have the same shape as errors with a `severity` of `warning` and
+/// without causes or a stack trace.
+pub fn warning_to_json(warning: &Warning) -> serde_json::Value {
+    let entry = DiagnosticEntry {
+        typ: Some(warning.kind.to_string()),
+        msg: warning.msg.clone(),
+        pos: Some(warning.pos.clone()),
+    };
+    let mut diagnostic = json_diagnostic_entry(&entry);
+    if let serde_json::Value::Object(ref mut map) = diagnostic {
+        map.insert(
+            "severity".to_string(),
+            serde_json::Value::String("warning".to_string()),
+        );
+    }
+    diagnostic
+}
+
+/// Renders a warning along with the line of source it points to.
+pub fn render_warning(warning: &Warning) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "warning[{}]: {}", warning.kind, warning.msg);
+    let _ = write_snippet(&mut out, &warning.pos, &mut BTreeMap::new());
+    out
+}
+
+fn write_snippet(
+    out: &mut String,
+    pos: &Position,
+    sources: &mut BTreeMap<PathBuf, Option<String>>,
+) -> fmt::Result {
+    let file = match pos.file {
+        Some(ref pb) => pb,
+        None => {
+            return writeln!(out, "  --> <eval>:{}:{}", pos.line, pos.column);
+        }
+    };
+    writeln!(
+        out,
+        "  --> {}:{}:{}",
+        file.to_string_lossy(),
+        pos.line,
+        pos.column
+    )?;
+    let src = sources
+        .entry(file.clone())
+        .or_insert_with(|| std::fs::read_to_string(file).ok());
+    let line = match src {
+        Some(ref src) if pos.line > 0 => src.lines().nth(pos.line - 1),
+        _ => None,
+    };
+    if let Some(line) = line {
+        let gutter = pos.line.to_string();
+        let pad = " ".repeat(gutter.len());
+        // Keep any tabs before the column so the caret lines up with the
+        // source when the terminal expands them.
+        let marker: String = line
+            .chars()
+            .chain(std::iter::repeat(' '))
+            .take(pos.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // Spans that run past this line are underlined to the end of it.
+        let width = match pos.end {
+            Some(ref end) if end.line == pos.line => end.column.saturating_sub(pos.column),
+            Some(_) => line.chars().count().saturating_sub(marker.len()),
+            None => 1,
+        };
+        writeln!(out, " {} |", pad)?;
+        writeln!(out, " {} | {}", gutter, line)?;
+        writeln!(out, " {} | {}{}", pad, marker, "^".repeat(width.max(1)))?;
+    }
+    Ok(())
+}
+
+/// Renders an error and its chain of causes along with the lines of source
+/// that each of them points to.
+///
+/// Source is read from the file recorded in each position. Positions
+/// without a file or whose file can no longer be read only get their
+/// location printed.
+pub fn render_with_source(err: &(dyn error::Error + 'static)) -> String {
+    let mut sources = BTreeMap::new();
+    let mut out = String::new();
+    for err in individual_errors(err) {
+        render_entries(err, &mut sources, &mut out);
+    }
+    out
+}
+
+fn render_entries(
+    err: &(dyn error::Error + 'static),
+    sources: &mut BTreeMap<PathBuf, Option<String>>,
+    out: &mut String,
+) {
+    let mut entries = Vec::new();
+    diagnostic_entries(err, &mut entries);
+    let mut last_pos: Option<&Position> = None;
+    for (i, entry) in entries.iter().enumerate() {
+        let label = if i == 0 { "error" } else { "caused by" };
+        let msg = entry.msg.trim_end();
+        // We ignore the fmt::Result since writing to a String can't fail.
+        let _ = match entry.typ {
+            Some(ref typ) => writeln!(out, "{}[{}]: {}", label, typ, msg),
+            None => writeln!(out, "{}: {}", label, msg),
+        };
+        if let Some(ref pos) = entry.pos {
+            // Parse errors often repeat the same position for every level of
+            // the stack so we only show the snippet once.
+            if last_pos != Some(pos) {
+                let _ = write_snippet(out, pos, sources);
+            }
+            last_pos = Some(pos);
+        }
+    }
+    let trace = error_trace(err);
+    if !trace.is_empty() {
+        out.push_str("stack backtrace:\n");
+    }
+    for (i, frame) in trace.iter().enumerate() {
+        if frame_hidden(i, trace.len()) {
+            if i == TRACE_EDGE_FRAMES {
+                let _ = writeln!(
+                    out,
+                    "      ... {} frames omitted",
+                    trace.len() - 2 * TRACE_EDGE_FRAMES
+                );
+            }
+            continue;
+        }
+        let location = |p: &Position| format!("{}:{}:{}", file_name(p), p.line, p.column);
+        let _ = writeln!(out, "{:>4}: {}", i, frame.describe(location));
+        let _ = writeln!(
+            out,
+            "      {} at {}",
+            frame.call_verb(),
+            location(&frame.call_site)
+        );
+        if let Some(ref pos) = frame.imported_at {
+            let _ = writeln!(out, "      imported at {}", location(pos));
+        }
+    }
+}
@@ -122,6 +122,7 @@ impl<'a> From<&'a OffsetStrIter<'a>> for Position {
             line: s.line(),
             column: s.column(),
             offset: s.get_offset(),
+            end: None,
         }
     }
 }
@@ -130,6 +131,12 @@ pub trait FilePositioned: Positioned {
     fn file(&self) -> Option<&PathBuf>;
 }
 
+impl FilePositioned for Position {
+    fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+}
+
 impl<'a> FilePositioned for OffsetStrIter<'a> {
     fn file(&self) -> Option<&PathBuf> {
         self.source_file.as_ref()
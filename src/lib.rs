@@ -23,6 +23,7 @@
 #[macro_use]
 extern crate abortable_parser;
 extern crate base64;
+extern crate indexmap;
 extern crate regex;
 extern crate serde_json;
 extern crate serde_yaml;
@@ -39,7 +40,11 @@ pub mod build;
 pub mod convert;
 pub mod error;
 pub mod iter;
+pub mod lint;
+pub mod lsp;
 pub mod parse;
+pub mod repl;
+pub mod typecheck;
 
 pub use crate::ast::Expression;
 pub use crate::ast::Statement;
@@ -0,0 +1,350 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Configurable lint rules for ucg files.
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use simple_error::SimpleError;
+use toml;
+
+use crate::ast::walk::{visit_statement, Visitor};
+use crate::ast::*;
+use crate::error::{Warning, WarningKind};
+
+/// The name of the project config file `ucg lint` looks for.
+pub const CONFIG_FILE: &str = ".ucg-lint.toml";
+
+/// The let binding naming pattern used when the config doesn't set one.
+pub const DEFAULT_NAMING_PATTERN: &str = "^[a-z_][a-z0-9_]*$";
+
+/// The maximum nesting depth used when the config doesn't set one.
+pub const DEFAULT_MAX_DEPTH: usize = 8;
+
+/// The rules and their settings. A rule set to None or false is off.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Let binding names must match this pattern.
+    pub let_naming: Option<Regex>,
+    /// Only these files, or the files under these directories, may use env.
+    pub env_access: Option<Vec<PathBuf>>,
+    /// Whether TRACE expressions are reported.
+    pub no_trace: bool,
+    /// Whether asserts without a desc field are reported.
+    pub assert_desc: bool,
+    /// How deeply tuples, lists, copies, selects, funcs and modules may nest.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            let_naming: Some(Regex::new(DEFAULT_NAMING_PATTERN).unwrap()),
+            env_access: None,
+            no_trace: true,
+            assert_desc: true,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+        }
+    }
+}
+
+fn config_error<S: Into<String>>(msg: S) -> Box<dyn Error> {
+    Box::new(SimpleError::new(msg.into()))
+}
+
+impl Config {
+    /// Parses a toml config. Each rule is a table. Listing a rule turns it
+    /// on unless it sets `enabled = false`. Relative paths are resolved
+    /// against base.
+    pub fn parse(src: &str, base: &Path) -> Result<Self, Box<dyn Error>> {
+        let doc = match src.parse::<toml::Value>() {
+            Ok(toml::Value::Table(doc)) => doc,
+            Ok(_) => return Err(config_error("Lint config must be a table")),
+            Err(e) => return Err(config_error(format!("Invalid lint config: {}", e))),
+        };
+        let mut config = Config::default();
+        for (rule, settings) in doc.iter() {
+            let settings = match settings {
+                toml::Value::Table(ref t) => t,
+                _ => return Err(config_error(format!("Lint rule {} must be a table", rule))),
+            };
+            let enabled = match settings.get("enabled") {
+                None => true,
+                Some(toml::Value::Boolean(b)) => *b,
+                Some(_) => {
+                    return Err(config_error(format!("{}.enabled must be a boolean", rule)));
+                }
+            };
+            let allowed_keys: &[&str] = match rule.as_str() {
+                "let_naming" => {
+                    config.let_naming = if enabled {
+                        let pattern = match settings.get("pattern") {
+                            None => DEFAULT_NAMING_PATTERN,
+                            Some(toml::Value::String(s)) => s.as_str(),
+                            Some(_) => {
+                                return Err(config_error("let_naming.pattern must be a string"));
+                            }
+                        };
+                        match Regex::new(pattern) {
+                            Ok(re) => Some(re),
+                            Err(e) => {
+                                return Err(config_error(format!(
+                                    "Invalid let_naming.pattern: {}",
+                                    e
+                                )));
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    &["enabled", "pattern"]
+                }
+                "env_access" => {
+                    let mut allowed = Vec::new();
+                    match settings.get("allowed") {
+                        None => {}
+                        Some(toml::Value::Array(paths)) => {
+                            for p in paths.iter() {
+                                match p {
+                                    toml::Value::String(s) => allowed.push(base.join(s)),
+                                    _ => {
+                                        return Err(config_error(
+                                            "env_access.allowed must be a list of paths",
+                                        ));
+                                    }
+                                }
+                            }
+                        }
+                        Some(_) => {
+                            return Err(config_error("env_access.allowed must be a list of paths"));
+                        }
+                    }
+                    config.env_access = if enabled { Some(allowed) } else { None };
+                    &["enabled", "allowed"]
+                }
+                "no_trace" => {
+                    config.no_trace = enabled;
+                    &["enabled"]
+                }
+                "assert_desc" => {
+                    config.assert_desc = enabled;
+                    &["enabled"]
+                }
+                "max_depth" => {
+                    let max = match settings.get("max") {
+                        None => DEFAULT_MAX_DEPTH,
+                        Some(toml::Value::Integer(i)) if *i > 0 => *i as usize,
+                        Some(_) => {
+                            return Err(config_error("max_depth.max must be a positive integer"));
+                        }
+                    };
+                    config.max_depth = if enabled { Some(max) } else { None };
+                    &["enabled", "max"]
+                }
+                _ => return Err(config_error(format!("Unknown lint rule {}", rule))),
+            };
+            if let Some(key) = settings
+                .keys()
+                .find(|k| !allowed_keys.contains(&k.as_str()))
+            {
+                return Err(config_error(format!(
+                    "Unknown setting {} for lint rule {}",
+                    key, rule
+                )));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Loads the config from a file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let src = fs::read_to_string(path).map_err(|e| {
+            config_error(format!(
+                "Unable to read lint config {}: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        })?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        Self::parse(&src, base)
+    }
+
+    /// Finds the project config in dir or the closest of its parents.
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|d| d.join(CONFIG_FILE))
+            .find(|p| p.is_file())
+    }
+}
+
+/// Runs the enabled rules over the statements of a file.
+pub fn lint(config: &Config, file: &Path, stmts: &[Statement]) -> Vec<Warning> {
+    let env_allowed = match config.env_access {
+        Some(ref allowed) => allowed.iter().any(|p| file.starts_with(p)),
+        None => true,
+    };
+    let mut linter = Linter {
+        config,
+        env_allowed,
+        depth: 0,
+        dots: Vec::new(),
+        warnings: Vec::new(),
+    };
+    for stmt in stmts.iter() {
+        visit_statement(&mut linter, stmt);
+    }
+    linter.warnings
+}
+
+struct Linter<'a> {
+    config: &'a Config,
+    env_allowed: bool,
+    depth: usize,
+    // Whether each expression we are inside of is a dot selector. The
+    // direct children of a dot selector are checked by the selector itself
+    // since its right side names a field rather than a binding.
+    dots: Vec<bool>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Linter<'a> {
+    fn warn<S: Into<String>>(&mut self, rule: &'static str, msg: S, pos: &Position) {
+        self.warnings
+            .push(Warning::new(msg, WarningKind::Lint(rule), pos.clone()));
+    }
+
+    fn nests(expr: &Expression) -> bool {
+        matches!(
+            expr,
+            Expression::Copy(_)
+                | Expression::Select(_)
+                | Expression::Func(_)
+                | Expression::Module(_)
+        )
+    }
+
+    fn nests_value(val: &Value) -> bool {
+        matches!(val, Value::Tuple(_) | Value::List(_))
+    }
+
+    fn enter(&mut self, pos: &Position) {
+        self.depth += 1;
+        if let Some(max) = self.config.max_depth {
+            // Only the outermost node that is too deep is reported.
+            if self.depth == max + 1 {
+                self.warn(
+                    "max_depth",
+                    format!("Nested more than {} levels deep", max),
+                    pos,
+                );
+            }
+        }
+    }
+
+    fn check_env(&mut self, expr: &Expression) {
+        if self.env_allowed {
+            return;
+        }
+        let selector = match expr {
+            Expression::Binary(ref def) if def.kind == BinaryExprType::DOT => {
+                return self.check_env(&def.left);
+            }
+            Expression::Simple(ref v) => v,
+            Expression::Copy(ref def) => &def.selector,
+            Expression::Call(ref def) => &def.funcref,
+            _ => return,
+        };
+        if let Value::Symbol(ref s) = selector {
+            if s.val == "env" {
+                self.warn(
+                    "env_access",
+                    "env is only allowed in the files configured for env_access",
+                    &s.pos,
+                );
+            }
+        }
+    }
+}
+
+impl<'a> Visitor for Linter<'a> {
+    fn visit_statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Let(ref def) => {
+                let bad_name = match self.config.let_naming {
+                    Some(ref re) if !re.is_match(&def.name.fragment) => Some(re.to_string()),
+                    _ => None,
+                };
+                if let Some(pattern) = bad_name {
+                    self.warn(
+                        "let_naming",
+                        format!(
+                            "Binding {} doesn't match the naming pattern {}",
+                            def.name.fragment, pattern
+                        ),
+                        &def.name.pos,
+                    );
+                }
+            }
+            Statement::Assert(Expression::Simple(Value::Tuple(ref fs)))
+                if self.config.assert_desc
+                    && !fs.val.iter().any(|(name, _)| name.fragment == "desc") =>
+            {
+                self.warn("assert_desc", "Assert has no desc field", &fs.pos);
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) {
+        if Self::nests(expr) {
+            self.enter(expr.pos());
+        }
+        if self.dots.last() != Some(&true) {
+            self.check_env(expr);
+        }
+        self.dots.push(match expr {
+            Expression::Binary(ref def) => def.kind == BinaryExprType::DOT,
+            _ => false,
+        });
+        if let Expression::Debug(ref def) = expr {
+            if self.config.no_trace {
+                self.warn("no_trace", "TRACE should not be committed", &def.pos);
+            }
+        }
+    }
+
+    fn leave_expression(&mut self, expr: &Expression) {
+        if Self::nests(expr) {
+            self.depth -= 1;
+        }
+        self.dots.pop();
+    }
+
+    fn visit_value(&mut self, val: &Value) {
+        if Self::nests_value(val) {
+            self.enter(val.pos());
+        }
+    }
+
+    fn leave_value(&mut self, val: &Value) {
+        if Self::nests_value(val) {
+            self.depth -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
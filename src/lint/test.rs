@@ -0,0 +1,96 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+use super::*;
+use crate::iter::OffsetStrIter;
+use crate::parse::parse;
+
+fn lint_str(config: &Config, file: &str, src: &str) -> Vec<(String, usize, usize)> {
+    let stmts = parse(OffsetStrIter::new(src).with_src_file(file), None).unwrap();
+    lint(config, Path::new(file), &stmts)
+        .into_iter()
+        .map(|w| (w.kind.to_string(), w.pos.line, w.pos.column))
+        .collect()
+}
+
+#[test]
+fn test_default_rules() {
+    let config = Config::default();
+    assert_eq!(
+        lint_str(
+            &config,
+            "/src/main.ucg",
+            "let fooBar = 1;\nlet t = TRACE 1 + 1;\nassert {ok = true};\nassert {ok = true, desc = \"ok\"};\nlet home = env.HOME;\n",
+        ),
+        vec![
+            ("let_naming".to_string(), 1, 5),
+            ("no_trace".to_string(), 2, 9),
+            ("assert_desc".to_string(), 3, 8),
+        ]
+    );
+}
+
+#[test]
+fn test_max_depth() {
+    let config = Config {
+        max_depth: Some(2),
+        ..Config::default()
+    };
+    assert_eq!(
+        lint_str(
+            &config,
+            "/src/main.ucg",
+            "let ok = {a = [1]};\nlet deep = {a = [{b = [1]}]};\n",
+        ),
+        vec![("max_depth".to_string(), 2, 18)]
+    );
+}
+
+#[test]
+fn test_env_access() {
+    let config = Config::parse(
+        "[env_access]\nallowed = [\"env.ucg\", \"envs\"]\n[let_naming]\nenabled = false\n",
+        Path::new("/src"),
+    )
+    .unwrap();
+    assert!(config.let_naming.is_none());
+    let src = "let home = env.HOME;\nlet t = {env = 1}.env;\nlet u = {env = {x = 1}}.env.x;\nlet p = env.PATH.x;\n";
+    assert_eq!(
+        lint_str(&config, "/src/main.ucg", src),
+        vec![
+            ("env_access".to_string(), 1, 12),
+            ("env_access".to_string(), 4, 9),
+        ]
+    );
+    assert_eq!(lint_str(&config, "/src/env.ucg", src), Vec::new());
+    assert_eq!(lint_str(&config, "/src/envs/prod.ucg", src), Vec::new());
+}
+
+#[test]
+fn test_config_errors() {
+    let base = Path::new("/src");
+    for (src, msg) in [
+        ("[nope]\n", "Unknown lint rule nope"),
+        (
+            "[max_depth]\nmax = 0\n",
+            "max_depth.max must be a positive integer",
+        ),
+        (
+            "[no_trace]\nenable = false\n",
+            "Unknown setting enable for lint rule no_trace",
+        ),
+        ("no_trace = false\n", "Lint rule no_trace must be a table"),
+    ] {
+        assert_eq!(Config::parse(src, base).unwrap_err().to_string(), msg);
+    }
+}
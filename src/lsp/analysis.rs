@@ -0,0 +1,736 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Symbol resolution over the ucg AST for the language server.
+//!
+//! Everything here works on the syntax alone. Nothing is evaluated so it
+//! still works for files that fail to build.
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast::printer::AstPrinter;
+use crate::ast::*;
+use crate::build::stdlib;
+use crate::error::StackPrinter;
+use crate::iter::OffsetStrIter;
+use crate::parse::parse;
+
+// Guards against alias cycles like `let a = b; let b = a;`.
+const MAX_RESOLVE_DEPTH: usize = 32;
+
+// The most lines of a definition to show in a hover.
+const MAX_HOVER_LINES: usize = 20;
+
+/// A parsed ucg file.
+#[derive(Debug)]
+pub struct Document {
+    pub path: PathBuf,
+    pub src: String,
+    pub stmts: Vec<Statement>,
+}
+
+impl Document {
+    /// Parses src as the contents of the file at path.
+    pub fn parse<P: Into<PathBuf>>(path: P, src: &str) -> Result<Document, StackPrinter<Position>> {
+        let path = path.into();
+        let stmts = parse(OffsetStrIter::new(src).with_src_file(path.clone()), None)?;
+        Ok(Document {
+            path,
+            src: src.to_string(),
+            stmts,
+        })
+    }
+}
+
+// A name in scope along with the expression bound to it if it has one.
+// Func arguments don't.
+#[derive(Debug, Clone)]
+struct Entry {
+    name: Token,
+    value: Option<Expression>,
+}
+
+type Frame = Vec<Entry>;
+
+/// What a symbol resolved to.
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub name: Token,
+    pub value: Option<Expression>,
+    pub doc: Rc<Document>,
+    // The scope the binding was defined in. Symbols in its value are
+    // resolved against it.
+    scope: Rc<Vec<Frame>>,
+}
+
+/// The kind of a completion item.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompletionKind {
+    Variable,
+    Field,
+    Func,
+    Module,
+    File,
+}
+
+/// A single completion candidate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+fn contains(pos: &Position, line: usize, column: usize) -> bool {
+    match pos.end {
+        Some(ref end) => {
+            (pos.line, pos.column) <= (line, column) && (line, column) < (end.line, end.column)
+        }
+        None => false,
+    }
+}
+
+fn let_entries(stmts: &[Statement]) -> Frame {
+    let mut frame = Vec::new();
+    for stmt in stmts.iter() {
+        if let Statement::Let(ref def) = stmt {
+            frame.push(Entry {
+                name: def.name.clone(),
+                value: Some(def.value.clone()),
+            });
+        }
+    }
+    frame
+}
+
+fn tuple_expr(fields: &FieldList, pos: &Position) -> Expression {
+    Expression::Simple(Value::Tuple(PositionedItem::new_with_pos(
+        fields.clone(),
+        pos.clone(),
+    )))
+}
+
+fn module_frame(def: &ModuleDef) -> Frame {
+    let mut frame = vec![Entry {
+        name: Token::new_with_pos("mod", TokenType::BAREWORD, def.pos.clone()),
+        value: Some(tuple_expr(&def.arg_set, &def.pos)),
+    }];
+    frame.extend(let_entries(&def.statements));
+    frame
+}
+
+fn stmt_exprs(stmt: &Statement) -> &Expression {
+    match stmt {
+        Statement::Let(ref def) => &def.value,
+        Statement::Expression(ref expr) => expr,
+        Statement::Assert(ref expr) => expr,
+        Statement::Output(_, _, _, ref expr) => expr,
+    }
+}
+
+// The expressions directly under this one. Modules and funcs introduce
+// scopes so frames_at handles their contents itself.
+fn child_exprs(expr: &Expression) -> Vec<&Expression> {
+    let mut out = Vec::new();
+    match expr {
+        Expression::Simple(Value::Tuple(ref fs)) => {
+            out.extend(fs.val.iter().map(|(_, e)| e));
+        }
+        Expression::Simple(Value::List(ref def)) => out.extend(def.elems.iter()),
+        Expression::Simple(_) => {}
+        Expression::Binary(ref def) => {
+            out.push(def.left.as_ref());
+            out.push(def.right.as_ref());
+        }
+        Expression::Copy(ref def) => out.extend(def.fields.iter().map(|(_, e)| e)),
+        Expression::Range(ref def) => {
+            out.push(def.start.as_ref());
+            if let Some(ref step) = def.step {
+                out.push(step.as_ref());
+            }
+            out.push(def.end.as_ref());
+        }
+        Expression::Grouped(ref e, _) => out.push(e.as_ref()),
+        Expression::Format(ref def) => match def.args {
+            FormatArgs::List(ref args) => out.extend(args.iter()),
+            FormatArgs::Single(ref e) => out.push(e.as_ref()),
+        },
+        Expression::Call(ref def) => out.extend(def.arglist.iter()),
+        Expression::Select(ref def) => {
+            out.push(def.val.as_ref());
+            if let Some(ref e) = def.default {
+                out.push(e.as_ref());
+            }
+            out.extend(def.tuple.iter().map(|(_, e)| e));
+        }
+        Expression::FuncOp(FuncOpDef::Reduce(ref def)) => {
+            out.push(def.func.as_ref());
+            out.push(def.acc.as_ref());
+            out.push(def.target.as_ref());
+        }
+        Expression::FuncOp(FuncOpDef::Map(ref def))
+        | Expression::FuncOp(FuncOpDef::Filter(ref def)) => {
+            out.push(def.func.as_ref());
+            out.push(def.target.as_ref());
+        }
+        Expression::Fail(ref def) => out.push(def.message.as_ref()),
+        Expression::Not(ref def) => out.push(def.expr.as_ref()),
+        Expression::Debug(ref def) => out.push(def.expr.as_ref()),
+        Expression::Func(_)
+        | Expression::Module(_)
+        | Expression::Import(_)
+        | Expression::Include(_) => {}
+    }
+    out
+}
+
+fn expr_frames_at(expr: &Expression, line: usize, column: usize, frames: &mut Vec<Frame>) {
+    if !contains(expr.pos(), line, column) {
+        return;
+    }
+    match expr {
+        Expression::Module(ref def) => {
+            // Modules can't see anything from the scope they are defined in.
+            frames.clear();
+            frames.push(module_frame(def));
+            stmts_frames_at(&def.statements, line, column, frames);
+            if let Some(ref out) = def.out_expr {
+                expr_frames_at(out, line, column, frames);
+            }
+        }
+        Expression::Func(ref def) => {
+            frames.push(
+                def.argdefs
+                    .iter()
+                    .map(|arg| Entry {
+                        name: Token::new_with_pos(
+                            arg.val.clone(),
+                            TokenType::BAREWORD,
+                            arg.pos.clone(),
+                        ),
+                        value: None,
+                    })
+                    .collect(),
+            );
+            expr_frames_at(&def.fields, line, column, frames);
+        }
+        _ => {
+            for child in child_exprs(expr) {
+                expr_frames_at(child, line, column, frames);
+            }
+        }
+    }
+}
+
+fn stmts_frames_at(stmts: &[Statement], line: usize, column: usize, frames: &mut Vec<Frame>) {
+    for stmt in stmts.iter() {
+        expr_frames_at(stmt_exprs(stmt), line, column, frames);
+    }
+}
+
+/// The scopes visible at a position in a document from outermost to
+/// innermost.
+fn frames_at(doc: &Document, line: usize, column: usize) -> Vec<Frame> {
+    let mut frames = vec![let_entries(&doc.stmts)];
+    stmts_frames_at(&doc.stmts, line, column, &mut frames);
+    frames
+}
+
+/// The dotted selector under or just before a position in a line of source.
+#[derive(Debug, PartialEq)]
+pub struct SelectorAt {
+    /// The segments before the one the position is in.
+    pub parents: Vec<String>,
+    /// The segment the position is in. When completing this is only the
+    /// part before the position.
+    pub name: String,
+    /// The byte column where name starts. 0 based.
+    pub start: usize,
+}
+
+/// Finds the selector at the 0 based byte column in line. When
+/// whole_word is true the name extends past the column to the end of the
+/// word.
+pub fn selector_at(line: &str, column: usize, whole_word: bool) -> SelectorAt {
+    let bytes: Vec<char> = line.chars().collect();
+    let column = column.min(bytes.len());
+    let mut start = column;
+    while start > 0 && is_symbol_char(bytes[start - 1]) {
+        start -= 1;
+    }
+    let mut end = column;
+    if whole_word {
+        while end < bytes.len() && is_symbol_char(bytes[end]) {
+            end += 1;
+        }
+    }
+    let name: String = bytes[start..end].iter().collect();
+    let mut parents = Vec::new();
+    let mut curr = start;
+    while curr > 0 && bytes[curr - 1] == '.' {
+        let seg_end = curr - 1;
+        let mut seg_start = seg_end;
+        while seg_start > 0 && is_symbol_char(bytes[seg_start - 1]) {
+            seg_start -= 1;
+        }
+        if seg_start == seg_end {
+            break;
+        }
+        parents.insert(0, bytes[seg_start..seg_end].iter().collect());
+        curr = seg_start;
+    }
+    SelectorAt {
+        parents,
+        name,
+        start,
+    }
+}
+
+/// If the text before a position ends inside the field list of a copy
+/// expression where a field name is expected returns the selector being
+/// copied.
+fn copy_selector_before(text: &str) -> Option<SelectorAt> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut depth = 0;
+    // Set once we have walked back past the start of the current field.
+    let mut past_field = false;
+    let mut i = chars.len();
+    while i > 0 {
+        i -= 1;
+        match chars[i] {
+            '}' | ')' | ']' => depth += 1,
+            '(' | '[' if depth == 0 => return None,
+            '(' | '[' => depth -= 1,
+            '{' if depth > 0 => depth -= 1,
+            '{' => {
+                let before: String = chars[..i].iter().collect();
+                let trimmed = before.trim_end();
+                let selector = selector_at(trimmed, trimmed.len(), false);
+                if selector.name.is_empty() || selector.name == "module" {
+                    return None;
+                }
+                return Some(selector);
+            }
+            ',' if depth == 0 => past_field = true,
+            '=' if depth == 0 && !past_field => {
+                // `==`, `!=`, `<=`, `>=` and `=>` are operators not field
+                // assignments.
+                let prev = if i > 0 { chars[i - 1] } else { ' ' };
+                let next = chars.get(i + 1).cloned().unwrap_or(' ');
+                if !"=!<>".contains(prev) && next != '=' && next != '>' {
+                    // We are in the value of a field.
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn completion_kind(value: &Option<Expression>, default: CompletionKind) -> CompletionKind {
+    match value {
+        Some(Expression::Func(_)) => CompletionKind::Func,
+        Some(Expression::Module(_)) | Some(Expression::Import(_)) => CompletionKind::Module,
+        _ => default,
+    }
+}
+
+/// Resolves symbols across a document and the files it imports.
+pub struct Analyzer {
+    import_paths: Vec<PathBuf>,
+    std: HashMap<String, &'static str>,
+    // Documents open in the editor. These take precedence over the disk.
+    open: BTreeMap<PathBuf, Rc<Document>>,
+    // Imported documents read from disk or the std library.
+    loaded: RefCell<BTreeMap<PathBuf, Option<Rc<Document>>>>,
+}
+
+impl Analyzer {
+    pub fn new(import_paths: Vec<PathBuf>) -> Self {
+        Analyzer {
+            import_paths,
+            std: stdlib::get_libs(),
+            open: BTreeMap::new(),
+            loaded: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records the last document that parsed for a path open in the editor.
+    pub fn set_open(&mut self, doc: Rc<Document>) {
+        self.open.insert(doc.path.clone(), doc);
+        // Files on disk may have changed as well.
+        self.loaded.borrow_mut().clear();
+    }
+
+    pub fn close(&mut self, path: &Path) {
+        self.open.remove(path);
+    }
+
+    pub fn get_open(&self, path: &Path) -> Option<Rc<Document>> {
+        self.open.get(path).cloned()
+    }
+
+    /// The names of the std library files sorted.
+    pub fn std_lib_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.std.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn load(&self, path: &Path) -> Option<Rc<Document>> {
+        if let Some(doc) = self.open.get(path) {
+            return Some(doc.clone());
+        }
+        if let Some(doc) = self.loaded.borrow().get(path) {
+            return doc.clone();
+        }
+        let src = match self.std.get(path.to_string_lossy().as_ref()) {
+            Some(src) => Some(src.to_string()),
+            None => std::fs::read_to_string(path).ok(),
+        };
+        let doc = src
+            .and_then(|src| Document::parse(path, &src).ok())
+            .map(Rc::new);
+        self.loaded
+            .borrow_mut()
+            .insert(path.to_path_buf(), doc.clone());
+        doc
+    }
+
+    fn import_target(&self, from: &Document, path: &str) -> Option<PathBuf> {
+        if path.starts_with("std/") {
+            return if self.std.contains_key(path) {
+                Some(PathBuf::from(path))
+            } else {
+                None
+            };
+        }
+        let rel = PathBuf::from(path);
+        if rel.is_absolute() {
+            return Some(rel);
+        }
+        if let Some(dir) = from.path.parent() {
+            let candidate = dir.join(&rel);
+            if candidate.exists() {
+                return candidate.canonicalize().ok();
+            }
+        }
+        for dir in self.import_paths.iter() {
+            let candidate = dir.join(&rel);
+            if candidate.exists() {
+                return candidate.canonicalize().ok();
+            }
+        }
+        None
+    }
+
+    fn lookup(&self, name: &str, doc: &Rc<Document>, scope: &[Frame]) -> Option<Binding> {
+        for (i, frame) in scope.iter().enumerate().rev() {
+            if let Some(entry) = frame.iter().find(|e| e.name.fragment == name) {
+                return Some(Binding {
+                    name: entry.name.clone(),
+                    value: entry.value.clone(),
+                    doc: doc.clone(),
+                    scope: Rc::new(scope[..=i].to_vec()),
+                });
+            }
+        }
+        None
+    }
+
+    fn bindings_for(doc: &Rc<Document>, scope: &Rc<Vec<Frame>>, frame: Frame) -> Vec<Binding> {
+        frame
+            .into_iter()
+            .map(|e| Binding {
+                name: e.name,
+                value: e.value,
+                doc: doc.clone(),
+                scope: scope.clone(),
+            })
+            .collect()
+    }
+
+    // The members of the value an expression evaluates to as best as we can
+    // tell without evaluating it.
+    fn expr_members(
+        &self,
+        expr: &Expression,
+        doc: &Rc<Document>,
+        scope: &Rc<Vec<Frame>>,
+        depth: usize,
+    ) -> Vec<Binding> {
+        if depth > MAX_RESOLVE_DEPTH {
+            return Vec::new();
+        }
+        match expr {
+            Expression::Simple(Value::Tuple(ref fs)) => Self::bindings_for(
+                doc,
+                scope,
+                fs.val
+                    .iter()
+                    .map(|(name, e)| Entry {
+                        name: name.clone(),
+                        value: Some(e.clone()),
+                    })
+                    .collect(),
+            ),
+            Expression::Simple(Value::Symbol(ref sym)) => match self.lookup(&sym.val, doc, scope) {
+                Some(b) => self.members_at_depth(&b, depth + 1),
+                None => Vec::new(),
+            },
+            Expression::Grouped(ref e, _) => self.expr_members(e, doc, scope, depth + 1),
+            Expression::Import(ref def) => {
+                let imported = self
+                    .import_target(doc, &def.path.fragment)
+                    .and_then(|p| self.load(&p));
+                match imported {
+                    Some(imported) => {
+                        let frame = let_entries(&imported.stmts);
+                        let scope = Rc::new(vec![frame.clone()]);
+                        Self::bindings_for(&imported, &scope, frame)
+                    }
+                    None => Vec::new(),
+                }
+            }
+            Expression::Copy(ref def) => {
+                let base = match def.selector {
+                    Value::Symbol(ref sym) => self.lookup(&sym.val, doc, scope),
+                    _ => None,
+                };
+                self.copy_members(base, def, doc, scope, depth)
+            }
+            Expression::Binary(ref def) if def.kind == BinaryExprType::DOT => {
+                let parents = self.expr_members(&def.left, doc, scope, depth + 1);
+                match def.right.as_ref() {
+                    Expression::Simple(Value::Symbol(ref sym)) => {
+                        match parents.into_iter().find(|b| b.name.fragment == sym.val) {
+                            Some(b) => self.members_at_depth(&b, depth + 1),
+                            None => Vec::new(),
+                        }
+                    }
+                    Expression::Copy(ref copy) => {
+                        let base = match copy.selector {
+                            Value::Symbol(ref sym) => {
+                                parents.into_iter().find(|b| b.name.fragment == sym.val)
+                            }
+                            _ => None,
+                        };
+                        self.copy_members(base, copy, doc, scope, depth)
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn copy_members(
+        &self,
+        base: Option<Binding>,
+        def: &CopyDef,
+        doc: &Rc<Document>,
+        scope: &Rc<Vec<Frame>>,
+        depth: usize,
+    ) -> Vec<Binding> {
+        let base = match base {
+            Some(base) => base,
+            None => return Vec::new(),
+        };
+        if let Some(Expression::Module(ref module)) = base.value {
+            // Copying a module produces its result.
+            let scope = Rc::new(vec![module_frame(module)]);
+            return match module.out_expr {
+                Some(ref out) => self.expr_members(out, &base.doc, &scope, depth + 1),
+                None => Self::bindings_for(&base.doc, &scope, let_entries(&module.statements)),
+            };
+        }
+        let mut members = self.members_at_depth(&base, depth + 1);
+        for (name, e) in def.fields.iter() {
+            members.retain(|b| b.name.fragment != name.fragment);
+            members.push(Binding {
+                name: name.clone(),
+                value: Some(e.clone()),
+                doc: doc.clone(),
+                scope: scope.clone(),
+            });
+        }
+        members
+    }
+
+    fn members_at_depth(&self, binding: &Binding, depth: usize) -> Vec<Binding> {
+        match binding.value {
+            Some(ref value) => self.expr_members(value, &binding.doc, &binding.scope, depth),
+            None => Vec::new(),
+        }
+    }
+
+    /// The fields of whatever the binding is bound to.
+    pub fn members(&self, binding: &Binding) -> Vec<Binding> {
+        self.members_at_depth(binding, 0)
+    }
+
+    fn resolve_path(
+        &self,
+        doc: &Rc<Document>,
+        line: usize,
+        column: usize,
+        parents: &[String],
+    ) -> Option<Vec<Binding>> {
+        let frames = frames_at(doc, line, column);
+        let mut candidates: Vec<Binding> = Vec::new();
+        // Inner scopes come first so that they shadow outer ones.
+        for (i, frame) in frames.iter().enumerate().rev() {
+            let scope = Rc::new(frames[..=i].to_vec());
+            candidates.extend(Self::bindings_for(doc, &scope, frame.clone()));
+        }
+        for name in parents.iter() {
+            let found = candidates.into_iter().find(|b| &b.name.fragment == name)?;
+            candidates = self.members(&found);
+        }
+        Some(candidates)
+    }
+
+    /// Resolves the symbol at the 1 based line and byte column of a document
+    /// to its binding.
+    pub fn definition(&self, doc: &Rc<Document>, line: usize, column: usize) -> Option<Binding> {
+        let text = doc.src.lines().nth(line.checked_sub(1)?)?;
+        let selector = selector_at(text, column.checked_sub(1)?, true);
+        if selector.name.is_empty() {
+            return None;
+        }
+        self.resolve_path(doc, line, column, &selector.parents)?
+            .into_iter()
+            .find(|b| b.name.fragment == selector.name)
+    }
+
+    /// Hover text for the symbol at the 1 based line and byte column of a
+    /// document.
+    pub fn hover(&self, doc: &Rc<Document>, line: usize, column: usize) -> Option<String> {
+        let binding = self.definition(doc, line, column)?;
+        let value = match binding.value {
+            Some(ref value) => value,
+            None => return Some(format!("`{}` is a func argument", binding.name.fragment)),
+        };
+        let mut buffer: Vec<u8> = Vec::new();
+        {
+            let mut printer = AstPrinter::new(4, &mut buffer);
+            printer.render_expr(value).ok()?;
+        }
+        let rendered = String::from_utf8_lossy(&buffer).to_string();
+        let mut lines: Vec<&str> = rendered.lines().collect();
+        if lines.len() > MAX_HOVER_LINES {
+            lines.truncate(MAX_HOVER_LINES);
+            lines.push("...");
+        }
+        Some(format!(
+            "```ucg\n{} = {}\n```",
+            binding.name.fragment,
+            lines.join("\n")
+        ))
+    }
+
+    /// Completion candidates for the 1 based line and byte column in src.
+    ///
+    /// The doc is the last version of src that parsed. It is used to
+    /// resolve symbols even when src itself doesn't currently parse.
+    pub fn complete(
+        &self,
+        doc: &Rc<Document>,
+        src: &str,
+        line: usize,
+        column: usize,
+    ) -> Vec<Completion> {
+        let text = src.lines().nth(line.saturating_sub(1)).unwrap_or("");
+        let col0 = column.saturating_sub(1).min(text.len());
+        let before = &text[..col0];
+        if let Some(idx) = before.rfind("import") {
+            let rest = before[idx + "import".len()..].trim_start();
+            if rest.starts_with('"') && !rest[1..].contains('"') {
+                return self
+                    .std_lib_names()
+                    .into_iter()
+                    .filter(|n| n.starts_with(&rest[1..]))
+                    .map(|n| Completion {
+                        label: n,
+                        kind: CompletionKind::File,
+                    })
+                    .collect();
+            }
+        }
+        let selector = selector_at(text, col0, false);
+        let (candidates, default_kind) = if !selector.parents.is_empty() {
+            (
+                self.resolve_path(doc, line, column, &selector.parents),
+                CompletionKind::Field,
+            )
+        } else {
+            let offset: usize = src
+                .lines()
+                .take(line.saturating_sub(1))
+                .map(|l| l.len() + 1)
+                .sum::<usize>()
+                + selector.start;
+            match copy_selector_before(&src[..offset.min(src.len())]) {
+                Some(copied) => {
+                    let mut path = copied.parents.clone();
+                    path.push(copied.name.clone());
+                    let copied_line = line;
+                    let last = path.pop().unwrap();
+                    let base = self
+                        .resolve_path(doc, copied_line, column, &path)
+                        .and_then(|c| c.into_iter().find(|b| b.name.fragment == last));
+                    let members = base.map(|b| match b.value {
+                        Some(Expression::Module(ref module)) => Self::bindings_for(
+                            &b.doc,
+                            &b.scope,
+                            module
+                                .arg_set
+                                .iter()
+                                .map(|(name, e)| Entry {
+                                    name: name.clone(),
+                                    value: Some(e.clone()),
+                                })
+                                .collect(),
+                        ),
+                        _ => self.members(&b),
+                    });
+                    (members, CompletionKind::Field)
+                }
+                None => (
+                    self.resolve_path(doc, line, column, &[]),
+                    CompletionKind::Variable,
+                ),
+            }
+        };
+        let mut seen = Vec::new();
+        let mut out = Vec::new();
+        for b in candidates.unwrap_or_default() {
+            if !b.name.fragment.starts_with(&selector.name) || seen.contains(&b.name.fragment) {
+                continue;
+            }
+            seen.push(b.name.fragment.clone());
+            out.push(Completion {
+                kind: completion_kind(&b.value, default_kind),
+                label: b.name.fragment,
+            });
+        }
+        out
+    }
+}
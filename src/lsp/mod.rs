@@ -0,0 +1,499 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! A language server for ucg speaking JSON-RPC over stdio.
+//!
+//! It supports publishing diagnostics, hover, go to definition and
+//! completion. Documents are synced in full on every change.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+
+use crate::build::assets::MemoryCache;
+use crate::build::{FileBuilder, DEFAULT_MAX_CALL_DEPTH};
+use crate::error::{individual_errors, to_json_diagnostic};
+
+pub mod analysis;
+
+use analysis::{Analyzer, CompletionKind, Document};
+
+// Documents are rebuilt as they are typed so half finished input can't be
+// allowed to run away with the editor.
+const MAX_STEPS: u64 = 1_000_000;
+const MAX_RANGE: usize = 100_000;
+const MAX_SIZE: usize = 1_000_000;
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// A JSON-RPC error code and message.
+type RpcError = (i64, String);
+
+// An open document, its text and a 1 based line and byte column in it.
+type Location<'a> = (Rc<Document>, &'a str, usize, usize);
+
+/// Reads a single Content-Length framed message. Returns None at the end of
+/// the input.
+pub fn read_message<R: BufRead>(r: &mut R) -> Result<Option<Value>, Box<dyn Error>> {
+    match read_body(r)? {
+        Some(body) => Ok(Some(serde_json::from_slice(&body)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the body of a single Content-Length framed message without parsing
+/// it. Returns None at the end of the input.
+pub fn read_body<R: BufRead>(r: &mut R) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if r.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let mut parts = header.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.parse::<usize>()?);
+        }
+    }
+    let len = match content_length {
+        Some(len) => len,
+        None => return Err("Missing Content-Length header".into()),
+    };
+    let mut body = vec![0; len];
+    r.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+/// Writes a single Content-Length framed message.
+pub fn write_message<W: Write>(w: &mut W, msg: &Value) -> Result<(), Box<dyn Error>> {
+    let body = serde_json::to_string(msg)?;
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Converts a file uri to a path.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    if !uri.starts_with("file://") {
+        return None;
+    }
+    let encoded = &uri.as_bytes()["file://".len()..];
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%' && i + 2 < encoded.len() {
+            let hex = std::str::from_utf8(&encoded[i + 1..i + 3]).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+/// Converts a path to a file uri.
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(b as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+// LSP positions count utf-16 code units from 0. Ours count bytes from 1.
+fn byte_column(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= character {
+            return idx + 1;
+        }
+        units += c.len_utf16();
+    }
+    line.len() + 1
+}
+
+fn utf16_character(line: &str, column: usize) -> usize {
+    let end = column.saturating_sub(1).min(line.len());
+    line.char_indices()
+        .take_while(|&(idx, _)| idx < end)
+        .map(|(_, c)| c.len_utf16())
+        .sum()
+}
+
+fn lsp_position(src: &str, line: usize, column: usize) -> Value {
+    let text = src.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    json!({
+        "line": line.saturating_sub(1),
+        "character": utf16_character(text, column),
+    })
+}
+
+fn completion_item_kind(kind: CompletionKind) -> u64 {
+    match kind {
+        CompletionKind::Func => 3,
+        CompletionKind::Field => 5,
+        CompletionKind::Variable => 6,
+        CompletionKind::Module => 9,
+        CompletionKind::File => 17,
+    }
+}
+
+// Converts a build error into an LSP diagnostic for the file at path.
+fn diagnostic(path: &Path, src: &str, err: &(dyn Error + 'static)) -> Value {
+    let diagnostic = to_json_diagnostic(err);
+    let mut entries = vec![diagnostic.clone()];
+    if let Some(causes) = diagnostic["causes"].as_array() {
+        entries.extend(causes.iter().cloned());
+    }
+    // The innermost error located in this file is the most precise place
+    // to report. Errors in imported files are reported at the import.
+    let file = path.to_string_lossy();
+    let in_file = |e: &Value| e["file"].as_str() == Some(file.as_ref()) && e["line"].is_u64();
+    let idx = entries.iter().rposition(&in_file);
+    // Otherwise the error happened in a func or module from another file so
+    // report the innermost call in this file.
+    let call_site = match (idx, diagnostic["trace"].as_array()) {
+        (None, Some(trace)) => trace
+            .iter()
+            .map(|frame| &frame["call_site"])
+            .find(|site| in_file(site))
+            .cloned(),
+        _ => None,
+    };
+    let idx = idx.unwrap_or(0);
+    let mut entry = entries[idx].clone();
+    if let Some(site) = call_site {
+        entry["line"] = site["line"].clone();
+        entry["column"] = site["column"].clone();
+        entry["end_line"] = Value::Null;
+        entry["end_column"] = Value::Null;
+    }
+    let mut message = entry["message"].as_str().unwrap_or("").to_string();
+    for cause in entries[idx + 1..].iter() {
+        message.push_str(&format!(
+            "\ncaused by: {}",
+            cause["message"].as_str().unwrap_or("")
+        ));
+    }
+    let line = entry["line"].as_u64().unwrap_or(1) as usize;
+    let column = entry["column"].as_u64().unwrap_or(1) as usize;
+    let (end_line, end_column) = match (entry["end_line"].as_u64(), entry["end_column"].as_u64()) {
+        (Some(l), Some(c)) => (l as usize, c as usize),
+        _ => (line, column + 1),
+    };
+    json!({
+        "range": {
+            "start": lsp_position(src, line, column),
+            "end": lsp_position(src, end_line, end_column),
+        },
+        "severity": 1,
+        "source": "ucg",
+        "code": entry["type"],
+        "message": message,
+    })
+}
+
+/// A language server for ucg files.
+pub struct Server {
+    import_paths: Vec<PathBuf>,
+    analyzer: Analyzer,
+    // The current text of every open document.
+    texts: BTreeMap<PathBuf, String>,
+    max_call_depth: usize,
+    shutdown: bool,
+    exit: bool,
+}
+
+impl Server {
+    pub fn new(import_paths: Vec<PathBuf>) -> Self {
+        Server {
+            analyzer: Analyzer::new(import_paths.clone()),
+            import_paths,
+            texts: BTreeMap::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            shutdown: false,
+            exit: false,
+        }
+    }
+
+    /// Sets how deeply func calls may nest when building open documents.
+    /// See `FileBuilder::set_max_call_depth`.
+    pub fn set_max_call_depth(&mut self, to: usize) {
+        self.max_call_depth = to;
+    }
+
+    /// Serves requests from r writing the responses to w until the client
+    /// sends exit or closes the input.
+    pub fn run<R: BufRead, W: Write>(&mut self, mut r: R, mut w: W) -> Result<(), Box<dyn Error>> {
+        while !self.exit {
+            let body = match read_body(&mut r)? {
+                Some(body) => body,
+                None => break,
+            };
+            // A body that isn't json gets an error but the framing is still
+            // intact so we keep serving.
+            let msg: Value = match serde_json::from_slice(&body) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    let out = json!({
+                        "jsonrpc": "2.0",
+                        "id": Value::Null,
+                        "error": {"code": PARSE_ERROR, "message": e.to_string()},
+                    });
+                    write_message(&mut w, &out)?;
+                    continue;
+                }
+            };
+            for out in self.handle(&msg) {
+                write_message(&mut w, &out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the client asked us to shut down before exiting.
+    pub fn clean_shutdown(&self) -> bool {
+        self.shutdown
+    }
+
+    /// Handles a single message returning any responses and notifications
+    /// to send back to the client.
+    pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let method = msg["method"].as_str().unwrap_or("");
+        let params = &msg["params"];
+        let id = match msg.get("id") {
+            Some(id) => id.clone(),
+            None => return self.handle_notification(method, params),
+        };
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                },
+                "serverInfo": { "name": "ucg", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/completion" => self.completion(params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        };
+        vec![match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": code, "message": message},
+            }),
+        }]
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let path = params["textDocument"]["uri"].as_str().and_then(uri_to_path);
+        match (method, path) {
+            ("exit", _) => {
+                self.exit = true;
+                Vec::new()
+            }
+            ("textDocument/didOpen", Some(path)) => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.update(path, text.to_string())
+            }
+            ("textDocument/didChange", Some(path)) => {
+                // We only advertise full sync so the last change has the
+                // whole document.
+                let changes = params["contentChanges"].as_array();
+                match changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    Some(text) => self.update(path, text.to_string()),
+                    None => Vec::new(),
+                }
+            }
+            ("textDocument/didSave", Some(path)) => match self.texts.get(&path).cloned() {
+                Some(text) => self.update(path, text),
+                None => Vec::new(),
+            },
+            ("textDocument/didClose", Some(path)) => {
+                self.texts.remove(&path);
+                self.analyzer.close(&path);
+                vec![self.publish(&path, Vec::new())]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn update(&mut self, path: PathBuf, text: String) -> Vec<Value> {
+        // If the new text doesn't parse we keep the last version that did
+        // so hover and friends keep working while the user types.
+        if let Ok(doc) = Document::parse(path.clone(), &text) {
+            self.analyzer.set_open(Rc::new(doc));
+        }
+        self.texts.insert(path.clone(), text);
+        let diagnostics = self.diagnostics(&path);
+        vec![self.publish(&path, diagnostics)]
+    }
+
+    fn publish(&self, path: &Path, diagnostics: Vec<Value>) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": path_to_uri(path), "diagnostics": diagnostics},
+        })
+    }
+
+    fn diagnostics(&self, path: &Path) -> Vec<Value> {
+        let src = match self.texts.get(path) {
+            Some(src) => src,
+            None => return Vec::new(),
+        };
+        let cache = Rc::new(RefCell::new(MemoryCache::new()));
+        let mut builder = FileBuilder::new(
+            path.parent().unwrap_or(Path::new(".")),
+            &self.import_paths,
+            cache,
+        );
+        builder.set_keep_going(true);
+        builder.set_max_call_depth(self.max_call_depth);
+        builder.set_max_steps(MAX_STEPS);
+        builder.set_max_range(MAX_RANGE);
+        builder.set_max_size(MAX_SIZE);
+        let mut diagnostics: Vec<Value> = match builder.build_str(path, src) {
+            Ok(_) => Vec::new(),
+            Err(err) => individual_errors(err.as_ref())
+                .into_iter()
+                .map(|err| diagnostic(path, src, err))
+                .collect(),
+        };
+        // Warnings in imported files are reported when those are open.
+        for warning in builder.warnings() {
+            if warning.pos.file.as_deref() != Some(path) {
+                continue;
+            }
+            let pos = &warning.pos;
+            diagnostics.push(json!({
+                "range": {
+                    "start": lsp_position(src, pos.line, pos.column),
+                    "end": match pos.end {
+                        Some(end) => lsp_position(src, end.line, end.column),
+                        None => lsp_position(src, pos.line, pos.column + 1),
+                    },
+                },
+                "severity": 2,
+                "source": "ucg",
+                "code": warning.kind.to_string(),
+                "message": warning.msg,
+            }));
+        }
+        diagnostics
+    }
+
+    // Finds the document, its text and the 1 based line and byte column a
+    // request refers to.
+    fn locate(&self, params: &Value) -> Result<Location<'_>, RpcError> {
+        let path = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(uri_to_path)
+            .ok_or_else(|| (INVALID_PARAMS, "Invalid textDocument uri".to_string()))?;
+        let line = params["position"]["line"]
+            .as_u64()
+            .ok_or_else(|| (INVALID_PARAMS, "Invalid position".to_string()))?
+            as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+        let (doc, src) = match (self.analyzer.get_open(&path), self.texts.get(&path)) {
+            (Some(doc), Some(src)) => (doc, src.as_str()),
+            _ => return Err((INVALID_PARAMS, format!("{} is not open", path.display()))),
+        };
+        let text = src.lines().nth(line).unwrap_or("");
+        Ok((doc, src, line + 1, byte_column(text, character)))
+    }
+
+    fn hover(&self, params: &Value) -> Result<Value, RpcError> {
+        let (doc, _, line, column) = match self.locate(params) {
+            Ok(found) => found,
+            Err(_) => return Ok(Value::Null),
+        };
+        Ok(match self.analyzer.hover(&doc, line, column) {
+            Some(value) => json!({"contents": {"kind": "markdown", "value": value}}),
+            None => Value::Null,
+        })
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, RpcError> {
+        let (doc, _, line, column) = match self.locate(params) {
+            Ok(found) => found,
+            Err(_) => return Ok(Value::Null),
+        };
+        let binding = match self.analyzer.definition(&doc, line, column) {
+            Some(binding) => binding,
+            None => return Ok(Value::Null),
+        };
+        // The std library has no files to jump to.
+        if !binding.doc.path.is_absolute() {
+            return Ok(Value::Null);
+        }
+        let pos = &binding.name.pos;
+        let src = &binding.doc.src;
+        Ok(json!({
+            "uri": path_to_uri(&binding.doc.path),
+            "range": {
+                "start": lsp_position(src, pos.line, pos.column),
+                "end": lsp_position(src, pos.line, pos.column + binding.name.fragment.len()),
+            },
+        }))
+    }
+
+    fn completion(&self, params: &Value) -> Result<Value, RpcError> {
+        let (doc, src, line, column) = match self.locate(params) {
+            Ok(found) => found,
+            Err(_) => return Ok(Value::Array(Vec::new())),
+        };
+        let items = self
+            .analyzer
+            .complete(&doc, src, line, column)
+            .into_iter()
+            .map(|c| json!({"label": c.label, "kind": completion_item_kind(c.kind)}))
+            .collect();
+        Ok(Value::Array(items))
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,242 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+use std::io::Cursor;
+
+use super::analysis::{selector_at, SelectorAt};
+use super::*;
+
+const URI: &str = "file:///tmp/ucg%20lsp/main.ucg";
+
+fn open(server: &mut Server, text: &str) -> Vec<Value> {
+    server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {"textDocument": {"uri": URI, "languageId": "ucg", "version": 1, "text": text}},
+    }))
+}
+
+fn request(server: &mut Server, method: &str, line: usize, character: usize) -> Value {
+    let mut out = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": {
+            "textDocument": {"uri": URI},
+            "position": {"line": line, "character": character},
+        },
+    }));
+    assert_eq!(out.len(), 1);
+    out.remove(0)
+}
+
+fn labels(completions: &Value) -> Vec<String> {
+    completions["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["label"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn test_uri_round_trip() {
+    let path = uri_to_path(URI).unwrap();
+    assert_eq!(path, PathBuf::from("/tmp/ucg lsp/main.ucg"));
+    assert_eq!(path_to_uri(&path), URI);
+}
+
+#[test]
+fn test_message_framing() {
+    let mut buffer: Vec<u8> = Vec::new();
+    write_message(&mut buffer, &json!({"id": 1})).unwrap();
+    assert_eq!(
+        String::from_utf8(buffer.clone()).unwrap(),
+        "Content-Length: 8\r\n\r\n{\"id\":1}"
+    );
+    let mut r = Cursor::new(buffer);
+    assert_eq!(read_message(&mut r).unwrap(), Some(json!({"id": 1})));
+    assert_eq!(read_message(&mut r).unwrap(), None);
+}
+
+#[test]
+fn test_malformed_message_keeps_serving() {
+    let mut input: Vec<u8> = Vec::new();
+    input.extend_from_slice(b"Content-Length: 5\r\n\r\n{nope");
+    write_message(
+        &mut input,
+        &json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}),
+    )
+    .unwrap();
+    write_message(&mut input, &json!({"jsonrpc": "2.0", "method": "exit"})).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    let mut server = Server::new(Vec::new());
+    server.run(Cursor::new(input), &mut output).unwrap();
+    assert!(server.clean_shutdown());
+    let mut r = Cursor::new(output);
+    let out = read_message(&mut r).unwrap().unwrap();
+    assert_eq!(out["id"], Value::Null);
+    assert_eq!(out["error"]["code"], PARSE_ERROR);
+    let out = read_message(&mut r).unwrap().unwrap();
+    assert_eq!(out["id"], 2);
+    assert_eq!(read_message(&mut r).unwrap(), None);
+}
+
+#[test]
+fn test_selector_at() {
+    assert_eq!(
+        selector_at("let x = foo.bar.baz;", 13, true),
+        SelectorAt {
+            parents: vec!["foo".to_string()],
+            name: "bar".to_string(),
+            start: 12,
+        }
+    );
+    assert_eq!(
+        selector_at("foo.ba", 6, false),
+        SelectorAt {
+            parents: vec!["foo".to_string()],
+            name: "ba".to_string(),
+            start: 4,
+        }
+    );
+}
+
+#[test]
+fn test_initialize_and_unknown_method() {
+    let mut server = Server::new(Vec::new());
+    let out =
+        server.handle(&json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}));
+    assert_eq!(out[0]["result"]["capabilities"]["hoverProvider"], true);
+    assert_eq!(out[0]["result"]["capabilities"]["textDocumentSync"], 1);
+    let out = server.handle(&json!({"jsonrpc": "2.0", "id": 2, "method": "nope"}));
+    assert_eq!(out[0]["error"]["code"], METHOD_NOT_FOUND);
+}
+
+#[test]
+fn test_diagnostics_published_on_open() {
+    let mut server = Server::new(Vec::new());
+    let out = open(&mut server, "let x = 1;\nlet y = x + \"a\";\n");
+    assert_eq!(out[0]["method"], "textDocument/publishDiagnostics");
+    assert_eq!(out[0]["params"]["uri"], URI);
+    let diagnostics = out[0]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 1);
+
+    // Every independent error is published.
+    let out = open(&mut server, "let x = 1 + \"a\";\nlet y = nope;\n");
+    let diagnostics = out[0]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[1]["range"]["start"]["line"], 1);
+
+    let out = open(&mut server, "let x = 1;\n");
+    assert_eq!(out[0]["params"]["diagnostics"], json!([]));
+}
+
+#[test]
+fn test_diagnostics_are_limited() {
+    let mut server = Server::new(Vec::new());
+    let out = open(&mut server, "let r = 0:100000000000;\n");
+    let diagnostics = out[0]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["code"], "LimitExceeded");
+}
+
+#[test]
+fn test_warnings_published() {
+    let mut server = Server::new(Vec::new());
+    let out = open(&mut server, "let x = 1;\nlet y = 2;\nout json x;\n");
+    let diagnostics = out[0]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 2);
+    assert_eq!(diagnostics[0]["code"], "UnusedBinding");
+    assert_eq!(
+        diagnostics[0]["range"],
+        json!({"start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 5}})
+    );
+}
+
+#[test]
+fn test_definition_and_hover() {
+    let mut server = Server::new(Vec::new());
+    open(
+        &mut server,
+        "let tpl = {\n    name = \"foo\",\n};\nlet n = tpl.name;\n",
+    );
+    let out = request(&mut server, "textDocument/definition", 3, 13);
+    assert_eq!(out["result"]["uri"], URI);
+    assert_eq!(
+        out["result"]["range"]["start"],
+        json!({"line": 1, "character": 4})
+    );
+    assert_eq!(
+        out["result"]["range"]["end"],
+        json!({"line": 1, "character": 8})
+    );
+
+    let out = request(&mut server, "textDocument/hover", 3, 9);
+    let hover = out["result"]["contents"]["value"].as_str().unwrap();
+    assert!(hover.starts_with("```ucg\ntpl = {"), "{}", hover);
+}
+
+#[test]
+fn test_definition_of_func_arg() {
+    let mut server = Server::new(Vec::new());
+    open(&mut server, "let f = func(arg) => arg;\n");
+    let out = request(&mut server, "textDocument/definition", 0, 22);
+    assert_eq!(
+        out["result"]["range"]["start"],
+        json!({"line": 0, "character": 13})
+    );
+}
+
+#[test]
+fn test_completion() {
+    let mut server = Server::new(Vec::new());
+    open(
+        &mut server,
+        "let tpl = {\n    name = \"foo\",\n    port = 80,\n};\nlet mk = module {\n    host = NULL,\n} => {\n    let out = mod.host;\n};\nlet n = tpl.name;\n",
+    );
+    // Field names after a dot.
+    let out = request(&mut server, "textDocument/completion", 9, 12);
+    assert_eq!(labels(&out), vec!["name", "port"]);
+    // Everything in scope.
+    let out = request(&mut server, "textDocument/completion", 9, 8);
+    assert_eq!(labels(&out), vec!["tpl", "mk", "n"]);
+    // The module parameters.
+    let out = request(&mut server, "textDocument/completion", 7, 18);
+    assert_eq!(labels(&out), vec!["host"]);
+}
+
+#[test]
+fn test_completion_in_copy_and_import() {
+    let mut server = Server::new(Vec::new());
+    open(
+        &mut server,
+        "let mk = module {\n    host = NULL,\n} => {\n};\n",
+    );
+    // The document doesn't have to parse for completion to work.
+    server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": {"uri": URI, "version": 2},
+            "contentChanges": [{"text": "let mk = module {\n    host = NULL,\n} => {\n};\nlet x = mk{host = 1, ho\nlet s = import \"std/li"}],
+        },
+    }));
+    let out = request(&mut server, "textDocument/completion", 4, 23);
+    assert_eq!(labels(&out), vec!["host"]);
+    let out = request(&mut server, "textDocument/completion", 5, 21);
+    assert_eq!(labels(&out), vec!["std/lists.ucg"]);
+}
@@ -17,18 +17,85 @@ extern crate dirs;
 extern crate ucglib;
 
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 use std::fs::File;
 use std::io;
+use std::io::{BufRead, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process;
 use std::rc::Rc;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
+use ucglib::ast::printer::AstPrinter;
 use ucglib::build;
 use ucglib::build::assets::{Cache, MemoryCache};
+use ucglib::build::report::{self, FileResults};
 use ucglib::build::Val;
 use ucglib::convert::traits;
 use ucglib::convert::{ConverterRegistry, ImporterRegistry};
+use ucglib::error::{
+    individual_errors, render_warning, render_with_source, to_json_diagnostic, warning_to_json,
+    BuildError, ErrorType, Warning,
+};
+use ucglib::iter::OffsetStrIter;
+use ucglib::lint;
+use ucglib::lsp;
+use ucglib::parse::parse;
+use ucglib::repl;
+use ucglib::tokenizer::CommentMap;
+use ucglib::typecheck;
+
+/// How errors get reported to the user.
+#[derive(Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl ErrorFormat {
+    fn report(self, err: &(dyn Error + 'static)) {
+        match self {
+            ErrorFormat::Human => eprint!("{}", render_with_source(err)),
+            ErrorFormat::Json => {
+                for err in individual_errors(err) {
+                    eprintln!("{}", to_json_diagnostic(err));
+                }
+            }
+        }
+    }
+
+    fn report_warning(self, warning: &Warning) {
+        match self {
+            ErrorFormat::Human => eprint!("{}", render_warning(warning)),
+            ErrorFormat::Json => eprintln!("{}", warning_to_json(warning)),
+        }
+    }
+}
+
+/// Settings shared by the commands that build ucg files.
+#[derive(Clone, Copy)]
+struct BuildOpts<'a> {
+    strict: bool,
+    keep_going: bool,
+    deny_warnings: bool,
+    max_call_depth: usize,
+    max_steps: Option<u64>,
+    max_range: Option<usize>,
+    max_size: Option<usize>,
+    sandbox: Option<&'a build::Sandbox>,
+    env: &'a Vec<(String, String)>,
+    args: &'a Vec<(String, Rc<Val>)>,
+    import_paths: &'a Vec<PathBuf>,
+    registry: &'a ConverterRegistry,
+    out_dir: Option<&'a Path>,
+    error_format: ErrorFormat,
+    // When set test results are collected here for a reporter instead of
+    // being printed.
+    test_results: Option<&'a RefCell<Vec<FileResults>>>,
+}
 
 fn do_flags<'a, 'b>() -> clap::App<'a, 'b> {
     clap_app!(
@@ -37,22 +104,74 @@ fn do_flags<'a, 'b>() -> clap::App<'a, 'b> {
             (author: crate_authors!())
             (about: "Universal Configuration Grammar compiler.")
             (@arg nostrict: --("no-strict") "Turn off strict checking.")
+            (@arg keep_going: --("keep-going") -k "Report every error in a file instead of stopping at the first one.")
+            (@arg deny_warnings: --("deny-warnings") "Fail the build of any file that has warnings.")
+            (@arg max_call_depth: --("max-call-depth") +takes_value "How deeply func calls and module copies may nest before the build fails. defaults to 1000.")
+            (@arg max_steps: --("max-steps") +takes_value "How many expressions a build may evaluate before it fails. defaults to unlimited.")
+            (@arg max_range: --("max-range") +takes_value "How many elements a range may produce before the build fails. defaults to unlimited.")
+            (@arg max_size: --("max-size") +takes_value "How many bytes a string or elements a list may hold before the build fails. defaults to unlimited.")
+            (@arg sandbox: --sandbox "Only expose the --allow-env environment variables and only allow imports and includes under the --sandbox-root directories.")
+            (@arg allow_env: --("allow-env") +takes_value +multiple number_of_values(1) requires[sandbox] "An environment variable a sandboxed build may read.")
+            (@arg sandbox_root: --("sandbox-root") +takes_value +multiple number_of_values(1) requires[sandbox] "A directory a sandboxed build may import and include files from. defaults to the current directory.")
+            (@arg error_format: --("error-format") +takes_value possible_value[human json] "How to print errors. json prints one diagnostic object per line. defaults to human.")
             (@subcommand eval =>
              (about: "Evaluate an expression with an optional ucg file as context.")
              (@arg expr: --expr -e +takes_value +required "Expression to evaluate.")
              (@arg target: --format +takes_value "Output type. (flags, json, env, exec) defaults to json.")
+             (@arg env_file: --("env-file") +takes_value +multiple number_of_values(1) "A file of KEY=VALUE lines to add to the environment. May be repeated.")
+             (@arg env: --env +takes_value +multiple number_of_values(1) "A KEY=VALUE pair to add to the environment. Overrides --env-file. May be repeated.")
+             (@arg no_inherit_env: --("no-inherit-env") "Don't start from the environment ucg was run with.")
+             (@arg define: -D +takes_value +multiple number_of_values(1) "A name=value pair to make available as args.name. The value is parsed as a ucg literal and otherwise taken as a string. May be repeated.")
              (@arg INPUT: "ucg file to use as context for the expression.")
             )
+            (@subcommand repl =>
+             (about: "Start an interactive session for evaluating ucg statements and expressions.")
+             (@arg target: --format +takes_value "Output type for results. (flags, json, env, exec) defaults to json.")
+             (@arg INPUT: "ucg file to load into the session before starting.")
+            )
             (@subcommand build =>
              (about: "Build a list of ucg files.")
              (@arg recurse: -r "Whether we should recurse in directories or not.")
+             (@arg watch: --watch "Rebuild files whenever they or anything they depend on changes.")
+             (@arg out_dir: --("out-dir") +takes_value "Directory to write outputs to. Mirrors the directory structure relative to the current directory.")
+             (@arg env_file: --("env-file") +takes_value +multiple number_of_values(1) "A file of KEY=VALUE lines to add to the environment. May be repeated.")
+             (@arg env: --env +takes_value +multiple number_of_values(1) "A KEY=VALUE pair to add to the environment. Overrides --env-file. May be repeated.")
+             (@arg no_inherit_env: --("no-inherit-env") "Don't start from the environment ucg was run with.")
+             (@arg define: -D +takes_value +multiple number_of_values(1) "A name=value pair to make available as args.name. The value is parsed as a ucg literal and otherwise taken as a string. May be repeated.")
              (@arg INPUT: ... "Input ucg files or directories to build. If not provided then build the contents of the current directory.")
             )
             (@subcommand test =>
              (about: "Check a list of ucg files for errors and run test assertions.")
              (@arg recurse: -r "Whether we should recurse or not.")
+             (@arg watch: --watch "Rerun tests whenever they or anything they depend on changes.")
+             (@arg env_file: --("env-file") +takes_value +multiple number_of_values(1) "A file of KEY=VALUE lines to add to the environment. May be repeated.")
+             (@arg env: --env +takes_value +multiple number_of_values(1) "A KEY=VALUE pair to add to the environment. Overrides --env-file. May be repeated.")
+             (@arg no_inherit_env: --("no-inherit-env") "Don't start from the environment ucg was run with.")
+             (@arg define: -D +takes_value +multiple number_of_values(1) "A name=value pair to make available as args.name. The value is parsed as a ucg literal and otherwise taken as a string. May be repeated.")
+             (@arg reporter: --reporter +takes_value possible_value[junit tap json] conflicts_with[watch] "Print the assertion results to stdout in this format instead of the human readable summary.")
              (@arg INPUT: ... "Input ucg files or directories to run test assertions for. If not provided it will scan the current directory for files with _test.ucg")
             )
+            (@subcommand fmt =>
+             (about: "Format ucg files into the canonical source form.")
+             (@arg recurse: -r "Whether we should recurse in directories or not.")
+             (@arg check: --check "Report files that would be reformatted without changing them.")
+             (@arg indent: --indent +takes_value "Number of spaces to indent with. defaults to 4.")
+             (@arg INPUT: ... "Input ucg files or directories to format. If not provided then format the contents of the current directory.")
+            )
+            (@subcommand check =>
+             (about: "Type check ucg files without evaluating them.")
+             (@arg recurse: -r "Whether we should recurse in directories or not.")
+             (@arg INPUT: ... "Input ucg files or directories to check. If not provided then check the contents of the current directory.")
+            )
+            (@subcommand lint =>
+             (about: "Check ucg files against the lint rules in the project config.")
+             (@arg recurse: -r "Whether we should recurse in directories or not.")
+             (@arg config: --config +takes_value "Lint config to use. defaults to the closest .ucg-lint.toml in the current directory or its parents.")
+             (@arg INPUT: ... "Input ucg files or directories to lint. If not provided then lint the contents of the current directory.")
+            )
+            (@subcommand lsp =>
+             (about: "Run a language server for ucg files over stdin and stdout.")
+            )
             (@subcommand converters =>
              (about: "list the available converters")
              (@arg converter: "Converter name to get help for.")
@@ -66,51 +185,135 @@ fn do_flags<'a, 'b>() -> clap::App<'a, 'b> {
     )
 }
 
-fn run_converter(c: &traits::Converter, v: Rc<Val>, f: Option<&str>) -> traits::ConvertResult {
-    let mut file: Box<std::io::Write> = match f {
-        Some(f) => {
-            let mut path_buf = PathBuf::from(f);
-            path_buf.set_extension(c.file_ext());
-            let new_path = path_buf.to_str().unwrap();
-            Box::new(File::create(&new_path)?)
+fn run_converter(c: &dyn traits::Converter, v: Rc<Val>) -> traits::ConvertResult {
+    c.convert(v, &mut io::stdout())
+}
+
+fn write_artifact(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    File::create(path)?.write_all(contents)
+}
+
+// Figures out where the output for a ucg file should be written.
+//
+// Outputs go next to the source file unless an out_dir is provided in which
+// case the directory structure relative to the current directory is mirrored
+// under the out_dir. A path named in the out statement is relative to that
+// directory. Otherwise the file takes the name of the source file with the
+// converter's extension.
+fn output_path(
+    file: &str,
+    out_dir: Option<&Path>,
+    out_path: Option<&str>,
+    ext: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let curr_dir = std::env::current_dir()?;
+    let file_path = curr_dir.join(file);
+    let src_dir = file_path.parent().unwrap_or(&curr_dir);
+    let base_dir = match out_dir {
+        Some(out_dir) => {
+            // Compare the resolved paths so a source reached through .. or a
+            // symlink is still mirrored to where it really is.
+            let real_curr_dir = curr_dir.canonicalize()?;
+            let real_src_dir = src_dir.canonicalize()?;
+            let rel_dir = match real_src_dir.strip_prefix(&real_curr_dir) {
+                Ok(rel_dir) => rel_dir,
+                Err(_) => {
+                    return Err(BuildError::new(
+                        format!(
+                            "Can not mirror {} under --out-dir because it is outside the current directory",
+                            file
+                        ),
+                        ErrorType::OutputError,
+                    )
+                    .to_boxed());
+                }
+            };
+            curr_dir.join(out_dir).join(rel_dir)
         }
-        None => Box::new(io::stdout()),
+        None => src_dir.to_path_buf(),
     };
-    c.convert(v, file.as_mut())
+    Ok(match out_path {
+        Some(out_path) => base_dir.join(out_path),
+        None => {
+            let mut path_buf = base_dir.join(file_path.file_name().unwrap());
+            path_buf.set_extension(ext);
+            path_buf
+        }
+    })
 }
 
 fn build_file<'a>(
-    file: &'a str,
+    file: &str,
     validate: bool,
-    strict: bool,
-    import_paths: &'a Vec<PathBuf>,
+    opts: &BuildOpts<'a>,
     cache: Rc<RefCell<Cache>>,
+    touched: Option<&mut BTreeSet<PathBuf>>,
 ) -> Result<build::FileBuilder<'a>, Box<dyn Error>> {
     let mut file_path_buf = PathBuf::from(file);
     if file_path_buf.is_relative() {
         file_path_buf = std::env::current_dir()?.join(file_path_buf);
     }
-    let mut builder = build::FileBuilder::new(std::env::current_dir()?, import_paths, cache);
-    builder.set_strict(strict);
+    let mut builder = build::FileBuilder::new(std::env::current_dir()?, opts.import_paths, cache);
+    builder.set_strict(opts.strict);
+    configure_builder(&mut builder, opts);
+    builder.set_keep_going(opts.keep_going);
     if validate {
         builder.enable_validate_mode();
     }
-    builder.build(file_path_buf)?;
-    if validate {
-        println!("{}", builder.assert_collector.summary);
+    let result = builder.build(file_path_buf);
+    if let Some(touched) = touched {
+        touched.extend(builder.touched_files());
+    }
+    let warnings = builder.warnings();
+    for warning in warnings.iter() {
+        opts.error_format.report_warning(warning);
+    }
+    result?;
+    if opts.deny_warnings && !warnings.is_empty() {
+        return Err(BuildError::new(
+            format!("{} warnings denied by --deny-warnings", warnings.len()),
+            ErrorType::Warning,
+        )
+        .to_boxed());
     }
     Ok(builder)
 }
 
 fn do_validate(
     file: &str,
-    strict: bool,
-    import_paths: &Vec<PathBuf>,
+    opts: &BuildOpts,
     cache: Rc<RefCell<Cache>>,
+    touched: Option<&mut BTreeSet<PathBuf>>,
 ) -> bool {
+    if let Some(test_results) = opts.test_results {
+        let (ok, file_results) = match build_file(file, true, opts, cache, touched) {
+            Ok(b) => (
+                b.assert_collector.success,
+                FileResults {
+                    file: file.to_string(),
+                    results: b.assert_collector.results,
+                    error: None,
+                },
+            ),
+            Err(err) => (
+                false,
+                FileResults {
+                    file: file.to_string(),
+                    results: Vec::new(),
+                    error: Some(err.to_string()),
+                },
+            ),
+        };
+        test_results.borrow_mut().push(file_results);
+        return ok;
+    }
     println!("Validating {}", file);
-    match build_file(file, true, strict, import_paths, cache) {
+    match build_file(file, true, opts, cache, touched) {
         Ok(b) => {
+            println!("{}", b.assert_collector.summary);
             if b.assert_collector.success {
                 println!("File {} Pass\n", file);
             } else {
@@ -118,8 +321,8 @@ fn do_validate(
                 return false;
             }
         }
-        Err(msg) => {
-            eprintln!("Err: {}", msg);
+        Err(err) => {
+            opts.error_format.report(err.as_ref());
             return false;
         }
     }
@@ -128,47 +331,97 @@ fn do_validate(
 
 fn do_compile(
     file: &str,
-    strict: bool,
-    import_paths: &Vec<PathBuf>,
+    opts: &BuildOpts,
     cache: Rc<RefCell<Cache>>,
-    registry: &ConverterRegistry,
+    touched: Option<&mut BTreeSet<PathBuf>>,
 ) -> bool {
     println!("Building {}", file);
-    let builder = match build_file(file, false, strict, import_paths, cache.clone()) {
+    let builder = match build_file(file, false, opts, cache, touched) {
         Ok(builder) => builder,
         Err(err) => {
-            eprintln!("{}", err);
+            opts.error_format.report(err.as_ref());
             return false;
         }
     };
-    let (typ, val) = match builder.out_lock {
-        Some((ref typ, ref val)) => (typ, val.clone()),
-        None => {
-            eprintln!("Build results in no artifacts.");
+    if builder.outputs.is_empty() {
+        eprintln!("Build results in no artifacts.");
+        return false;
+    }
+    // Convert every output before writing any of them so a bad output
+    // doesn't leave a partial set of artifacts behind.
+    let mut artifacts = Vec::new();
+    let mut seen_paths = BTreeSet::new();
+    for out in builder.outputs.iter() {
+        let converter = match opts.registry.get_converter(&out.typ) {
+            Some(converter) => converter,
+            None => {
+                let err = BuildError::with_pos(
+                    format!("No such converter {}", out.typ),
+                    ErrorType::ConvertError,
+                    out.pos.clone(),
+                );
+                opts.error_format.report(&err);
+                return false;
+            }
+        };
+        let path = match output_path(
+            file,
+            opts.out_dir,
+            out.path.as_deref(),
+            &converter.file_ext(),
+        ) {
+            Ok(path) => path,
+            Err(err) => {
+                opts.error_format.report(err.as_ref());
+                return false;
+            }
+        };
+        if !seen_paths.insert(path.clone()) {
+            let err = BuildError::with_pos(
+                format!(
+                    "Output would overwrite another output at {}",
+                    path.to_string_lossy()
+                ),
+                ErrorType::OutputError,
+                out.pos.clone(),
+            );
+            opts.error_format.report(&err);
             return false;
         }
-    };
-    match registry.get_converter(typ) {
-        Some(converter) => {
-            run_converter(converter, val, Some(file)).unwrap();
-            eprintln!("Build successful");
-            return true;
+        let mut buffer: Vec<u8> = Vec::new();
+        if let Err(cause) = converter.convert(out.val.clone(), &mut buffer) {
+            let err = BuildError::with_pos(
+                format!("Unable to convert output to {}", out.typ),
+                ErrorType::ConvertError,
+                out.pos.clone(),
+            )
+            .wrap_cause(cause);
+            opts.error_format.report(&err);
+            return false;
         }
-        None => {
-            eprintln!("No such converter {}", typ);
+        artifacts.push((path, buffer));
+    }
+    for (path, buffer) in artifacts {
+        if let Err(cause) = write_artifact(&path, &buffer) {
+            let err = BuildError::new(
+                format!("Unable to write {}", path.to_string_lossy()),
+                ErrorType::OSError,
+            )
+            .wrap_cause(Box::new(cause));
+            opts.error_format.report(&err);
             return false;
         }
     }
+    eprintln!("Build successful");
+    true
 }
 
 fn visit_ucg_files(
     path: &Path,
     recurse: bool,
     validate: bool,
-    strict: bool,
-    import_paths: &Vec<PathBuf>,
+    opts: &BuildOpts,
     cache: Rc<RefCell<Cache>>,
-    registry: &ConverterRegistry,
 ) -> Result<bool, Box<dyn Error>> {
     let our_path = String::from(path.to_string_lossy());
     let mut result = true;
@@ -186,76 +439,166 @@ fn visit_ucg_files(
             let next_path = next_item.path();
             let path_as_string = String::from(next_path.to_string_lossy());
             if next_path.is_dir() && recurse {
-                if let Err(e) = visit_ucg_files(
-                    &next_path,
-                    recurse,
-                    validate,
-                    strict,
-                    import_paths,
-                    cache.clone(),
-                    registry,
-                ) {
-                    eprintln!("{}", e);
+                if let Err(e) = visit_ucg_files(&next_path, recurse, validate, opts, cache.clone())
+                {
+                    opts.error_format.report(e.as_ref());
                     result = false;
                 }
             } else {
                 if validate && path_as_string.ends_with("_test.ucg") {
-                    if !do_validate(&path_as_string, strict, import_paths, cache.clone()) {
+                    if !do_validate(&path_as_string, opts, cache.clone(), None) {
                         result = false;
                         summary.push_str(format!("{} - FAIL\n", path_as_string).as_str())
                     } else {
                         summary.push_str(format!("{} - PASS\n", path_as_string).as_str())
                     }
                 } else if !validate && path_as_string.ends_with(".ucg") {
-                    if !do_compile(
-                        &path_as_string,
-                        strict,
-                        import_paths,
-                        cache.clone(),
-                        registry,
-                    ) {
+                    if !do_compile(&path_as_string, opts, cache.clone(), None) {
                         result = false;
                     }
                 }
             }
         }
     } else if validate && our_path.ends_with("_test.ucg") {
-        if !do_validate(&our_path, strict, import_paths, cache) {
+        if !do_validate(&our_path, opts, cache, None) {
             result = false;
             summary.push_str(format!("{} - FAIL\n", our_path).as_str());
         } else {
             summary.push_str(format!("{} - PASS\n", &our_path).as_str());
         }
     } else if !validate {
-        if !do_compile(&our_path, strict, import_paths, cache, registry) {
+        if !do_compile(&our_path, opts, cache, None) {
             result = false;
         }
     }
-    if validate && !summary.is_empty() {
+    if validate && !summary.is_empty() && opts.test_results.is_none() {
         println!("RESULTS:");
         println!("{}", summary);
     }
     Ok(result)
 }
 
-fn inspect_command(
-    matches: &clap::ArgMatches,
-    import_paths: &Vec<PathBuf>,
-    cache: Rc<RefCell<Cache>>,
-    registry: &ConverterRegistry,
-    strict: bool,
-) {
+// How often watch mode checks files for changes.
+const WATCH_POLL_INTERVAL_MS: u64 = 500;
+
+fn collect_ucg_files(
+    path: &Path,
+    recurse: bool,
+    validate: bool,
+    files: &mut BTreeSet<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    if !path.is_dir() {
+        if !validate || path.to_string_lossy().ends_with("_test.ucg") {
+            files.insert(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(path)? {
+        let next_path = entry?.path();
+        let path_as_string = next_path.to_string_lossy().to_string();
+        if next_path.is_dir() {
+            if recurse {
+                collect_ucg_files(&next_path, recurse, validate, files)?;
+            }
+        } else if (validate && path_as_string.ends_with("_test.ucg"))
+            || (!validate && path_as_string.ends_with(".ucg"))
+        {
+            files.insert(next_path);
+        }
+    }
+    Ok(())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn watch_ucg_files(paths: &[PathBuf], recurse: bool, validate: bool, opts: &BuildOpts) -> ! {
+    // The files each top level file depended on during its last build along
+    // with their modification times from before that build started.
+    let mut deps: BTreeMap<PathBuf, BTreeMap<PathBuf, Option<SystemTime>>> = BTreeMap::new();
+    loop {
+        let mut top_level = BTreeSet::new();
+        for path in paths.iter() {
+            if let Err(e) = collect_ucg_files(path, recurse, validate, &mut top_level) {
+                opts.error_format.report(e.as_ref());
+            }
+        }
+        deps.retain(|file, _| top_level.contains(file));
+        let mut rebuilt = false;
+        for file in top_level.iter() {
+            let changed = match deps.get(file) {
+                Some(times) => times.iter().any(|(p, t)| modified_time(p) != *t),
+                None => true,
+            };
+            if !changed {
+                continue;
+            }
+            rebuilt = true;
+            // Take the times before building so that anything edited while the
+            // build runs counts as changed on the next pass.
+            let mut before: BTreeMap<PathBuf, Option<SystemTime>> = BTreeMap::new();
+            for p in deps.get(file).into_iter().flat_map(|times| times.keys()) {
+                before.insert(p.clone(), modified_time(p));
+            }
+            before.insert(file.clone(), modified_time(file));
+            let started = SystemTime::now();
+            // Each file gets a fresh cache so that all of its imports are read
+            // again and tracked as its dependencies.
+            let cache: Rc<RefCell<Cache>> = Rc::new(RefCell::new(MemoryCache::new()));
+            let mut touched = BTreeSet::new();
+            touched.insert(file.clone());
+            let file_name = file.to_string_lossy().to_string();
+            if validate {
+                do_validate(&file_name, opts, cache, Some(&mut touched));
+            } else {
+                do_compile(&file_name, opts, cache, Some(&mut touched));
+            }
+            let times = touched
+                .into_iter()
+                .map(|p| {
+                    // New dependencies weren't known until the build read
+                    // them. If they were modified after it started they may
+                    // have been read mid edit so they're left without a time
+                    // to force another build.
+                    let t = match before.get(&p) {
+                        Some(t) => *t,
+                        None => modified_time(&p).filter(|t| *t < started),
+                    };
+                    (p, t)
+                })
+                .collect();
+            deps.insert(file.clone(), times);
+        }
+        if rebuilt {
+            println!("Watching for changes...");
+        }
+        thread::sleep(Duration::from_millis(WATCH_POLL_INTERVAL_MS));
+    }
+}
+
+fn watch_command(matches: &clap::ArgMatches, validate: bool, opts: &BuildOpts) -> ! {
+    let recurse = matches.is_present("recurse");
+    let paths: Vec<PathBuf> = match matches.values_of("INPUT") {
+        Some(files) => files.map(PathBuf::from).collect(),
+        None => vec![std::env::current_dir().unwrap()],
+    };
+    watch_ucg_files(&paths, recurse, validate, opts)
+}
+
+fn inspect_command(matches: &clap::ArgMatches, opts: &BuildOpts, cache: Rc<RefCell<Cache>>) {
     let file = matches.value_of("INPUT").unwrap_or("std/functional.ucg");
     let sym = matches.value_of("expr");
     let target = matches.value_of("target").unwrap_or("json");
     let mut builder =
-        build::FileBuilder::new(std::env::current_dir().unwrap(), import_paths, cache);
-    builder.set_strict(strict);
-    match registry.get_converter(target) {
+        build::FileBuilder::new(std::env::current_dir().unwrap(), opts.import_paths, cache);
+    builder.set_strict(opts.strict);
+    configure_builder(&mut builder, opts);
+    match opts.registry.get_converter(target) {
         Some(converter) => {
             let result = builder.build(file);
-            if !result.is_ok() {
-                eprintln!("{:?}", result.err().unwrap());
+            if let Err(err) = result {
+                opts.error_format.report(err.as_ref());
                 process::exit(1);
             }
             let val = match sym {
@@ -271,7 +614,7 @@ fn inspect_command(
                     match builder.eval_string(&normalized) {
                         Ok(v) => Some(v.clone()),
                         Err(e) => {
-                            eprintln!("{}", e);
+                            opts.error_format.report(e.as_ref());
                             process::exit(1);
                         }
                     }
@@ -280,8 +623,7 @@ fn inspect_command(
             };
             match val {
                 Some(value) => {
-                    // We use None here because we always output to stdout for an inspect.
-                    run_converter(converter, value, None).unwrap();
+                    run_converter(converter, value).unwrap();
                     println!("");
                     process::exit(0);
                 }
@@ -301,27 +643,114 @@ fn inspect_command(
     }
 }
 
-fn build_command(
-    matches: &clap::ArgMatches,
-    import_paths: &Vec<PathBuf>,
-    cache: Rc<RefCell<Cache>>,
-    registry: &ConverterRegistry,
-    strict: bool,
-) {
+fn repl_eval(
+    builder: &mut build::FileBuilder,
+    converter: &dyn traits::Converter,
+    stmt: &str,
+) -> Result<(), Box<dyn Error>> {
+    let val = builder.eval_string(stmt)?;
+    // Every entry in a session gets its own chance at an out statement.
+    builder.outputs.clear();
+    let mut stdout = io::stdout();
+    converter.convert(val, &mut stdout)?;
+    println!();
+    Ok(())
+}
+
+fn repl_command(matches: &clap::ArgMatches, opts: &BuildOpts, cache: Rc<RefCell<Cache>>) {
+    let registry = opts.registry;
+    let mut target = matches.value_of("target").unwrap_or("json").to_string();
+    if registry.get_converter(&target).is_none() {
+        eprintln!(
+            "No such format {}\nrun `ucg converters` to see available formats.",
+            target
+        );
+        process::exit(1);
+    }
+    let new_builder = || {
+        let mut builder = build::FileBuilder::new(
+            std::env::current_dir().unwrap(),
+            opts.import_paths,
+            cache.clone(),
+        );
+        builder.set_strict(opts.strict);
+        configure_builder(&mut builder, opts);
+        builder
+    };
+    let mut builder = new_builder();
+    if let Some(file) = matches.value_of("INPUT") {
+        let mut file_path_buf = PathBuf::from(file);
+        if file_path_buf.is_relative() {
+            file_path_buf = std::env::current_dir().unwrap().join(file_path_buf);
+        }
+        if let Err(e) = builder.build(file_path_buf) {
+            opts.error_format.report(e.as_ref());
+            process::exit(1);
+        }
+        builder.outputs.clear();
+    }
+    println!(
+        "ucg {} interactive session. Type :help for help.",
+        crate_version!()
+    );
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut session = repl::Session::new();
+    loop {
+        print!("{}", session.prompt());
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            None => {
+                println!();
+                break;
+            }
+        };
+        match session.read_line(&line) {
+            repl::Input::Quit => break,
+            repl::Input::Help => print!("{}", repl::HELP),
+            repl::Input::Reset => {
+                builder = new_builder();
+                println!("Session reset.");
+            }
+            repl::Input::Format(Some(name)) => {
+                if registry.get_converter(&name).is_some() {
+                    target = name;
+                } else {
+                    eprintln!("No such format {}", name);
+                }
+            }
+            repl::Input::Format(None) => println!("{}", target),
+            repl::Input::Unknown(cmd) => {
+                eprintln!("Unknown command {}. Type :help for help.", cmd)
+            }
+            repl::Input::Continue => {}
+            repl::Input::Eval(stmt) => {
+                let converter = registry.get_converter(&target).unwrap();
+                if let Err(e) = repl_eval(&mut builder, converter, &stmt) {
+                    opts.error_format.report(e.as_ref());
+                }
+            }
+        }
+    }
+}
+
+fn build_command(matches: &clap::ArgMatches, opts: &BuildOpts, cache: Rc<RefCell<Cache>>) {
+    let mut opts = *opts;
+    opts.out_dir = matches.value_of("out_dir").map(Path::new);
+    if matches.is_present("watch") {
+        watch_command(matches, false, &opts);
+    }
     let files = matches.values_of("INPUT");
     let recurse = matches.is_present("recurse");
     let mut ok = true;
     if files.is_none() {
         let curr_dir = std::env::current_dir().unwrap();
-        let ok = visit_ucg_files(
-            curr_dir.as_path(),
-            recurse,
-            false,
-            strict,
-            import_paths,
-            cache.clone(),
-            &registry,
-        );
+        let ok = visit_ucg_files(curr_dir.as_path(), recurse, false, &opts, cache.clone());
         if let Ok(false) = ok {
             process::exit(1)
         }
@@ -329,15 +758,7 @@ fn build_command(
     }
     for file in files.unwrap() {
         let pb = PathBuf::from(file);
-        if let Ok(false) = visit_ucg_files(
-            &pb,
-            recurse,
-            false,
-            strict,
-            import_paths,
-            cache.clone(),
-            &registry,
-        ) {
+        if let Ok(false) = visit_ucg_files(&pb, recurse, false, &opts, cache.clone()) {
             ok = false;
         }
     }
@@ -346,53 +767,270 @@ fn build_command(
     }
 }
 
-fn test_command(
-    matches: &clap::ArgMatches,
-    import_paths: &Vec<PathBuf>,
-    cache: Rc<RefCell<Cache>>,
-    registry: &ConverterRegistry,
-    strict: bool,
-) {
+fn test_command(matches: &clap::ArgMatches, opts: &BuildOpts, cache: Rc<RefCell<Cache>>) {
+    if matches.is_present("watch") {
+        watch_command(matches, true, opts);
+    }
+    let test_results = RefCell::new(Vec::new());
+    let mut opts = *opts;
+    if matches.is_present("reporter") {
+        opts.test_results = Some(&test_results);
+    }
     let files = matches.values_of("INPUT");
     let recurse = matches.is_present("recurse");
+    let mut ok = true;
     if files.is_none() {
         let curr_dir = std::env::current_dir().unwrap();
-        let ok = visit_ucg_files(
-            curr_dir.as_path(),
-            recurse,
-            true,
-            strict,
-            import_paths,
-            cache.clone(),
-            &registry,
-        );
-        if let Ok(false) = ok {
-            process::exit(1)
+        if let Ok(false) = visit_ucg_files(curr_dir.as_path(), recurse, true, &opts, cache.clone())
+        {
+            ok = false;
         }
     } else {
-        let mut ok = true;
         for file in files.unwrap() {
             let pb = PathBuf::from(file);
             //if pb.is_dir() {
-            if let Ok(false) = visit_ucg_files(
-                pb.as_path(),
-                recurse,
-                true,
-                strict,
-                import_paths,
-                cache.clone(),
-                &registry,
-            ) {
+            if let Ok(false) = visit_ucg_files(pb.as_path(), recurse, true, &opts, cache.clone()) {
                 ok = false;
             }
         }
-        if !ok {
-            process::exit(1)
-        }
+    }
+    let results = test_results.borrow();
+    let stdout = io::stdout();
+    let written = match matches.value_of("reporter") {
+        Some("junit") => report::write_junit(&results, stdout.lock()),
+        Some("tap") => report::write_tap(&results, stdout.lock()),
+        Some("json") => report::write_json(&results, stdout.lock()),
+        _ => Ok(()),
+    };
+    if let Err(e) = written {
+        opts.error_format.report(e.as_ref());
+        process::exit(1);
+    }
+    if !ok {
+        process::exit(1)
     }
     process::exit(0);
 }
 
+fn format_file(file: &Path, indent: usize, check: bool) -> Result<bool, Box<dyn Error>> {
+    let mut src = String::new();
+    File::open(file)?.read_to_string(&mut src)?;
+    let mut comment_map = CommentMap::new();
+    let stmts = match parse(
+        OffsetStrIter::new(&src).with_src_file(file),
+        Some(&mut comment_map),
+    ) {
+        Ok(stmts) => stmts,
+        Err(err) => return Err(Box::new(err)),
+    };
+    let mut buffer: Vec<u8> = Vec::new();
+    {
+        let mut printer = AstPrinter::new(indent, &mut buffer)
+            .with_comment_map(&comment_map)
+            .with_source(&src);
+        printer.render(&stmts)?;
+    }
+    if buffer == src.as_bytes() {
+        return Ok(true);
+    }
+    if check {
+        println!("{} needs formatting", file.to_string_lossy());
+        return Ok(false);
+    }
+    File::create(file)?.write_all(&buffer)?;
+    println!("Formatted {}", file.to_string_lossy());
+    Ok(true)
+}
+
+fn report_fmt_error(path: &Path, err: &(dyn Error + 'static), error_format: ErrorFormat) {
+    match error_format {
+        ErrorFormat::Human => eprintln!("Err: {}: {}", path.to_string_lossy(), err),
+        ErrorFormat::Json => error_format.report(err),
+    }
+}
+
+// Calls f for every ucg file under path. Returns false if f did or any
+// error happened.
+fn visit_source_files<F>(path: &Path, recurse: bool, error_format: ErrorFormat, f: &F) -> bool
+where
+    F: Fn(&Path) -> Result<bool, Box<dyn Error>>,
+{
+    if !path.is_dir() {
+        return match f(path) {
+            Ok(ok) => ok,
+            Err(e) => {
+                report_fmt_error(path, e.as_ref(), error_format);
+                false
+            }
+        };
+    }
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report_fmt_error(path, &e, error_format);
+            return false;
+        }
+    };
+    let mut result = true;
+    for entry in entries {
+        let next_path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                report_fmt_error(path, &e, error_format);
+                result = false;
+                continue;
+            }
+        };
+        let is_dir = next_path.is_dir();
+        if (is_dir && !recurse) || (!is_dir && !next_path.to_string_lossy().ends_with(".ucg")) {
+            continue;
+        }
+        if !visit_source_files(&next_path, recurse, error_format, f) {
+            result = false;
+        }
+    }
+    result
+}
+
+fn fmt_command(matches: &clap::ArgMatches, error_format: ErrorFormat) {
+    let files = matches.values_of("INPUT");
+    let recurse = matches.is_present("recurse");
+    let check = matches.is_present("check");
+    let indent = match matches.value_of("indent").unwrap_or("4").parse::<usize>() {
+        Ok(indent) => indent,
+        Err(e) => {
+            eprintln!("Invalid indent: {}", e);
+            process::exit(1);
+        }
+    };
+    let format = |path: &Path| format_file(path, indent, check);
+    let mut ok = true;
+    match files {
+        Some(files) => {
+            for file in files {
+                if !visit_source_files(&PathBuf::from(file), recurse, error_format, &format) {
+                    ok = false;
+                }
+            }
+        }
+        None => {
+            let curr_dir = std::env::current_dir().unwrap();
+            ok = visit_source_files(curr_dir.as_path(), recurse, error_format, &format);
+        }
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+// Returns whether the file had no type errors.
+fn check_file(file: &Path, error_format: ErrorFormat) -> Result<bool, Box<dyn Error>> {
+    let file = std::env::current_dir()?.join(file);
+    let mut src = String::new();
+    File::open(&file)?.read_to_string(&mut src)?;
+    let stmts = match parse(OffsetStrIter::new(&src).with_src_file(&file), None) {
+        Ok(stmts) => stmts,
+        Err(err) => return Err(Box::new(err)),
+    };
+    let errors = typecheck::check(&stmts);
+    for err in errors.iter() {
+        error_format.report(err);
+    }
+    Ok(errors.is_empty())
+}
+
+fn check_command(matches: &clap::ArgMatches, error_format: ErrorFormat) {
+    let recurse = matches.is_present("recurse");
+    let check = |path: &Path| check_file(path, error_format);
+    let mut ok = true;
+    match matches.values_of("INPUT") {
+        Some(files) => {
+            for file in files {
+                if !visit_source_files(&PathBuf::from(file), recurse, error_format, &check) {
+                    ok = false;
+                }
+            }
+        }
+        None => {
+            let curr_dir = std::env::current_dir().unwrap();
+            ok = visit_source_files(curr_dir.as_path(), recurse, error_format, &check);
+        }
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+// Returns whether the file had no lint findings.
+fn lint_file(
+    file: &Path,
+    config: &lint::Config,
+    error_format: ErrorFormat,
+) -> Result<bool, Box<dyn Error>> {
+    let file = std::env::current_dir()?.join(file);
+    let mut src = String::new();
+    File::open(&file)?.read_to_string(&mut src)?;
+    let stmts = match parse(OffsetStrIter::new(&src).with_src_file(&file), None) {
+        Ok(stmts) => stmts,
+        Err(err) => return Err(Box::new(err)),
+    };
+    let warnings = lint::lint(config, &file, &stmts);
+    for warning in warnings.iter() {
+        error_format.report_warning(warning);
+    }
+    Ok(warnings.is_empty())
+}
+
+fn lint_command(matches: &clap::ArgMatches, error_format: ErrorFormat) {
+    let recurse = matches.is_present("recurse");
+    let curr_dir = std::env::current_dir().unwrap();
+    let config_path = match matches.value_of("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => lint::Config::find(&curr_dir),
+    };
+    let config = match config_path {
+        Some(path) => match lint::Config::load(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => lint::Config::default(),
+    };
+    let lint = |path: &Path| lint_file(path, &config, error_format);
+    let mut ok = true;
+    match matches.values_of("INPUT") {
+        Some(files) => {
+            for file in files {
+                if !visit_source_files(&PathBuf::from(file), recurse, error_format, &lint) {
+                    ok = false;
+                }
+            }
+        }
+        None => {
+            ok = visit_source_files(curr_dir.as_path(), recurse, error_format, &lint);
+        }
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+fn lsp_command(import_paths: &[PathBuf], max_call_depth: usize) {
+    let mut server = lsp::Server::new(import_paths.to_vec());
+    server.set_max_call_depth(max_call_depth);
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(e) = server.run(stdin.lock(), stdout.lock()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    if !server.clean_shutdown() {
+        process::exit(1);
+    }
+}
+
 fn converters_command(matches: &clap::ArgMatches, registry: &ConverterRegistry) {
     if let Some(ref cname) = matches.value_of("converter") {
         let mut found = false;
@@ -445,9 +1083,144 @@ fn env_help() {
     );
 }
 
+// The stack the commands run with before accounting for func calls.
+const BASE_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+// How deeply func calls and module copies may nest by default. The commands
+// run on a thread sized for it so this can be far deeper than the library's
+// default.
+const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// Parses the value of a flag that takes a positive integer exiting if it
+// isn't one.
+fn positive_flag<T: FromStr + Default + PartialEq>(
+    matches: &clap::ArgMatches,
+    name: &str,
+    flag: &str,
+) -> Option<T> {
+    matches.value_of(name).map(|v| match v.parse::<T>() {
+        Ok(n) if n != T::default() => n,
+        _ => {
+            eprintln!("--{} must be a positive integer", flag);
+            process::exit(1);
+        }
+    })
+}
+
+// Applies the limits, sandbox, environment and args from the flags.
+fn configure_builder(builder: &mut build::FileBuilder, opts: &BuildOpts) {
+    builder.set_max_call_depth(opts.max_call_depth);
+    if let Some(max) = opts.max_steps {
+        builder.set_max_steps(max);
+    }
+    if let Some(max) = opts.max_range {
+        builder.set_max_range(max);
+    }
+    if let Some(max) = opts.max_size {
+        builder.set_max_size(max);
+    }
+    if let Some(sandbox) = opts.sandbox {
+        builder.set_sandbox(sandbox.clone());
+    }
+    builder.set_env(opts.env.clone());
+    builder.set_args(opts.args.clone());
+}
+
+fn args_from_flags(matches: Option<&clap::ArgMatches>) -> Vec<(String, Rc<Val>)> {
+    let mut args = Vec::new();
+    let defines = matches.and_then(|m| m.values_of("define"));
+    for define in defines.into_iter().flatten() {
+        match build::args::parse_define(define) {
+            Ok(arg) => build::args::merge(&mut args, vec![arg]),
+            Err(e) => {
+                eprintln!("Invalid -D value: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    args
+}
+
+// Builds the environment from the process environment, env files and env
+// flags with the later ones overriding the earlier.
+fn env_from_flags(matches: Option<&clap::ArgMatches>) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+    let matches = match matches {
+        Some(matches) => matches,
+        None => return std::env::vars().collect(),
+    };
+    if !matches.is_present("no_inherit_env") {
+        vars.extend(std::env::vars());
+    }
+    for path in matches.values_of("env_file").into_iter().flatten() {
+        let parsed = std::fs::read_to_string(path)
+            .map_err(|e| Box::new(e) as Box<dyn Error>)
+            .and_then(|src| build::envfile::parse(&src));
+        match parsed {
+            Ok(file_vars) => build::envfile::merge(&mut vars, file_vars),
+            Err(e) => {
+                eprintln!("Invalid env file {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    for assignment in matches.values_of("env").into_iter().flatten() {
+        match build::envfile::parse_assignment(assignment) {
+            Ok(var) => build::envfile::merge(&mut vars, vec![var]),
+            Err(e) => {
+                eprintln!("Invalid --env value: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    vars
+}
+
+fn sandbox_from_flags(matches: &clap::ArgMatches) -> build::Sandbox {
+    let mut sandbox = build::Sandbox::new();
+    for name in matches.values_of("allow_env").into_iter().flatten() {
+        sandbox = sandbox.with_env_var(name);
+    }
+    match matches.values_of("sandbox_root") {
+        Some(roots) => {
+            for root in roots {
+                sandbox = sandbox.with_root(root);
+            }
+        }
+        None => sandbox = sandbox.with_root(std::env::current_dir().unwrap()),
+    }
+    sandbox
+}
+
 fn main() {
-    let mut app = do_flags();
-    let app_matches = app.clone().get_matches();
+    let app_matches = do_flags().get_matches();
+    let max_call_depth = positive_flag(&app_matches, "max_call_depth", "max-call-depth")
+        .unwrap_or(DEFAULT_MAX_CALL_DEPTH);
+    // Run on a thread with enough stack for the deepest allowed recursion so
+    // hitting the limit is a clean error rather than a stack overflow.
+    let stack_size = max_call_depth
+        .saturating_mul(build::STACK_PER_CALL)
+        .saturating_add(BASE_STACK_SIZE);
+    let child = thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || run(app_matches, max_call_depth));
+    match child {
+        Ok(child) => {
+            if child.join().is_err() {
+                process::exit(101);
+            }
+        }
+        Err(e) => {
+            eprintln!(
+                "Unable to allocate a stack for --max-call-depth {}: {}",
+                max_call_depth, e
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn run(app_matches: clap::ArgMatches<'static>, max_call_depth: usize) {
     let cache: Rc<RefCell<Cache>> = Rc::new(RefCell::new(MemoryCache::new()));
     let registry = ConverterRegistry::make_registry();
     let mut import_paths = Vec::new();
@@ -469,12 +1242,50 @@ fn main() {
     } else {
         true
     };
+    let error_format = match app_matches.value_of("error_format") {
+        Some("json") => ErrorFormat::Json,
+        _ => ErrorFormat::Human,
+    };
+    let sandbox = if app_matches.is_present("sandbox") {
+        Some(sandbox_from_flags(&app_matches))
+    } else {
+        None
+    };
+    let env = env_from_flags(app_matches.subcommand().1);
+    let args = args_from_flags(app_matches.subcommand().1);
+    let opts = BuildOpts {
+        strict,
+        keep_going: app_matches.is_present("keep_going"),
+        deny_warnings: app_matches.is_present("deny_warnings"),
+        max_call_depth,
+        max_steps: positive_flag(&app_matches, "max_steps", "max-steps"),
+        max_range: positive_flag(&app_matches, "max_range", "max-range"),
+        max_size: positive_flag(&app_matches, "max_size", "max-size"),
+        sandbox: sandbox.as_ref(),
+        env: &env,
+        args: &args,
+        import_paths: &import_paths,
+        registry: &registry,
+        out_dir: None,
+        error_format,
+        test_results: None,
+    };
     if let Some(matches) = app_matches.subcommand_matches("eval") {
-        inspect_command(matches, &import_paths, cache, &registry, strict);
+        inspect_command(matches, &opts, cache);
+    } else if let Some(matches) = app_matches.subcommand_matches("repl") {
+        repl_command(matches, &opts, cache);
     } else if let Some(matches) = app_matches.subcommand_matches("build") {
-        build_command(matches, &import_paths, cache, &registry, strict);
+        build_command(matches, &opts, cache);
     } else if let Some(matches) = app_matches.subcommand_matches("test") {
-        test_command(matches, &import_paths, cache, &registry, strict);
+        test_command(matches, &opts, cache);
+    } else if let Some(matches) = app_matches.subcommand_matches("fmt") {
+        fmt_command(matches, error_format);
+    } else if let Some(matches) = app_matches.subcommand_matches("check") {
+        check_command(matches, error_format);
+    } else if let Some(matches) = app_matches.subcommand_matches("lint") {
+        lint_command(matches, error_format);
+    } else if let Some(_) = app_matches.subcommand_matches("lsp") {
+        lsp_command(&import_paths, max_call_depth)
     } else if let Some(matches) = app_matches.subcommand_matches("converters") {
         converters_command(matches, &registry)
     } else if let Some(_) = app_matches.subcommand_matches("importers") {
@@ -483,7 +1294,7 @@ fn main() {
     } else if let Some(_) = app_matches.subcommand_matches("env") {
         env_help()
     } else {
-        app.print_help().unwrap();
+        do_flags().print_help().unwrap();
         println!("");
     }
 }
@@ -15,12 +15,13 @@
 //! The Parsing stage of the ucg compiler.
 use std;
 use std::borrow::Borrow;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
 use abortable_parser;
 use abortable_parser::combinators::eoi;
 use abortable_parser::iter::SliceIter;
-use abortable_parser::{Error, Peekable, Result};
+use abortable_parser::{Error, Offsetable, Peekable, Result};
 
 use self::precedence::op_expression;
 use crate::ast::*;
@@ -209,6 +210,50 @@ make_fn!(
     )
 );
 
+fn type_annotation(input: SliceIter<Token>) -> Result<SliceIter<Token>, Token> {
+    let parsed = do_each!(input,
+        _ => punct!(":"),
+        typ => wrap_err!(must!(match_type!(BAREWORD)), "Expected a type name"),
+        (typ)
+    );
+    match parsed {
+        Result::Complete(rest, typ) => {
+            if TYPE_NAMES.contains(&typ.fragment.as_str()) {
+                Result::Complete(rest, typ)
+            } else {
+                Result::Abort(Error::new(
+                    format!(
+                        "Unknown type {}. Expected one of {}",
+                        typ.fragment,
+                        TYPE_NAMES.join(", ")
+                    ),
+                    Box::new(input.clone()),
+                ))
+            }
+        }
+        Result::Abort(e) => Result::Abort(e),
+        Result::Fail(e) => Result::Fail(e),
+        Result::Incomplete(offset) => Result::Incomplete(offset),
+    }
+}
+
+make_fn!(
+    param_value<SliceIter<Token>, (Token, Option<Token>, Expression)>,
+    do_each!(
+            field => wrap_err!(either!(match_type!(BOOLEAN), match_type!(BAREWORD), match_type!(STR)),
+                               "Field names must be a bareword or a string."),
+            typ => optional!(type_annotation),
+            _ => must!(punct!("=")),
+            value => must!(expression),
+            (field, typ, value)
+    )
+);
+
+make_fn!(
+    param_list<SliceIter<Token>, Vec<(Token, Option<Token>, Expression)>>,
+    separated!(punct!(","), param_value)
+);
+
 make_fn!(
     field_value<SliceIter<Token>, (Token, Expression)>,
     do_each!(
@@ -344,31 +389,46 @@ make_fn!(
 
 fn tuple_to_func<'a>(
     pos: Position,
-    vals: Option<Vec<Value>>,
+    vals: Option<Vec<(Value, Option<Token>)>>,
     val: Expression,
 ) -> ConvertResult<'a, Expression> {
     let mut default_args = match vals {
         None => Vec::new(),
         Some(vals) => vals,
     };
+    let mut arg_types = BTreeMap::new();
     let arglist = default_args
         .drain(0..)
-        .map(|s| PositionedItem {
-            pos: s.pos().clone(),
-            val: s.to_string(),
+        .map(|(s, typ)| {
+            if let Some(typ) = typ {
+                arg_types.insert(s.to_string(), typ);
+            }
+            PositionedItem {
+                pos: s.pos().clone(),
+                val: s.to_string(),
+            }
         })
         .collect();
     Ok(Expression::Func(FuncDef {
         scope: None,
         argdefs: arglist,
+        arg_types,
         fields: Box::new(val),
+        rec_funcs: None,
         pos: pos,
     }))
 }
 
 make_fn!(
-    arglist<SliceIter<Token>, Vec<Value>>,
-    separated!(punct!(","), symbol)
+    arglist<SliceIter<Token>, Vec<(Value, Option<Token>)>>,
+    separated!(
+        punct!(","),
+        do_each!(
+            name => symbol,
+            typ => optional!(type_annotation),
+            (name, typ)
+        )
+    )
 );
 
 fn module_expression(input: SliceIter<Token>) -> Result<SliceIter<Token>, Expression> {
@@ -376,9 +436,9 @@ fn module_expression(input: SliceIter<Token>) -> Result<SliceIter<Token>, Expres
         pos => pos,
         _ => word!("module"),
         _ => must!(punct!("{")),
-        arglist => trace_parse!(optional!(field_list)),
+        arglist => trace_parse!(optional!(param_list)),
         _ => optional!(punct!(",")),
-        _ => must!(punct!("}")),
+        arg_close => must!(punct!("}")),
         _ => must!(punct!("=>")),
         out_expr => optional!(
             do_each!(
@@ -391,14 +451,24 @@ fn module_expression(input: SliceIter<Token>) -> Result<SliceIter<Token>, Expres
         _ => must!(punct!("{")),
         stmt_list =>  trace_parse!(repeat!(statement)),
         _ => must!(punct!("}")),
-        (pos, arglist, out_expr, stmt_list)
+        (pos, arglist, arg_close, out_expr, stmt_list)
     );
     match parsed {
         Result::Abort(e) => Result::Abort(e),
         Result::Fail(e) => Result::Fail(e),
         Result::Incomplete(offset) => Result::Incomplete(offset),
-        Result::Complete(rest, (pos, arglist, out_expr, stmt_list)) => {
-            let mut def = ModuleDef::new(arglist.unwrap_or_else(|| Vec::new()), stmt_list, pos);
+        Result::Complete(rest, (pos, arglist, arg_close, out_expr, stmt_list)) => {
+            let mut arg_set = Vec::new();
+            let mut arg_types = BTreeMap::new();
+            for (name, typ, value) in arglist.unwrap_or_default() {
+                if let Some(typ) = typ {
+                    arg_types.insert(name.fragment.clone(), typ);
+                }
+                arg_set.push((name, value));
+            }
+            let mut def = ModuleDef::new(arg_set, stmt_list, pos);
+            def.arg_types = arg_types;
+            def.arg_end = arg_close.pos.end;
             if let Some(expr) = out_expr {
                 def.set_out_expr(expr);
             }
@@ -752,8 +822,24 @@ fn unprefixed_expression(input: SliceIter<Token>) -> ParseResult<Expression> {
     )
 }
 
+// Extends the span of an expression to the end of the last token consumed
+// while parsing it.
+fn with_span_end(
+    mut expr: Expression,
+    input: &SliceIter<Token>,
+    rest: &SliceIter<Token>,
+) -> Expression {
+    let consumed = rest.get_offset() - input.get_offset();
+    if consumed > 0 {
+        if let Some(tok) = input.clone().nth(consumed - 1) {
+            expr.pos_mut().end = tok.pos.end;
+        }
+    }
+    expr
+}
+
 make_fn!(
-    non_op_expression<SliceIter<Token>, Expression>,
+    non_op_expression_body<SliceIter<Token>, Expression>,
     either!(
         trace_parse!(func_op_expression),
         trace_parse!(func_expression),
@@ -769,13 +855,27 @@ make_fn!(
     )
 );
 
+fn non_op_expression(input: SliceIter<Token>) -> ParseResult<Expression> {
+    let _input = input.clone();
+    match non_op_expression_body(input) {
+        Result::Complete(rest, expr) => {
+            let expr = with_span_end(expr, &_input, &rest);
+            Result::Complete(rest, expr)
+        }
+        result => result,
+    }
+}
+
 fn expression(input: SliceIter<Token>) -> ParseResult<Expression> {
     let _input = input.clone();
     match trace_parse!(_input, op_expression) {
         Result::Incomplete(i) => Result::Incomplete(i),
         Result::Fail(_) => trace_parse!(input, wrap_err!(non_op_expression, "Expected Expression")),
         Result::Abort(e) => Result::Abort(e),
-        Result::Complete(rest, expr) => Result::Complete(rest, expr),
+        Result::Complete(rest, expr) => {
+            let expr = with_span_end(expr, &input, &rest);
+            Result::Complete(rest, expr)
+        }
     }
 }
 
@@ -828,15 +928,32 @@ make_fn!(
     )
 );
 
+make_fn!(
+    out_path_and_expression<SliceIter<Token>, (Option<Token>, Expression)>,
+    either!(
+        // A string followed by another expression names the output path.
+        do_each!(
+            path => match_type!(STR),
+            expr => expression,
+            _ => punct!(";"),
+            ((Some(path), expr))
+        ),
+        do_each!(
+            expr => wrap_err!(must!(expression), "Expected Expression to export"),
+            _ => must!(punct!(";")),
+            ((None, expr))
+        )
+    )
+);
+
 make_fn!(
     out_statement<SliceIter<Token>, Statement>,
     do_each!(
         pos => pos,
         _ => word!("out"),
         typ => wrap_err!(must!(match_type!(BAREWORD)), "Expected converter name"),
-        expr => wrap_err!(must!(expression), "Expected Expression to export"),
-        _ => must!(punct!(";")),
-        (Statement::Output(pos, typ.clone(), expr.clone()))
+        path_and_expr => out_path_and_expression,
+        (Statement::Output(pos, typ.clone(), path_and_expr.0, path_and_expr.1))
     )
 );
 
@@ -852,51 +969,100 @@ fn statement(i: SliceIter<Token>) -> Result<SliceIter<Token>, Statement> {
 }
 //trace_macros!(false);
 
-/// Parses a LocatedSpan into a list of Statements or an `error::Error`.
-pub fn parse<'a>(input: OffsetStrIter<'a>) -> std::result::Result<Vec<Statement>, String> {
-    match tokenize(input.clone()) {
-        Ok(tokenized) => {
-            let mut out = Vec::new();
-            let mut i_ = SliceIter::new(&tokenized);
-            loop {
-                let i = i_.clone();
-                if let Some(tok) = i.peek_next() {
-                    if tok.typ == TokenType::END {
-                        break;
-                    }
-                }
-                match statement(i.clone()) {
-                    Result::Abort(e) => {
-                        let ctx_err = StackPrinter { err: e };
-                        return Err(format!("{}", ctx_err));
-                    }
-                    Result::Fail(e) => {
-                        let ctx_err = StackPrinter { err: e };
-                        return Err(format!("{}", ctx_err));
-                    }
-                    Result::Incomplete(_ei) => {
-                        let err = abortable_parser::Error::new(
-                            "Unexpected end of parse input",
-                            Box::new(i.clone()),
-                        );
-                        let ctx_err = StackPrinter { err: err };
-                        return Err(format!("{}", ctx_err));
-                    }
-                    Result::Complete(rest, stmt) => {
-                        out.push(stmt);
-                        i_ = rest;
-                        if eoi(i).is_complete() {
-                            break;
-                        }
-                    }
+// Finds the index of the token just past the `;` that ends the statement
+// starting at start. Returns None if the statement is never terminated.
+fn resync_point(toks: &[Token], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (idx, tok) in toks.iter().enumerate().skip(start) {
+        match (&tok.typ, tok.fragment.as_str()) {
+            (TokenType::PUNCT, "{") | (TokenType::PUNCT, "(") | (TokenType::PUNCT, "[") => {
+                depth += 1
+            }
+            (TokenType::PUNCT, "}") | (TokenType::PUNCT, ")") | (TokenType::PUNCT, "]")
+                if depth > 0 =>
+            {
+                depth -= 1
+            }
+            (TokenType::PUNCT, ";") if depth == 0 => return Some(idx + 1),
+            (TokenType::END, _) => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_statements<'a>(
+    input: OffsetStrIter<'a>,
+    comment_map: Option<&mut CommentMap>,
+    keep_going: bool,
+) -> std::result::Result<Vec<Statement>, Vec<StackPrinter<Position>>> {
+    let tokenized = match tokenize(input.clone(), comment_map) {
+        Ok(tokenized) => tokenized,
+        Err(e) => return Err(vec![e]),
+    };
+    let mut out = Vec::new();
+    let mut errors = Vec::new();
+    let mut start = 0;
+    loop {
+        let i = SliceIter::new(&tokenized[start..]);
+        if let Some(tok) = i.peek_next() {
+            if tok.typ == TokenType::END {
+                break;
+            }
+        }
+        let err = match statement(i.clone()) {
+            Result::Abort(e) => e,
+            Result::Fail(e) => e,
+            Result::Incomplete(_ei) => {
+                abortable_parser::Error::new("Unexpected end of parse input", Box::new(i.clone()))
+            }
+            Result::Complete(rest, stmt) => {
+                out.push(stmt);
+                start += rest.get_offset();
+                if eoi(i).is_complete() {
+                    break;
                 }
+                continue;
             }
-            return Ok(out);
+        };
+        let ctx_err = StackPrinter { err };
+        errors.push(ctx_err.to_positioned(|i| Position::from(i)));
+        if !keep_going {
+            break;
         }
-        Err(e) => {
-            return Err(e);
+        // Skip to the end of the broken statement and try again from there.
+        match resync_point(&tokenized, start) {
+            Some(next) => start = next,
+            None => break,
         }
     }
+    if errors.is_empty() {
+        Ok(out)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses a LocatedSpan into a list of Statements or an `error::Error`.
+///
+/// If a CommentMap is provided then the comments in the input are collected into it.
+pub fn parse<'a>(
+    input: OffsetStrIter<'a>,
+    comment_map: Option<&mut CommentMap>,
+) -> std::result::Result<Vec<Statement>, StackPrinter<Position>> {
+    parse_statements(input, comment_map, false).map_err(|mut errs| errs.remove(0))
+}
+
+/// Parses a LocatedSpan into a list of Statements collecting every statement
+/// that fails to parse instead of stopping at the first.
+///
+/// After a failure the parser skips ahead to the `;` that ends the broken
+/// statement and resumes from there.
+pub fn parse_all<'a>(
+    input: OffsetStrIter<'a>,
+    comment_map: Option<&mut CommentMap>,
+) -> std::result::Result<Vec<Statement>, Vec<StackPrinter<Position>>> {
+    parse_statements(input, comment_map, true)
 }
 
 pub mod precedence;
@@ -376,7 +376,8 @@ fn parse_op(
             let (_, peek_op) = try_parse!(parse_operator_element(i.clone()));
             lookahead_op = peek_op;
         }
-        let pos = lhs.pos().clone();
+        let mut pos = lhs.pos().clone();
+        pos.end = rhs.pos().end;
         lhs = Expression::Binary(BinaryOpDef {
             kind: op.clone(),
             left: Box::new(lhs.clone()),
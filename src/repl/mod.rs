@@ -0,0 +1,173 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! The line handling for `ucg repl` sessions.
+//!
+//! A Session turns the lines a user types into commands and complete
+//! statements. Evaluating them is left to the caller.
+
+/// The help text for a session.
+pub const HELP: &str = "Enter ucg statements terminated by a `;`. Statements can span multiple
+lines and an empty line evaluates whatever has been entered so far.
+
+Commands:
+    :help            Show this message.
+    :format <name>   Print results using the named converter.
+    :reset           Forget all of the bindings in the session.
+    :quit, :exit     Exit the session.
+";
+
+/// What a line of input asks the session to do.
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    /// Evaluate a complete statement.
+    Eval(String),
+    /// Wait for more lines before evaluating anything.
+    Continue,
+    Help,
+    Reset,
+    Quit,
+    /// Switch to the named format or print the current one without a name.
+    Format(Option<String>),
+    /// A command that doesn't exist.
+    Unknown(String),
+}
+
+/// Collects the lines of a session into statements.
+#[derive(Debug, Default)]
+pub struct Session {
+    buffer: String,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The prompt to show before reading the next line.
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            "> "
+        } else {
+            "... "
+        }
+    }
+
+    /// Reads a line of input.
+    ///
+    /// Commands are only recognized at the start of a statement. A line
+    /// ending with `;`, ignoring any trailing comment, outside of any
+    /// brackets, braces or parens or an empty line completes the statement
+    /// entered so far.
+    pub fn read_line(&mut self, line: &str) -> Input {
+        let trimmed = line.trim();
+        if self.buffer.is_empty() && trimmed.starts_with(':') {
+            return parse_command(trimmed);
+        }
+        if trimmed.is_empty() && self.buffer.trim().is_empty() {
+            self.buffer.clear();
+            return Input::Continue;
+        }
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        let scanned = scan(&self.buffer);
+        let terminated = scanned.code.trim_end().ends_with(';');
+        if !trimmed.is_empty() && (!terminated || scanned.depth > 0) {
+            return Input::Continue;
+        }
+        let mut stmt = self.buffer.trim().to_string();
+        self.buffer.clear();
+        if !terminated {
+            // A trailing comment would swallow the `;` on the same line.
+            if scanned.ends_in_comment {
+                stmt.push('\n');
+            }
+            stmt.push(';');
+        }
+        Input::Eval(stmt)
+    }
+}
+
+// What scan found in the lines entered so far.
+struct Scanned {
+    // How many brackets, braces and parens are still open.
+    depth: i64,
+    // The source with its comments removed.
+    code: String,
+    // Whether a comment came after the last of the code.
+    ends_in_comment: bool,
+}
+
+// Scans src for open brackets, braces and parens and comments. Anything
+// inside of a string or a comment doesn't count towards the depth.
+fn scan(src: &str) -> Scanned {
+    let mut depth = 0;
+    let mut code = String::new();
+    let mut ends_in_comment = false;
+    let mut in_str = false;
+    let mut escaped = false;
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_str {
+            code.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_str = false,
+                _ => (),
+            }
+            continue;
+        }
+        match c {
+            '"' => in_str = true,
+            '/' if chars.peek() == Some(&'/') => {
+                ends_in_comment = true;
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        code.push(c);
+                        break;
+                    }
+                }
+                continue;
+            }
+            '{' | '[' | '(' => depth += 1,
+            '}' | ']' | ')' => depth -= 1,
+            _ => (),
+        }
+        if !c.is_whitespace() {
+            ends_in_comment = false;
+        }
+        code.push(c);
+    }
+    Scanned {
+        depth,
+        code,
+        ends_in_comment,
+    }
+}
+
+fn parse_command(cmd: &str) -> Input {
+    let mut parts = cmd.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(":quit"), _) | (Some(":exit"), _) => Input::Quit,
+        (Some(":help"), _) => Input::Help,
+        (Some(":reset"), _) => Input::Reset,
+        (Some(":format"), name) => Input::Format(name.map(str::to_string)),
+        (Some(cmd), _) => Input::Unknown(cmd.to_string()),
+        (None, _) => Input::Continue,
+    }
+}
+
+#[cfg(test)]
+mod test;
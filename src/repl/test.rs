@@ -0,0 +1,116 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+use super::*;
+
+#[test]
+fn test_commands() {
+    let mut session = Session::new();
+    assert_eq!(session.read_line(":quit"), Input::Quit);
+    assert_eq!(session.read_line("  :exit  "), Input::Quit);
+    assert_eq!(session.read_line(":help"), Input::Help);
+    assert_eq!(session.read_line(":reset"), Input::Reset);
+    assert_eq!(session.read_line(":format"), Input::Format(None));
+    assert_eq!(
+        session.read_line(":format yaml"),
+        Input::Format(Some("yaml".to_string()))
+    );
+    assert_eq!(
+        session.read_line(":nope"),
+        Input::Unknown(":nope".to_string())
+    );
+    assert!(HELP.contains(":quit, :exit"));
+}
+
+#[test]
+fn test_single_line_statement() {
+    let mut session = Session::new();
+    assert_eq!(session.prompt(), "> ");
+    assert_eq!(
+        session.read_line("let x = 1;"),
+        Input::Eval("let x = 1;".to_string())
+    );
+    assert_eq!(session.read_line(""), Input::Continue);
+    assert_eq!(session.prompt(), "> ");
+}
+
+#[test]
+fn test_multi_line_statement() {
+    let mut session = Session::new();
+    assert_eq!(session.read_line("let t = {"), Input::Continue);
+    assert_eq!(session.prompt(), "... ");
+    // Commands are only recognized at the start of a statement.
+    assert_eq!(session.read_line("    :quit = 1,"), Input::Continue);
+    assert_eq!(
+        session.read_line("};"),
+        Input::Eval("let t = {\n    :quit = 1,\n};".to_string())
+    );
+    assert_eq!(session.prompt(), "> ");
+}
+
+#[test]
+fn test_empty_line_completes_statement() {
+    let mut session = Session::new();
+    assert_eq!(session.read_line("1 +"), Input::Continue);
+    assert_eq!(session.read_line("2"), Input::Continue);
+    assert_eq!(session.read_line(""), Input::Eval("1 +\n2;".to_string()));
+}
+
+#[test]
+fn test_multi_line_module() {
+    let mut session = Session::new();
+    assert_eq!(session.read_line("let m = module{} => {"), Input::Continue);
+    assert_eq!(session.read_line("    let y = 2; // }"), Input::Continue);
+    assert_eq!(
+        session.read_line("};"),
+        Input::Eval("let m = module{} => {\n    let y = 2; // }\n};".to_string())
+    );
+}
+
+#[test]
+fn test_strings_do_not_change_nesting() {
+    let mut session = Session::new();
+    assert_eq!(session.read_line("let t = {"), Input::Continue);
+    assert_eq!(session.read_line("    s = \"}\\\"{;\","), Input::Continue);
+    assert_eq!(
+        session.read_line("    f = func() => \"(\";"),
+        Input::Continue
+    );
+    assert_eq!(
+        session.read_line("};"),
+        Input::Eval("let t = {\n    s = \"}\\\"{;\",\n    f = func() => \"(\";\n};".to_string())
+    );
+}
+
+#[test]
+fn test_trailing_comment_completes_statement() {
+    let mut session = Session::new();
+    assert_eq!(
+        session.read_line("let x = 1; // one"),
+        Input::Eval("let x = 1; // one".to_string())
+    );
+    assert_eq!(session.prompt(), "> ");
+    assert_eq!(session.read_line("1 + 2 // three"), Input::Continue);
+    assert_eq!(
+        session.read_line(""),
+        Input::Eval("1 + 2 // three\n;".to_string())
+    );
+    // The comment repeats the code before it.
+    assert_eq!(session.read_line("1 // 1"), Input::Continue);
+    assert_eq!(session.read_line(""), Input::Eval("1 // 1\n;".to_string()));
+    assert_eq!(session.read_line("let s = \"a // b\""), Input::Continue);
+    assert_eq!(
+        session.read_line(";"),
+        Input::Eval("let s = \"a // b\"\n;".to_string())
+    );
+}
@@ -14,6 +14,7 @@
 
 //! The tokenization stage of the ucg compiler.
 use std;
+use std::collections::BTreeMap;
 
 use abortable_parser::combinators::*;
 use abortable_parser::iter::SliceIter;
@@ -132,7 +133,9 @@ macro_rules! do_text_token_tok {
         do_each!($i,
            span => input!(),
            frag => text_token!($text_token),
-           _ => either!(whitespace, comment),
+           // We only peek at the trailing whitespace or comment so that comments
+           // following a keyword still make it into the token stream.
+           _ => peek!(either!(whitespace, comment)),
            (Token {
                typ: $type,
                pos: Position::from(&span),
@@ -451,8 +454,21 @@ fn token<'a>(input: OffsetStrIter<'a>) -> Result<OffsetStrIter<'a>, Token> {
     )
 }
 
+/// Maps a line number to the comment found on that line.
+///
+/// Comments are not part of the token stream handed to the parser. Tools that
+/// need to preserve them, like the formatter, can ask `tokenize` to collect them
+/// here instead.
+pub type CommentMap = BTreeMap<usize, Token>;
+
 /// Consumes an input OffsetStrIter and returns either a Vec<Token> or a error::Error.
-pub fn tokenize<'a>(input: OffsetStrIter<'a>) -> std::result::Result<Vec<Token>, String> {
+///
+/// If a CommentMap is provided then any comments encountered will be stored in it
+/// keyed by their line.
+pub fn tokenize<'a>(
+    input: OffsetStrIter<'a>,
+    mut comment_map: Option<&mut CommentMap>,
+) -> std::result::Result<Vec<Token>, StackPrinter<Position>> {
     let mut out = Vec::new();
     let mut i = input.clone();
     loop {
@@ -467,7 +483,7 @@ pub fn tokenize<'a>(input: OffsetStrIter<'a>) -> std::result::Result<Vec<Token>,
                     Box::new(i.clone()),
                 );
                 let ctx_err = StackPrinter { err: err };
-                return Err(format!("{}", ctx_err));
+                return Err(ctx_err.to_positioned(|i| Position::from(i)));
             }
             Result::Fail(e) => {
                 let err = abortable_parser::Error::caused_by(
@@ -476,18 +492,26 @@ pub fn tokenize<'a>(input: OffsetStrIter<'a>) -> std::result::Result<Vec<Token>,
                     Box::new(i.clone()),
                 );
                 let ctx_err = StackPrinter { err: err };
-                return Err(format!("{}", ctx_err));
+                return Err(ctx_err.to_positioned(|i| Position::from(i)));
             }
             Result::Incomplete(_offset) => {
                 let err =
                     abortable_parser::Error::new("Invalid Token encountered", Box::new(i.clone()));
                 let ctx_err = StackPrinter { err: err };
-                return Err(format!("{}", ctx_err));
+                return Err(ctx_err.to_positioned(|i| Position::from(i)));
             }
-            Result::Complete(rest, tok) => {
+            Result::Complete(rest, mut tok) => {
+                tok.pos.end = Some(SpanEnd::from(&Position::from(&rest)));
                 i = rest;
-                if tok.typ == TokenType::COMMENT || tok.typ == TokenType::WS {
-                    // we skip comments and whitespace
+                if tok.typ == TokenType::COMMENT {
+                    if let Some(ref mut map) = comment_map {
+                        map.insert(tok.pos.line, tok);
+                    }
+                    // comments are otherwise skipped.
+                    continue;
+                }
+                if tok.typ == TokenType::WS {
+                    // we skip whitespace
                     continue;
                 }
                 out.push(tok);
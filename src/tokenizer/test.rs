@@ -89,7 +89,7 @@ fn test_string_with_escaping() {
 #[test]
 fn test_tokenize_bareword_with_dash() {
     let input = OffsetStrIter::new("foo-bar ");
-    let result = tokenize(input.clone());
+    let result = tokenize(input.clone(), None);
     assert!(result.is_ok(), format!("result {:?} is not ok", result));
     if let Ok(toks) = result {
         assert_eq!(toks.len(), 2);
@@ -157,7 +157,7 @@ fn test_tokenize_one_of_each() {
         "map out filter assert let import func select as => [ ] { } ; = % / * \
          + - . ( ) , 1 . foo \"bar\" // comment\n ; true false == < > <= >= !=",
     );
-    let result = tokenize(input.clone());
+    let result = tokenize(input.clone(), None);
     assert!(result.is_ok(), format!("result {:?} is not ok", result));
     let v = result.unwrap();
     for (i, t) in v.iter().enumerate() {
@@ -170,7 +170,7 @@ fn test_tokenize_one_of_each() {
 #[test]
 fn test_parse_has_end() {
     let input = OffsetStrIter::new("foo");
-    let result = tokenize(input.clone());
+    let result = tokenize(input.clone(), None);
     assert!(result.is_ok());
     let v = result.unwrap();
     assert_eq!(v.len(), 2);
@@ -206,7 +206,8 @@ fn test_parse_comment() {
                     file: None,
                     line: 1,
                     column: 1,
-                    offset: 0
+                    offset: 0,
+                    end: None,
                 },
             }
         );
@@ -223,7 +224,8 @@ fn test_parse_comment() {
                     file: None,
                     column: 1,
                     line: 1,
-                    offset: 0
+                    offset: 0,
+                    end: None,
                 },
             }
         );
@@ -240,7 +242,8 @@ fn test_parse_comment() {
                     file: None,
                     column: 1,
                     line: 1,
-                    offset: 0
+                    offset: 0,
+                    end: None,
                 },
             }
         );
@@ -258,6 +261,7 @@ fn test_match_word() {
             line: 1,
             column: 1,
             offset: 0,
+            end: None,
         },
     }];
     let result = word!(SliceIter::new(input.as_slice()), "foo");
@@ -277,6 +281,7 @@ fn test_match_word_empty_input() {
             line: 1,
             column: 1,
             offset: 0,
+            end: None,
         },
     }];
     let result = word!(SliceIter::new(input.as_slice()), "foo");
@@ -300,6 +305,7 @@ fn test_match_punct() {
             line: 1,
             column: 1,
             offset: 0,
+            end: None,
         },
     }];
     let result = punct!(SliceIter::new(input.as_slice()), "!");
@@ -319,6 +325,7 @@ fn test_match_type() {
             line: 1,
             column: 1,
             offset: 0,
+            end: None,
         },
     }];
     let result = match_type!(SliceIter::new(input.as_slice()), BAREWORD);
@@ -327,3 +334,27 @@ fn test_match_type() {
         res => assert!(false, format!("Fail: {:?}", res)),
     }
 }
+
+#[test]
+fn test_tokens_have_span_ends() {
+    let result = tokenize(OffsetStrIter::new("let foo = \"bar\";\n"), None).unwrap();
+    let ends: Vec<Option<SpanEnd>> = result.iter().map(|t| t.pos.end).collect();
+    assert_eq!(
+        ends[0],
+        Some(SpanEnd {
+            line: 1,
+            column: 4,
+            offset: 3
+        })
+    );
+    assert_eq!(
+        ends[3],
+        Some(SpanEnd {
+            line: 1,
+            column: 16,
+            offset: 15
+        })
+    );
+    // The END token has nothing to span.
+    assert_eq!(ends[5], None);
+}
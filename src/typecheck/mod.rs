@@ -0,0 +1,536 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! A static type check over parsed ucg statements.
+//!
+//! Types are inferred from literals and followed through let bindings,
+//! copies, func returns and module outputs. Anything that can't be worked out
+//! without evaluating is Unknown and never produces an error so only code
+//! that would definitely fail when evaluated is reported.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::ast::*;
+use crate::error::{BuildError, ErrorType};
+
+/// The statically known type of an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Unknown,
+    Null,
+    Boolean,
+    Int,
+    Float,
+    Str,
+    List,
+    Env,
+    /// The known fields of a tuple. When exact is false the tuple may have
+    /// other fields too.
+    Tuple {
+        fields: Vec<(String, Type)>,
+        exact: bool,
+    },
+    /// The types of the arguments a func takes and the type it returns.
+    Func {
+        args: Vec<Type>,
+        ret: Box<Type>,
+    },
+    Module {
+        params: Vec<(String, Type)>,
+        out: Box<Type>,
+    },
+}
+
+impl Type {
+    /// The same names `Val::type_name` uses.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Type::Unknown => "Unknown",
+            Type::Null => "EmptyValue",
+            Type::Boolean => "Boolean",
+            Type::Int => "Integer",
+            Type::Float => "Float",
+            Type::Str => "String",
+            Type::List => "List",
+            Type::Env => "Env",
+            Type::Tuple { .. } => "Tuple",
+            Type::Func { .. } => "Func",
+            Type::Module { .. } => "Module",
+        }
+    }
+
+    /// Whether a value of this type could be used where one of the other type
+    /// is expected. Unknown and NULL are compatible with everything.
+    pub fn compatible(&self, other: &Type) -> bool {
+        match (self, other) {
+            (&Type::Unknown, _) | (_, &Type::Unknown) | (&Type::Null, _) | (_, &Type::Null) => true,
+            (l, r) => l.name() == r.name(),
+        }
+    }
+
+    /// The type a type annotation names. Funcs and modules can't be
+    /// described any further so their annotations are Unknown.
+    pub fn from_annotation(name: &str) -> Type {
+        match name {
+            "null" => Type::Null,
+            "bool" => Type::Boolean,
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "str" => Type::Str,
+            "list" => Type::List,
+            "tuple" => Type::Tuple {
+                fields: Vec::new(),
+                exact: false,
+            },
+            _ => Type::Unknown,
+        }
+    }
+
+    fn field(&self, name: &str) -> Option<&Type> {
+        match self {
+            Type::Tuple { fields, .. } => fields.iter().find(|f| f.0 == name).map(|f| &f.1),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        w.write_str(self.name())
+    }
+}
+
+/// Type checks the statements of a file without evaluating them.
+///
+/// Returns every type error found in the order they appear.
+pub fn check(stmts: &[Statement]) -> Vec<BuildError> {
+    let mut checker = Checker {
+        scopes: vec![BTreeMap::new()],
+        errors: Vec::new(),
+    };
+    checker.statements(stmts);
+    checker.errors
+}
+
+struct Checker {
+    // The let bindings in scope. The innermost scope is last.
+    scopes: Vec<BTreeMap<String, Type>>,
+    errors: Vec<BuildError>,
+}
+
+impl Checker {
+    fn error<S: Into<String>>(&mut self, msg: S, pos: &Position) {
+        self.errors
+            .push(BuildError::with_pos(msg, ErrorType::TypeFail, pos.clone()));
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        if name == "env" {
+            return Type::Env;
+        }
+        self.scopes
+            .iter()
+            .rev()
+            .filter_map(|s| s.get(name))
+            .next()
+            .cloned()
+            .unwrap_or(Type::Unknown)
+    }
+
+    fn bind(&mut self, name: &str, typ: Type) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), typ);
+    }
+
+    // Returns the types of the let bindings in the statements.
+    fn statements(&mut self, stmts: &[Statement]) -> Vec<(String, Type)> {
+        let mut bindings = Vec::new();
+        for stmt in stmts.iter() {
+            match stmt {
+                Statement::Let(ref def) => {
+                    let typ = self.expr(&def.value);
+                    self.bind(&def.name.fragment, typ.clone());
+                    bindings.push((def.name.fragment.clone(), typ));
+                }
+                Statement::Expression(ref expr) | Statement::Assert(ref expr) => {
+                    self.expr(expr);
+                }
+                Statement::Output(_, _, _, ref expr) => {
+                    self.expr(expr);
+                }
+            }
+        }
+        bindings
+    }
+
+    fn fields(&mut self, fields: &FieldList) -> Vec<(String, Type)> {
+        fields
+            .iter()
+            .map(|(name, expr)| (name.fragment.clone(), self.expr(expr)))
+            .collect()
+    }
+
+    fn value(&mut self, val: &Value) -> Type {
+        match val {
+            Value::Empty(_) => Type::Null,
+            Value::Boolean(_) => Type::Boolean,
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::Str(_) => Type::Str,
+            Value::Symbol(ref s) => self.lookup(&s.val),
+            Value::Tuple(ref fs) => Type::Tuple {
+                fields: self.fields(&fs.val),
+                exact: true,
+            },
+            Value::List(ref def) => {
+                for expr in def.elems.iter() {
+                    self.expr(expr);
+                }
+                Type::List
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expression) -> Type {
+        match expr {
+            Expression::Simple(ref val) => self.value(val),
+            Expression::Binary(ref def) => self.binary(def),
+            Expression::Copy(ref def) => {
+                let base = self.value(&def.selector);
+                self.copy(&base, def)
+            }
+            Expression::Call(ref def) => {
+                let func = self.value(&def.funcref);
+                self.call(&func, def)
+            }
+            Expression::Format(ref def) => {
+                match def.args {
+                    FormatArgs::List(ref args) => {
+                        for expr in args.iter() {
+                            self.expr(expr);
+                        }
+                    }
+                    FormatArgs::Single(ref expr) => {
+                        self.expr(expr);
+                    }
+                }
+                Type::Str
+            }
+            Expression::FuncOp(ref def) => self.func_op(def),
+            Expression::Grouped(ref expr, _) => self.expr(expr),
+            Expression::Func(ref def) => {
+                let args: Vec<Type> = def
+                    .argdefs
+                    .iter()
+                    .map(|arg| match def.arg_types.get(&arg.val) {
+                        Some(typ) => Type::from_annotation(&typ.fragment),
+                        None => Type::Unknown,
+                    })
+                    .collect();
+                let mut scope = BTreeMap::new();
+                for (arg, typ) in def.argdefs.iter().zip(args.iter()) {
+                    scope.insert(arg.val.clone(), typ.clone());
+                }
+                self.scopes.push(scope);
+                let ret = self.expr(&def.fields);
+                self.scopes.pop();
+                Type::Func {
+                    args,
+                    ret: Box::new(ret),
+                }
+            }
+            Expression::Module(ref def) => self.module(def),
+            Expression::Range(ref def) => {
+                self.expr(&def.start);
+                self.expr(&def.end);
+                if let Some(ref step) = def.step {
+                    self.expr(step);
+                }
+                Type::List
+            }
+            Expression::Select(ref def) => {
+                self.expr(&def.val);
+                let mut branches: Vec<Type> =
+                    self.fields(&def.tuple).into_iter().map(|f| f.1).collect();
+                if let Some(ref default) = def.default {
+                    branches.push(self.expr(default));
+                }
+                match branches.first() {
+                    Some(first) if branches.iter().all(|t| t == first) => first.clone(),
+                    _ => Type::Unknown,
+                }
+            }
+            Expression::Import(_) | Expression::Include(_) => Type::Unknown,
+            Expression::Fail(ref def) => {
+                self.expr(&def.message);
+                Type::Unknown
+            }
+            Expression::Not(ref def) => {
+                self.expr(&def.expr);
+                Type::Boolean
+            }
+            Expression::Debug(ref def) => self.expr(&def.expr),
+        }
+    }
+
+    fn binary(&mut self, def: &BinaryOpDef) -> Type {
+        if def.kind == BinaryExprType::DOT {
+            let left = self.expr(&def.left);
+            return self.select(&left, &def.right);
+        }
+        let left = self.expr(&def.left);
+        let right = self.expr(&def.right);
+        let (op, supported): (&str, &[Type]) = match def.kind {
+            BinaryExprType::Add => ("+", &[Type::Int, Type::Float, Type::Str, Type::List]),
+            BinaryExprType::Sub => ("-", &[Type::Int, Type::Float]),
+            BinaryExprType::Mul => ("*", &[Type::Int, Type::Float]),
+            BinaryExprType::Div => ("/", &[Type::Int, Type::Float]),
+            BinaryExprType::Mod => ("modulus", &[Type::Int, Type::Float]),
+            _ => return Type::Boolean,
+        };
+        let known = match (&left, &right) {
+            (&Type::Unknown, &Type::Unknown) => return Type::Unknown,
+            (&Type::Unknown, known) | (known, &Type::Unknown) => known.clone(),
+            (l, r) => {
+                if l.name() != r.name() && supported.contains(l) {
+                    self.error(format!("Expected {} but got {}", l, r), def.right.pos());
+                    return Type::Unknown;
+                }
+                l.clone()
+            }
+        };
+        if !supported.contains(&known) {
+            self.error(
+                format!("{} does not support the '{}' operation", known, op),
+                def.left.pos(),
+            );
+            return Type::Unknown;
+        }
+        known
+    }
+
+    // The type of a dot selector applied to a value of type left.
+    fn select(&mut self, left: &Type, right: &Expression) -> Type {
+        let name = match right {
+            Expression::Simple(Value::Symbol(ref s)) | Expression::Simple(Value::Str(ref s)) => {
+                Some(s)
+            }
+            Expression::Copy(CopyDef {
+                selector: Value::Symbol(ref s),
+                ..
+            })
+            | Expression::Call(CallDef {
+                funcref: Value::Symbol(ref s),
+                ..
+            }) => Some(s),
+            _ => None,
+        };
+        let name = match name {
+            Some(name) => name,
+            None => {
+                // Indexes and computed selectors can only be checked
+                // against values that have no fields at all.
+                match left {
+                    Type::Null | Type::Boolean | Type::Int | Type::Float => {
+                        self.error(format!("Can't select from a {}", left), right.pos());
+                    }
+                    _ => {}
+                }
+                return Type::Unknown;
+            }
+        };
+        let field = match left {
+            Type::Unknown | Type::Module { .. } | Type::Func { .. } => Type::Unknown,
+            Type::Env => Type::Str,
+            Type::Tuple { exact, .. } => match left.field(&name.val) {
+                Some(typ) => typ.clone(),
+                None if *exact => {
+                    self.error(format!("Tuple has no field {}", name.val), &name.pos);
+                    return Type::Unknown;
+                }
+                None => Type::Unknown,
+            },
+            _ => {
+                self.error(
+                    format!("Can't select field {} from a {}", name.val, left),
+                    &name.pos,
+                );
+                return Type::Unknown;
+            }
+        };
+        match right {
+            Expression::Copy(ref def) => self.copy(&field, def),
+            Expression::Call(ref def) => self.call(&field, def),
+            _ => field,
+        }
+    }
+
+    fn copy(&mut self, base: &Type, def: &CopyDef) -> Type {
+        let overrides = self.fields(&def.fields);
+        let (known, result) = match base {
+            Type::Tuple { fields, exact } => {
+                let mut merged = fields.clone();
+                for (name, typ) in overrides.iter() {
+                    match merged.iter_mut().find(|f| &f.0 == name) {
+                        Some(field) if field.1.compatible(typ) => {
+                            if *typ != Type::Null {
+                                field.1 = typ.clone();
+                            }
+                        }
+                        Some(_) => {}
+                        None => merged.push((name.clone(), typ.clone())),
+                    }
+                }
+                let result = Type::Tuple {
+                    fields: merged,
+                    exact: *exact,
+                };
+                (fields.clone(), result)
+            }
+            Type::Module { params, out } => (params.clone(), out.as_ref().clone()),
+            Type::Unknown => return Type::Unknown,
+            _ => {
+                self.error(
+                    format!("Expected Tuple or Module but got ({})", base),
+                    &def.pos,
+                );
+                return Type::Unknown;
+            }
+        };
+        for ((name, typ), (token, _)) in overrides.iter().zip(def.fields.iter()) {
+            if let Some((_, expected)) = known.iter().find(|f| &f.0 == name) {
+                if !expected.compatible(typ) {
+                    self.error(
+                        format!(
+                            "Expected type {} for field {} but got ({})",
+                            expected, name, typ
+                        ),
+                        &token.pos,
+                    );
+                }
+            }
+        }
+        result
+    }
+
+    fn call(&mut self, func: &Type, def: &CallDef) -> Type {
+        let arg_types: Vec<Type> = def.arglist.iter().map(|expr| self.expr(expr)).collect();
+        match func {
+            Type::Func { args, ret } => {
+                if def.arglist.len() > args.len() {
+                    self.errors.push(BuildError::with_pos(
+                        "Func called with too many args",
+                        ErrorType::BadArgLen,
+                        def.pos.clone(),
+                    ));
+                }
+                for ((expected, typ), expr) in
+                    args.iter().zip(arg_types.iter()).zip(def.arglist.iter())
+                {
+                    if !expected.compatible(typ) {
+                        self.error(
+                            format!("Expected argument of type {} but got ({})", expected, typ),
+                            expr.pos(),
+                        );
+                    }
+                }
+                ret.as_ref().clone()
+            }
+            Type::Unknown => Type::Unknown,
+            _ => {
+                self.error(format!("{} is not a Function", func), &def.pos);
+                Type::Unknown
+            }
+        }
+    }
+
+    fn func_op(&mut self, def: &FuncOpDef) -> Type {
+        let (func, target) = match def {
+            FuncOpDef::Reduce(ref def) => {
+                self.expr(&def.acc);
+                (&def.func, &def.target)
+            }
+            FuncOpDef::Map(ref def) => (&def.func, &def.target),
+            FuncOpDef::Filter(ref def) => (&def.func, &def.target),
+        };
+        let func_type = self.expr(func);
+        let target_type = self.expr(target);
+        match func_type {
+            Type::Func { .. } | Type::Unknown => {}
+            _ => self.error(format!("{} is not a Function", func_type), func.pos()),
+        }
+        match (def, target_type) {
+            (FuncOpDef::Reduce(_), _) => Type::Unknown,
+            (_, Type::List) => Type::List,
+            (_, Type::Str) => Type::Str,
+            (_, Type::Tuple { .. }) => Type::Tuple {
+                fields: Vec::new(),
+                exact: false,
+            },
+            _ => Type::Unknown,
+        }
+    }
+
+    fn module(&mut self, def: &ModuleDef) -> Type {
+        let mut params = self.fields(&def.arg_set);
+        for &mut (ref name, ref mut typ) in params.iter_mut() {
+            if let Some(annotation) = def.arg_types.get(name) {
+                let annotated = Type::from_annotation(&annotation.fragment);
+                if annotated != Type::Unknown {
+                    *typ = annotated;
+                }
+            }
+        }
+        // Modules only see their parameters through mod. A NULL default
+        // can be replaced by anything.
+        let mod_fields = params
+            .iter()
+            .map(|(name, typ)| {
+                let typ = if *typ == Type::Null {
+                    Type::Unknown
+                } else {
+                    typ.clone()
+                };
+                (name.clone(), typ)
+            })
+            .collect();
+        let mut scope = BTreeMap::new();
+        scope.insert(
+            "mod".to_string(),
+            Type::Tuple {
+                fields: mod_fields,
+                exact: false,
+            },
+        );
+        let outer = std::mem::replace(&mut self.scopes, vec![scope]);
+        let bindings = self.statements(&def.statements);
+        let out = match def.out_expr {
+            Some(ref expr) => self.expr(expr),
+            None => Type::Tuple {
+                fields: bindings,
+                exact: false,
+            },
+        };
+        self.scopes = outer;
+        Type::Module {
+            params,
+            out: Box::new(out),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
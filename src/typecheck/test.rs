@@ -0,0 +1,102 @@
+// Copyright 2019 Jeremy Wall <jeremy@marzhillstudios.com>
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+use super::*;
+use crate::iter::OffsetStrIter;
+use crate::parse::parse;
+
+fn check_str(src: &str) -> Vec<(String, usize, usize)> {
+    let stmts = parse(OffsetStrIter::new(src), None).unwrap();
+    check(&stmts)
+        .into_iter()
+        .map(|e| {
+            let pos = e.pos.unwrap();
+            (e.msg, pos.line, pos.column)
+        })
+        .collect()
+}
+
+#[test]
+fn test_binary_op_errors() {
+    assert_eq!(
+        check_str(
+            "let a = 1 + \"x\";\nlet b = \"a\" - \"b\";\nlet c = 1.0 * 2.0;\nlet d = a + 1;\n"
+        ),
+        vec![
+            ("Expected Integer but got String".to_string(), 1, 13),
+            (
+                "String does not support the '-' operation".to_string(),
+                2,
+                9
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_selector_errors() {
+    assert_eq!(
+        check_str("let l = [1];\nlet a = l.foo;\nlet b = l.0;\nlet t = {a = 1};\nlet c = t.b;\nlet d = t.a + \"s\";\nlet e = env.HOME + \"s\";\n"),
+        vec![
+            ("Can't select field foo from a List".to_string(), 2, 11),
+            ("Tuple has no field b".to_string(), 5, 11),
+            ("Expected Integer but got String".to_string(), 6, 15),
+        ]
+    );
+}
+
+#[test]
+fn test_call_errors() {
+    assert_eq!(
+        check_str("let n = 1;\nlet a = n(1);\nlet f = func(x) => x + 1;\nlet b = f(1, 2);\nlet c = f(1) + \"s\";\nlet d = map(n, [1]);\n"),
+        vec![
+            ("Integer is not a Function".to_string(), 2, 9),
+            ("Func called with too many args".to_string(), 4, 9),
+            ("Expected Integer but got String".to_string(), 5, 16),
+            ("Integer is not a Function".to_string(), 6, 13),
+        ]
+    );
+}
+
+#[test]
+fn test_copy_errors() {
+    assert_eq!(
+        check_str("let t = {port = 80, host = NULL};\nlet a = t{port = \"80\", host = \"h\", extra = 1};\nlet b = a{extra = 2}.host + 1;\nlet mk = module{replicas = 1, name = NULL} => (result) {\n    let result = mod.replicas + mod.name;\n};\nlet c = mk{replicas = \"two\", name = 1};\nlet n = 1;\nlet d = n{};\n"),
+        vec![
+            ("Expected type Integer for field port but got (String)".to_string(), 2, 11),
+            ("Expected String but got Integer".to_string(), 3, 29),
+            ("Expected type Integer for field replicas but got (String)".to_string(), 7, 12),
+            ("Expected Tuple or Module but got (Integer)".to_string(), 9, 9),
+        ]
+    );
+}
+
+#[test]
+fn test_annotation_errors() {
+    assert_eq!(
+        check_str("let f = func(name: str, port: int) => port + name;\nlet a = f(\"h\", \"80\");\nlet mk = module{host: str = NULL} => {};\nlet b = mk{host = 1};\n"),
+        vec![
+            ("Expected Integer but got String".to_string(), 1, 46),
+            ("Expected argument of type Integer but got (String)".to_string(), 2, 16),
+            ("Expected type String for field host but got (Integer)".to_string(), 4, 12),
+        ]
+    );
+}
+
+#[test]
+fn test_unknown_types_are_not_errors() {
+    assert_eq!(
+        check_str("let lib = import \"std/lists.ucg\";\nlet a = lib.foo + 1;\nlet f = func(x) => x.foo + \"s\";\nlet s = select \"a\", 1, {a = 1, b = \"b\"};\nlet b = s + 1;\n"),
+        Vec::new()
+    );
+}